@@ -0,0 +1,325 @@
+use std::fs::File;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
+use glam::UVec2;
+use serde_json::{json, Value};
+
+use yewoh::assets::map::{CHUNK_SIZE, load_map_patches, MAP_BLOCK_LENGTH, MapChunk, read_map_chunk};
+use yewoh::assets::mul::MulFile;
+use yewoh::assets::multi::load_multi_data;
+use yewoh::assets::radar::{load_radar_colors, render_minimap};
+use yewoh::assets::tiles::{ItemInfo, LandInfo, load_tile_data, TileFlags};
+use yewoh::assets::uop::{hash, read_uop_index};
+
+mod png;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about)]
+struct Args {
+    /// Path to the Ultima Online Classic data.
+    #[clap(short, long, default_value = "uodata", env = "UO_DATA")]
+    uo_data_path: PathBuf,
+
+    /// Pretty-print JSON output.
+    #[clap(short, long)]
+    pretty: bool,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the entries in a UOP file.
+    UopList {
+        /// Path to the UOP file.
+        path: PathBuf,
+    },
+
+    /// Calculate the UOP hashes of entry names.
+    UopHash {
+        names: Vec<String>,
+    },
+
+    /// Dump the tiledata for a graphic.
+    Tile {
+        /// The graphic ID.
+        #[clap(value_parser = parse_int)]
+        id: u32,
+
+        /// Look up a land tile instead of an item.
+        #[clap(long)]
+        land: bool,
+    },
+
+    /// Export the component list of a multi.
+    Multi {
+        /// The multi ID.
+        #[clap(value_parser = parse_int)]
+        id: u32,
+    },
+
+    /// Render a region of a map as a PNG.
+    RenderMap {
+        /// The map index.
+        #[clap(long, default_value = "0")]
+        map: usize,
+
+        /// The size of the map in tiles, if it is not one of the standard maps.
+        #[clap(long, value_parser = parse_size)]
+        map_size: Option<UVec2>,
+
+        #[clap(long, value_parser = parse_int)]
+        x: u32,
+
+        #[clap(long, value_parser = parse_int)]
+        y: u32,
+
+        #[clap(long, value_parser = parse_int)]
+        width: u32,
+
+        #[clap(long, value_parser = parse_int)]
+        height: u32,
+
+        /// Where to write the PNG.
+        #[clap(short, long)]
+        output: PathBuf,
+    },
+
+    /// Render a whole map as a minimap PNG, using the radar colours.
+    Minimap {
+        /// The map index.
+        #[clap(long, default_value = "0")]
+        map: usize,
+
+        /// The size of the map in tiles, if it is not one of the standard maps.
+        #[clap(long, value_parser = parse_size)]
+        map_size: Option<UVec2>,
+
+        /// The number of tiles along each edge of a pixel.
+        #[clap(long, default_value = "1")]
+        scale: usize,
+
+        /// Where to write the PNG.
+        #[clap(short, long)]
+        output: PathBuf,
+    },
+}
+
+fn parse_int(src: &str) -> Result<u32, String> {
+    let result = if let Some(hex) = src.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        src.parse()
+    };
+    result.map_err(|e| e.to_string())
+}
+
+fn parse_size(src: &str) -> Result<UVec2, String> {
+    let (width, height) = src.split_once('x')
+        .ok_or_else(|| "expected a size like 7168x4096".to_string())?;
+    Ok(UVec2::new(parse_int(width)?, parse_int(height)?))
+}
+
+fn default_map_size(map: usize) -> Option<UVec2> {
+    match map {
+        0 | 1 => Some(UVec2::new(7168, 4096)),
+        2 => Some(UVec2::new(2304, 1600)),
+        3 => Some(UVec2::new(2560, 2048)),
+        4 => Some(UVec2::new(1448, 1448)),
+        5 => Some(UVec2::new(1280, 4096)),
+        _ => None,
+    }
+}
+
+fn flag_names(flags: TileFlags) -> Value {
+    flags.iter_names().map(|(name, _)| name).collect()
+}
+
+fn land_json(id: u32, info: &LandInfo) -> Value {
+    json!({
+        "id": id,
+        "name": info.name,
+        "flags": flag_names(info.flags),
+        "texture_id": info.texture_id,
+    })
+}
+
+fn item_json(id: u32, info: &ItemInfo) -> Value {
+    json!({
+        "id": id,
+        "name": info.name,
+        "flags": flag_names(info.flags),
+        "weight": info.weight,
+        "quality": info.quality,
+        "light_index": info.light_index(),
+        "misc_data": info.misc_data,
+        "quantity": info.quantity,
+        "animation": info.animation,
+        "hue": info.hue,
+        "stacking_offset": info.stacking_offset,
+        "value": info.value,
+        "height": info.height,
+    })
+}
+
+fn uop_list(path: &PathBuf) -> anyhow::Result<Value> {
+    let mut file = File::open(path)?;
+    let mut entries = read_uop_index(&mut file)?.into_iter().collect::<Vec<_>>();
+    entries.sort_by_key(|(_, location)| location.data_offset);
+
+    Ok(entries.into_iter()
+        .map(|(key_hash, location)| json!({
+            "hash": format!("{:016x}", key_hash),
+            "offset": location.data_offset,
+            "compressed_length": location.compressed_length,
+            "length": location.decompressed_length,
+            "compressed": location.is_compressed,
+        }))
+        .collect())
+}
+
+async fn render_map(
+    args: &Args, map: usize, map_size: Option<UVec2>, min: UVec2, size: UVec2, output: &PathBuf,
+) -> anyhow::Result<Value> {
+    let map_size = map_size.or_else(|| default_map_size(map))
+        .ok_or_else(|| anyhow!("unknown size for map {map}"))?;
+    // glam 0.23 has no saturating vector ops, so saturate per axis.
+    let end = UVec2::new(min.x.saturating_add(size.x), min.y.saturating_add(size.y));
+    let max = end.min(map_size);
+    if max.x <= min.x || max.y <= min.y {
+        return Err(anyhow!("region is outside of the map"));
+    }
+
+    let width = (max.x - min.x) as usize;
+    let height = (max.y - min.y) as usize;
+    let height_blocks = (map_size.y as usize).div_ceil(CHUNK_SIZE);
+    let map_file = MulFile::open(&args.uo_data_path, &format!("map{}", map))?;
+    let patches = load_map_patches(&args.uo_data_path, map).await?;
+
+    let read_chunk = |block_x: usize, block_y: usize| -> anyhow::Result<MapChunk> {
+        let block = block_x * height_blocks + block_y;
+        if let Some(chunk) = patches.get(&(block as u32)) {
+            return Ok(chunk.clone());
+        }
+
+        let mut bytes = [0u8; MAP_BLOCK_LENGTH];
+        map_file.read_at((block * MAP_BLOCK_LENGTH) as u64, &mut bytes)?;
+        read_map_chunk(&mut &bytes[..])
+    };
+
+    let mut pixels = vec![[0u8; 3]; width * height];
+    let block_min = min / CHUNK_SIZE as u32;
+    let block_max = (max + (CHUNK_SIZE as u32 - 1)) / CHUNK_SIZE as u32;
+    for block_x in block_min.x..block_max.x {
+        for block_y in block_min.y..block_max.y {
+            let chunk = read_chunk(block_x as usize, block_y as usize)?;
+            for tile_y in 0..CHUNK_SIZE {
+                for tile_x in 0..CHUNK_SIZE {
+                    let x = block_x as usize * CHUNK_SIZE + tile_x;
+                    let y = block_y as usize * CHUNK_SIZE + tile_y;
+                    if x < min.x as usize || y < min.y as usize || x >= max.x as usize || y >= max.y as usize {
+                        continue;
+                    }
+
+                    let (_, z) = chunk.get(tile_x, tile_y);
+                    let shade = (z as i32 + 128) as u8;
+                    pixels[(y - min.y as usize) * width + (x - min.x as usize)] = [shade; 3];
+                }
+            }
+        }
+    }
+
+    std::fs::write(output, png::encode_rgb(width, height, &pixels)?)?;
+    Ok(json!({
+        "output": output,
+        "map": map,
+        "x": min.x,
+        "y": min.y,
+        "width": width,
+        "height": height,
+    }))
+}
+
+async fn minimap(
+    args: &Args, map: usize, map_size: Option<UVec2>, scale: usize, output: &PathBuf,
+) -> anyhow::Result<Value> {
+    let map_size = map_size.or_else(|| default_map_size(map))
+        .ok_or_else(|| anyhow!("unknown size for map {map}"))?;
+    let colors = load_radar_colors(&args.uo_data_path).await?;
+    let minimap = render_minimap(
+        &args.uo_data_path, map, map_size.x as usize, map_size.y as usize, &colors, scale).await?;
+
+    std::fs::write(output, png::encode_rgb(minimap.width, minimap.height, &minimap.pixels)?)?;
+    Ok(json!({
+        "output": output,
+        "map": map,
+        "scale": scale,
+        "width": minimap.width,
+        "height": minimap.height,
+    }))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let output = match &args.command {
+        Command::UopList { path } => uop_list(path)?,
+        Command::UopHash { names } => names.iter()
+            .map(|name| json!({
+                "name": name,
+                "hash": format!("{:016x}", hash(name.as_bytes())),
+            }))
+            .collect(),
+        Command::Tile { id, land } => {
+            let tile_data = load_tile_data(&args.uo_data_path).await?;
+            if *land {
+                let info = tile_data.land.get(*id as usize)
+                    .ok_or_else(|| anyhow!("no such land tile {id}"))?;
+                land_json(*id, info)
+            } else {
+                let info = tile_data.items.get(*id as usize)
+                    .ok_or_else(|| anyhow!("no such item {id}"))?;
+                item_json(*id, info)
+            }
+        }
+        Command::Multi { id } => {
+            let tile_data = load_tile_data(&args.uo_data_path).await?;
+            let multi_data = load_multi_data(&args.uo_data_path, &tile_data).await?;
+            let prefab = multi_data.prefabs.get(*id as usize)
+                .ok_or_else(|| anyhow!("no such multi {id}"))?;
+            let components = prefab.components.iter()
+                .map(|c| json!({
+                    "graphic": c.graphic,
+                    "x": c.position.x,
+                    "y": c.position.y,
+                    "z": c.position.z,
+                    "flags": c.component_flags,
+                    "tile_flags": flag_names(c.tile_flags),
+                    "tooltip_ids": c.tooltip_ids,
+                }))
+                .collect::<Vec<_>>();
+            json!({
+                "id": id,
+                "components": components,
+            })
+        }
+        Command::RenderMap { map, map_size, x, y, width, height, output } => {
+            render_map(&args, *map, *map_size, UVec2::new(*x, *y), UVec2::new(*width, *height), output).await?
+        }
+        Command::Minimap { map, map_size, scale, output } => {
+            minimap(&args, *map, *map_size, *scale, output).await?
+        }
+    };
+
+    let text = if args.pretty {
+        serde_json::to_string_pretty(&output)?
+    } else {
+        serde_json::to_string(&output)?
+    };
+    println!("{}", text);
+    Ok(())
+}
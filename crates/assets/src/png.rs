@@ -0,0 +1,53 @@
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for part in parts {
+        for byte in *part {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xedb88320 & mask);
+            }
+        }
+    }
+    !crc
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> anyhow::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc32(&[kind, data]).to_be_bytes())?;
+    Ok(())
+}
+
+/// Encode an 8-bit RGB image as a PNG.
+pub fn encode_rgb(width: usize, height: usize, pixels: &[[u8; 3]]) -> anyhow::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filter and interlacing.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in pixels.chunks(width) {
+        encoder.write_all(&[0])?;
+        for pixel in row {
+            encoder.write_all(pixel)?;
+        }
+    }
+    let data = encoder.finish()?;
+
+    let mut out = Vec::new();
+    out.write_all(SIGNATURE)?;
+    write_chunk(&mut out, b"IHDR", &header)?;
+    write_chunk(&mut out, b"IDAT", &data)?;
+    write_chunk(&mut out, b"IEND", &[])?;
+    Ok(out)
+}
@@ -0,0 +1,522 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::anyhow;
+use byteorder::{LittleEndian as Endian, ReadBytesExt};
+use glam::IVec2;
+
+use crate::assets::mul::read_optional;
+use crate::assets::uop::UopBuffer;
+
+pub const ANIMATION_DIRECTIONS: usize = 5;
+
+const MAX_ANIMATION_FILES: usize = 6;
+const MAX_UOP_FILES: usize = 6;
+const PALETTE_SIZE: usize = 256;
+const FRAME_END: u32 = 0x7fff7fff;
+const DOUBLE_XOR: u32 = (0x200 << 22) | (0x200 << 12);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationGroup {
+    Monster,
+    Animal,
+    People,
+}
+
+impl AnimationGroup {
+    pub fn action_count(self) -> usize {
+        match self {
+            AnimationGroup::Monster => 22,
+            AnimationGroup::Animal => 13,
+            AnimationGroup::People => 35,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationFrame {
+    pub center: IVec2,
+    pub width: u16,
+    pub height: u16,
+    /// ARGB1555 pixels, row-major. Transparent pixels are zero.
+    pub pixels: Vec<u16>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BodyConversion {
+    file_index: usize,
+    body: u16,
+}
+
+#[derive(Debug, Clone)]
+struct MulAnimationFile {
+    index: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl MulAnimationFile {
+    fn entry(&self, index: usize) -> Option<&[u8]> {
+        let start = index * 12;
+        let mut entry = self.index.get(start..start + 12)?;
+        let offset = entry.read_u32::<Endian>().ok()?;
+        let length = entry.read_u32::<Endian>().ok()?;
+        if offset == 0xffffffff || length == 0xffffffff || length == 0 {
+            return None;
+        }
+
+        let offset = offset as usize;
+        self.data.get(offset..offset + length as usize)
+    }
+}
+
+/// The group the client assumes for a body from its ID alone, as used for `anim.mul` and for
+/// UOP animations, which don't record a group.
+fn default_group(body: u16) -> AnimationGroup {
+    if body < 200 {
+        AnimationGroup::Monster
+    } else if body < 400 {
+        AnimationGroup::Animal
+    } else {
+        AnimationGroup::People
+    }
+}
+
+fn mul_group(file_index: usize, body: u16) -> (AnimationGroup, usize) {
+    let group = default_group(body);
+    let body = body as usize;
+    match file_index {
+        1 => if body < 200 {
+            (AnimationGroup::Monster, body * 110)
+        } else {
+            (AnimationGroup::Animal, 22000 + (body - 200) * 65)
+        },
+        2 => if body < 300 {
+            (AnimationGroup::Animal, body * 65)
+        } else if body < 400 {
+            (AnimationGroup::Monster, 33000 + (body - 300) * 110)
+        } else {
+            (AnimationGroup::People, 35000 + (body - 400) * 175)
+        },
+        _ => match group {
+            AnimationGroup::Monster => (group, body * 110),
+            AnimationGroup::Animal => (group, 22000 + (body - 200) * 65),
+            AnimationGroup::People => (group, 35000 + (body - 400) * 175),
+        },
+    }
+}
+
+fn read_palette(mut bytes: &[u8]) -> anyhow::Result<[u16; PALETTE_SIZE]> {
+    let mut palette = [0u16; PALETTE_SIZE];
+    for entry in palette.iter_mut() {
+        *entry = bytes.read_u16::<Endian>()? | 0x8000;
+    }
+    Ok(palette)
+}
+
+fn decode_frame(palette: &[u16; PALETTE_SIZE], mut bytes: &[u8]) -> anyhow::Result<AnimationFrame> {
+    let center_x = bytes.read_i16::<Endian>()? as i32;
+    let center_y = bytes.read_i16::<Endian>()? as i32;
+    let width = bytes.read_u16::<Endian>()?;
+    let height = bytes.read_u16::<Endian>()?;
+    let mut pixels = vec![0u16; width as usize * height as usize];
+
+    loop {
+        let header = bytes.read_u32::<Endian>()?;
+        if header == FRAME_END {
+            break;
+        }
+
+        let header = header ^ DOUBLE_XOR;
+        let x = ((header >> 22) & 0x3ff) as i32 + center_x - 0x200;
+        let y = ((header >> 12) & 0x3ff) as i32 + center_y + height as i32 - 0x200;
+        let run_length = (header & 0xfff) as usize;
+
+        if bytes.len() < run_length {
+            return Err(anyhow!("unexpected EOF in animation frame"));
+        }
+
+        let (run, rest) = bytes.split_at(run_length);
+        bytes = rest;
+
+        if y < 0 || y >= height as i32 {
+            continue;
+        }
+
+        let row = y as usize * width as usize;
+        for (i, colour) in run.iter().enumerate() {
+            let x = x + i as i32;
+            if x >= 0 && x < width as i32 {
+                pixels[row + x as usize] = palette[*colour as usize];
+            }
+        }
+    }
+
+    Ok(AnimationFrame {
+        center: IVec2::new(center_x, center_y),
+        width,
+        height,
+        pixels,
+    })
+}
+
+struct UopFrameHeader {
+    start: usize,
+    offset: usize,
+}
+
+fn read_uop_frame_headers(bytes: &[u8]) -> anyhow::Result<Vec<UopFrameHeader>> {
+    let mut header = bytes.get(32..)
+        .ok_or_else(|| anyhow!("truncated animation header"))?;
+    let frame_count = header.read_u32::<Endian>()? as usize;
+    let data_start = header.read_u32::<Endian>()? as usize;
+
+    let mut frames = Vec::with_capacity(frame_count);
+    for i in 0..frame_count {
+        let start = data_start + i * 16;
+        let mut entry = bytes.get(start..start + 16)
+            .ok_or_else(|| anyhow!("truncated animation frame table"))?;
+        let _action = entry.read_u16::<Endian>()?;
+        let _frame_id = entry.read_u16::<Endian>()?;
+        let _bounds = entry.read_u64::<Endian>()?;
+        let offset = entry.read_u32::<Endian>()? as usize;
+        frames.push(UopFrameHeader { start, offset });
+    }
+
+    Ok(frames)
+}
+
+fn uop_path(body: u16, action: usize) -> String {
+    format!("build/animationlegacyframe/{:06}/{:02}.bin", body, action)
+}
+
+#[derive(Default)]
+pub struct Animations {
+    mul_files: Vec<Option<MulAnimationFile>>,
+    uop_files: Vec<UopBuffer<Vec<u8>>>,
+    body_conversions: HashMap<u16, BodyConversion>,
+}
+
+impl Animations {
+    fn uop_entry(&self, body: u16, action: usize) -> Option<anyhow::Result<Vec<u8>>> {
+        let path = uop_path(body, action);
+        self.uop_files.iter()
+            .find_map(|uop| uop.get(&path))
+            .map(|mut entry| {
+                let mut contents = Vec::with_capacity(entry.len());
+                entry.read_to_end(&mut contents)?;
+                Ok(contents)
+            })
+    }
+
+    fn has_uop_body(&self, body: u16) -> bool {
+        let path = uop_path(body, 0);
+        self.uop_files.iter().any(|uop| uop.get(&path).is_some())
+    }
+
+    fn resolve_mul(&self, body: u16) -> Option<(&MulAnimationFile, AnimationGroup, usize)> {
+        let (file_index, body) = match self.body_conversions.get(&body) {
+            Some(conversion) if self.mul_file(conversion.file_index).is_some() =>
+                (conversion.file_index, conversion.body),
+            _ => (0, body),
+        };
+
+        let file = self.mul_file(file_index)?;
+        let (group, base) = mul_group(file_index, body);
+        Some((file, group, base))
+    }
+
+    fn mul_file(&self, file_index: usize) -> Option<&MulAnimationFile> {
+        self.mul_files.get(file_index).and_then(|f| f.as_ref())
+    }
+
+    /// Returns the animation group for a body, which determines which actions are available.
+    pub fn group(&self, body: u16) -> Option<AnimationGroup> {
+        if self.has_uop_body(body) {
+            return Some(default_group(body));
+        }
+
+        self.resolve_mul(body).map(|(_, group, _)| group)
+    }
+
+    /// Returns the number of frames in each direction of the given action, or `None` if the
+    /// body does not have that action.
+    pub fn frame_count(&self, body: u16, action: usize) -> anyhow::Result<Option<usize>> {
+        if let Some(contents) = self.uop_entry(body, action) {
+            let frames = read_uop_frame_headers(&contents?)?;
+            return Ok(Some(frames.len() / ANIMATION_DIRECTIONS));
+        }
+
+        let (file, group, base) = match self.resolve_mul(body) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        if action >= group.action_count() {
+            return Ok(None);
+        }
+
+        let entry = match file.entry(base + action * ANIMATION_DIRECTIONS) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let mut header = entry.get(PALETTE_SIZE * 2..)
+            .ok_or_else(|| anyhow!("truncated animation entry"))?;
+        Ok(Some(header.read_u32::<Endian>()? as usize))
+    }
+
+    /// Decodes the frames of one direction of an action.
+    pub fn frames(&self, body: u16, action: usize, direction: usize) -> anyhow::Result<Vec<AnimationFrame>> {
+        if direction >= ANIMATION_DIRECTIONS {
+            return Err(anyhow!("invalid animation direction {direction}"));
+        }
+
+        if let Some(contents) = self.uop_entry(body, action) {
+            let contents = contents?;
+            let headers = read_uop_frame_headers(&contents)?;
+            let per_direction = headers.len() / ANIMATION_DIRECTIONS;
+            let mut frames = Vec::with_capacity(per_direction);
+
+            for header in &headers[direction * per_direction..(direction + 1) * per_direction] {
+                let start = header.start + header.offset;
+                let bytes = contents.get(start..)
+                    .ok_or_else(|| anyhow!("animation frame out of bounds"))?;
+                let palette = read_palette(bytes)?;
+                frames.push(decode_frame(&palette, &bytes[PALETTE_SIZE * 2..])?);
+            }
+
+            return Ok(frames);
+        }
+
+        let (file, group, base) = self.resolve_mul(body)
+            .ok_or_else(|| anyhow!("no animations for body {body}"))?;
+        if action >= group.action_count() {
+            return Err(anyhow!("body {body} has no action {action}"));
+        }
+
+        let entry = match file.entry(base + action * ANIMATION_DIRECTIONS + direction) {
+            Some(x) => x,
+            None => return Ok(Vec::new()),
+        };
+
+        let palette = read_palette(entry)?;
+        let frame_table = &entry[PALETTE_SIZE * 2..];
+        let mut header = frame_table;
+        let frame_count = header.read_u32::<Endian>()? as usize;
+        let mut frames = Vec::with_capacity(frame_count);
+
+        for _ in 0..frame_count {
+            let offset = header.read_u32::<Endian>()? as usize;
+            let bytes = frame_table.get(offset..)
+                .ok_or_else(|| anyhow!("animation frame out of bounds"))?;
+            frames.push(decode_frame(&palette, bytes)?);
+        }
+
+        Ok(frames)
+    }
+}
+
+fn parse_body_conversions(contents: &str) -> HashMap<u16, BodyConversion> {
+    let mut conversions = HashMap::new();
+
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut parts = line.split_whitespace().map(|p| p.parse::<i32>());
+        let body = match parts.next() {
+            Some(Ok(x)) if x >= 0 => x as u16,
+            _ => continue,
+        };
+
+        for (column, value) in parts.enumerate() {
+            match value {
+                Ok(x) if x >= 0 => {
+                    conversions.insert(body, BodyConversion {
+                        file_index: column + 1,
+                        body: x as u16,
+                    });
+                    break;
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+    }
+
+    conversions
+}
+
+pub async fn load_animations(data_path: &Path) -> anyhow::Result<Animations> {
+    let mut mul_files = Vec::with_capacity(MAX_ANIMATION_FILES);
+    for i in 0..MAX_ANIMATION_FILES {
+        let name = if i == 0 { "anim".to_string() } else { format!("anim{}", i + 1) };
+        let index = read_optional(&data_path.join(format!("{}.idx", name))).await?;
+        let data = read_optional(&data_path.join(format!("{}.mul", name))).await?;
+        mul_files.push(index.zip(data).map(|(index, data)| MulAnimationFile { index, data }));
+    }
+
+    let mut uop_files = Vec::new();
+    for i in 1..=MAX_UOP_FILES {
+        if let Some(contents) = read_optional(&data_path.join(format!("AnimationFrame{}.uop", i))).await? {
+            uop_files.push(UopBuffer::try_from_backing(contents)?);
+        }
+    }
+
+    let body_conversions = match read_optional(&data_path.join("Bodyconv.def")).await? {
+        Some(contents) => parse_body_conversions(&String::from_utf8_lossy(&contents)),
+        None => HashMap::new(),
+    };
+
+    Ok(Animations {
+        mul_files,
+        uop_files,
+        body_conversions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+
+    use crate::assets::uop::UopWriter;
+
+    use super::*;
+
+    const RED: u16 = 0x7c00;
+
+    fn palette() -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PALETTE_SIZE * 2);
+        for i in 0..PALETTE_SIZE {
+            bytes.write_u16::<Endian>(if i == 1 { RED } else { 0 }).unwrap();
+        }
+        bytes
+    }
+
+    /// Encode a 3x2 frame with a single run of two pixels on the bottom row.
+    fn frame() -> Vec<u8> {
+        let (center_x, center_y, width, height) = (1i16, 2i16, 3u16, 2u16);
+        let mut bytes = Vec::new();
+        bytes.write_i16::<Endian>(center_x).unwrap();
+        bytes.write_i16::<Endian>(center_y).unwrap();
+        bytes.write_u16::<Endian>(width).unwrap();
+        bytes.write_u16::<Endian>(height).unwrap();
+
+        let (x, y) = (1i32, 1i32);
+        let header_x = (x - center_x as i32 + 0x200) as u32 & 0x3ff;
+        let header_y = (y - center_y as i32 - height as i32 + 0x200) as u32 & 0x3ff;
+        let header = (header_x << 22) | (header_y << 12) | 2;
+        bytes.write_u32::<Endian>(header ^ DOUBLE_XOR).unwrap();
+        bytes.extend_from_slice(&[1, 1]);
+        bytes.write_u32::<Endian>(FRAME_END).unwrap();
+        bytes
+    }
+
+    fn assert_frame(frame: &AnimationFrame) {
+        assert_eq!(frame.center, IVec2::new(1, 2));
+        assert_eq!((frame.width, frame.height), (3, 2));
+        assert_eq!(frame.pixels, vec![0, 0, 0, 0, RED | 0x8000, RED | 0x8000]);
+    }
+
+    /// An anim.idx/anim.mul pair with one frame in direction 0 of action 0 for `body`.
+    fn mul_file(body: u16) -> MulAnimationFile {
+        let mut entry = palette();
+        entry.write_u32::<Endian>(1).unwrap();
+        entry.write_u32::<Endian>(8).unwrap();
+        entry.extend(frame());
+
+        let (_, base) = mul_group(0, body);
+        let mut index = Vec::new();
+        for i in 0..=base {
+            let (offset, length) = if i == base { (0, entry.len() as u32) } else { (0xffffffff, 0) };
+            index.write_u32::<Endian>(offset).unwrap();
+            index.write_u32::<Endian>(length).unwrap();
+            index.write_u32::<Endian>(0).unwrap();
+        }
+
+        MulAnimationFile { index, data: entry }
+    }
+
+    #[test]
+    fn reads_mul_animations() {
+        let animations = Animations {
+            mul_files: vec![Some(mul_file(3))],
+            ..Default::default()
+        };
+
+        assert_eq!(animations.group(3), Some(AnimationGroup::Monster));
+        assert_eq!(animations.frame_count(3, 0).unwrap(), Some(1));
+        assert_eq!(animations.frame_count(3, 1).unwrap(), None);
+        assert_eq!(animations.frame_count(3, 22).unwrap(), None);
+
+        let frames = animations.frames(3, 0, 0).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_frame(&frames[0]);
+        assert!(animations.frames(3, 0, 1).unwrap().is_empty());
+        assert!(animations.frames(3, 0, ANIMATION_DIRECTIONS).is_err());
+    }
+
+    #[test]
+    fn reads_uop_animations() {
+        // Header, then a frame table with one frame per direction, each pointing at the
+        // same palette and frame data.
+        let table_start = 40;
+        let data_start = table_start + ANIMATION_DIRECTIONS * 16;
+        let mut entry = vec![0u8; 32];
+        entry.write_u32::<Endian>(ANIMATION_DIRECTIONS as u32).unwrap();
+        entry.write_u32::<Endian>(table_start as u32).unwrap();
+        for i in 0..ANIMATION_DIRECTIONS {
+            let start = table_start + i * 16;
+            entry.write_u16::<Endian>(0).unwrap();
+            entry.write_u16::<Endian>(i as u16).unwrap();
+            entry.write_u64::<Endian>(0).unwrap();
+            entry.write_u32::<Endian>((data_start - start) as u32).unwrap();
+        }
+        entry.extend(palette());
+        entry.extend(frame());
+
+        let mut writer = UopWriter::new();
+        writer.insert_compressed(&uop_path(400, 0), &entry).unwrap();
+        let animations = Animations {
+            uop_files: vec![UopBuffer::try_from_backing(writer.to_bytes().unwrap()).unwrap()],
+            ..Default::default()
+        };
+
+        assert_eq!(animations.group(400), Some(AnimationGroup::People));
+        assert_eq!(animations.group(401), None);
+        assert_eq!(animations.frame_count(400, 0).unwrap(), Some(1));
+        assert_eq!(animations.frame_count(400, 1).unwrap(), None);
+
+        let frames = animations.frames(400, 0, 4).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_frame(&frames[0]);
+    }
+
+    #[test]
+    fn parses_body_conversions() {
+        let conversions = parse_body_conversions("\
+            # Body anim2 anim3 anim4 anim5\n\
+            10 20 -1\n\
+            400 -1 401 # comment\n\
+            bad line\n\
+            -1 5\n");
+
+        assert_eq!(conversions.len(), 2);
+        assert_eq!((conversions[&10].file_index, conversions[&10].body), (1, 20));
+        assert_eq!((conversions[&400].file_index, conversions[&400].body), (2, 401));
+    }
+
+    #[test]
+    fn converted_bodies_need_their_file() {
+        let mut animations = Animations {
+            mul_files: vec![Some(mul_file(3)), None],
+            body_conversions: parse_body_conversions("3 250\n"),
+            ..Default::default()
+        };
+
+        // The conversion is ignored while anim2 is missing.
+        assert_eq!(animations.group(3), Some(AnimationGroup::Monster));
+
+        animations.mul_files[1] = Some(mul_file(0));
+        assert_eq!(animations.group(3), Some(AnimationGroup::Animal));
+    }
+}
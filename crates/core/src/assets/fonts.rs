@@ -0,0 +1,321 @@
+use std::path::Path;
+
+use byteorder::{LittleEndian as Endian, ReadBytesExt};
+use glam::UVec2;
+
+use crate::assets::mul::read_optional;
+
+const ASCII_FIRST_CHAR: u32 = 0x20;
+const ASCII_CHAR_COUNT: usize = 224;
+const UNICODE_CHAR_COUNT: usize = 0x10000;
+const MAX_UNICODE_FONTS: usize = 13;
+const UNICODE_SPACE_WIDTH: u32 = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GlyphMetrics {
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl GlyphMetrics {
+    /// The horizontal distance from the start of this glyph to the start of the next.
+    pub fn advance(&self) -> u32 {
+        (self.x_offset + self.width as i32).max(0) as u32
+    }
+}
+
+/// Glyph metrics for measuring and laying out text.
+pub trait FontMetrics {
+    fn glyph(&self, c: char) -> Option<GlyphMetrics>;
+
+    fn line_height(&self) -> u32;
+
+    fn char_width(&self, c: char) -> u32 {
+        self.glyph(c).map_or(0, |g| g.advance())
+    }
+
+    /// The width of a single line of text.
+    fn line_width(&self, text: &str) -> u32 {
+        text.chars().map(|c| self.char_width(c)).sum()
+    }
+}
+
+/// A bitmap font from fonts.mul, covering characters 0x20-0xff.
+#[derive(Debug, Clone, Default)]
+pub struct AsciiFont {
+    pub glyphs: Vec<GlyphMetrics>,
+    pub line_height: u32,
+}
+
+impl FontMetrics for AsciiFont {
+    fn glyph(&self, c: char) -> Option<GlyphMetrics> {
+        let index = (c as u32).checked_sub(ASCII_FIRST_CHAR)?;
+        self.glyphs.get(index as usize).copied()
+    }
+
+    fn line_height(&self) -> u32 {
+        self.line_height
+    }
+}
+
+/// A font from one of the unifont*.mul files.
+#[derive(Debug, Clone, Default)]
+pub struct UnicodeFont {
+    pub glyphs: Vec<Option<GlyphMetrics>>,
+    pub line_height: u32,
+}
+
+impl FontMetrics for UnicodeFont {
+    fn glyph(&self, c: char) -> Option<GlyphMetrics> {
+        match self.glyphs.get(c as usize).copied().flatten() {
+            Some(glyph) => Some(glyph),
+            None if c == ' ' => Some(GlyphMetrics { width: UNICODE_SPACE_WIDTH, ..Default::default() }),
+            None => None,
+        }
+    }
+
+    fn line_height(&self) -> u32 {
+        self.line_height
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Fonts {
+    pub ascii: Vec<AsciiFont>,
+    /// Unicode fonts by index; unifont.mul is font 0.
+    pub unicode: Vec<Option<UnicodeFont>>,
+}
+
+impl Fonts {
+    pub fn ascii(&self, index: usize) -> Option<&AsciiFont> {
+        self.ascii.get(index)
+    }
+
+    pub fn unicode(&self, index: usize) -> Option<&UnicodeFont> {
+        self.unicode.get(index).and_then(|f| f.as_ref())
+    }
+}
+
+fn parse_ascii_fonts(mut data: &[u8]) -> anyhow::Result<Vec<AsciiFont>> {
+    let mut fonts = Vec::new();
+
+    while !data.is_empty() {
+        data.read_u8()?;
+
+        let mut glyphs = Vec::with_capacity(ASCII_CHAR_COUNT);
+        let mut line_height = 0;
+        for _ in 0..ASCII_CHAR_COUNT {
+            let width = data.read_u8()? as u32;
+            let height = data.read_u8()? as u32;
+            data.read_u8()?;
+
+            let pixels_length = (width * height * 2) as usize;
+            if data.len() < pixels_length {
+                return Err(anyhow::anyhow!("truncated glyph in font {}", fonts.len()));
+            }
+            data = &data[pixels_length..];
+
+            line_height = line_height.max(height);
+            glyphs.push(GlyphMetrics { x_offset: 0, y_offset: 0, width, height });
+        }
+
+        fonts.push(AsciiFont { glyphs, line_height });
+    }
+
+    Ok(fonts)
+}
+
+fn parse_unicode_font(data: &[u8]) -> anyhow::Result<UnicodeFont> {
+    let mut table = data;
+    let mut glyphs = Vec::with_capacity(UNICODE_CHAR_COUNT);
+    let mut line_height = 0;
+
+    for _ in 0..UNICODE_CHAR_COUNT {
+        let offset = table.read_u32::<Endian>()? as usize;
+        if offset == 0 || offset == 0xffffffff {
+            glyphs.push(None);
+            continue;
+        }
+
+        let mut header = match data.get(offset..offset + 4) {
+            Some(x) => x,
+            None => {
+                glyphs.push(None);
+                continue;
+            }
+        };
+        let x_offset = header.read_i8()? as i32;
+        let y_offset = header.read_i8()? as i32;
+        let width = header.read_u8()? as u32;
+        let height = header.read_u8()? as u32;
+
+        line_height = line_height.max((y_offset + height as i32).max(0) as u32);
+        glyphs.push(Some(GlyphMetrics { x_offset, y_offset, width, height }));
+    }
+
+    Ok(UnicodeFont { glyphs, line_height })
+}
+
+pub async fn load_fonts(data_path: &Path) -> anyhow::Result<Fonts> {
+    let ascii = match read_optional(&data_path.join("fonts.mul")).await? {
+        Some(data) => parse_ascii_fonts(&data)?,
+        None => Vec::new(),
+    };
+
+    let mut unicode = Vec::with_capacity(MAX_UNICODE_FONTS);
+    for index in 0..MAX_UNICODE_FONTS {
+        let name = if index == 0 {
+            "unifont.mul".to_string()
+        } else {
+            format!("unifont{}.mul", index)
+        };
+
+        let font = match read_optional(&data_path.join(name)).await? {
+            Some(data) => Some(parse_unicode_font(&data)?),
+            None => None,
+        };
+        unicode.push(font);
+    }
+
+    Ok(Fonts { ascii, unicode })
+}
+
+/// Measure the size of some text, which may contain newlines.
+pub fn measure_text(font: &impl FontMetrics, text: &str) -> UVec2 {
+    let mut size = UVec2::ZERO;
+    for line in text.split('\n') {
+        size.x = size.x.max(font.line_width(line));
+        size.y += font.line_height();
+    }
+    size
+}
+
+/// Split text into lines no wider than `max_width`.
+///
+/// Lines are broken at spaces where possible, and words which don't fit on a line by
+/// themselves are broken between characters.
+pub fn wrap_text(font: &impl FontMetrics, text: &str, max_width: u32) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let mut line_width = 0;
+
+        for word in paragraph.split(' ') {
+            let word_width = font.line_width(word);
+            let space_width = if line.is_empty() { 0 } else { font.char_width(' ') };
+
+            if !line.is_empty() && line_width + space_width + word_width > max_width {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0;
+            } else if !line.is_empty() {
+                line.push(' ');
+                line_width += space_width;
+            }
+
+            for c in word.chars() {
+                let width = font.char_width(c);
+                if !line.is_empty() && line_width + width > max_width {
+                    lines.push(std::mem::take(&mut line));
+                    line_width = 0;
+                }
+                line.push(c);
+                line_width += width;
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Remove HTML tags from text, for measuring the contents of HTML gump areas.
+pub fn strip_html(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_tag = false;
+    let mut tag = String::new();
+
+    for c in text.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                tag.clear();
+            }
+            '>' if in_tag => {
+                in_tag = false;
+                let name = tag.trim().trim_start_matches('/')
+                    .split_whitespace().next().unwrap_or("")
+                    .trim_end_matches('/')
+                    .to_ascii_lowercase();
+                if name == "br" || name == "p" {
+                    result.push('\n');
+                }
+            }
+            _ if in_tag => tag.push(c),
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every character is two pixels wide, except spaces which are one.
+    struct TestFont;
+
+    impl FontMetrics for TestFont {
+        fn glyph(&self, c: char) -> Option<GlyphMetrics> {
+            let width = if c == ' ' { 1 } else { 2 };
+            Some(GlyphMetrics { width, height: 10, ..Default::default() })
+        }
+
+        fn line_height(&self) -> u32 {
+            10
+        }
+    }
+
+    fn wrap(text: &str, max_width: u32) -> Vec<String> {
+        wrap_text(&TestFont, text, max_width)
+    }
+
+    #[test]
+    fn measures_lines() {
+        assert_eq!(measure_text(&TestFont, "ab\nabc"), UVec2::new(6, 20));
+        assert_eq!(measure_text(&TestFont, "a b"), UVec2::new(5, 10));
+        assert_eq!(measure_text(&TestFont, ""), UVec2::new(0, 10));
+    }
+
+    #[test]
+    fn wraps_at_spaces() {
+        assert_eq!(wrap("aa bb cc", 7), vec!["aa", "bb", "cc"]);
+        assert_eq!(wrap("aa bb cc", 9), vec!["aa bb", "cc"]);
+        assert_eq!(wrap("aa bb", 100), vec!["aa bb"]);
+    }
+
+    #[test]
+    fn breaks_overlong_words() {
+        assert_eq!(wrap("abcdefgh", 6), vec!["abc", "def", "gh"]);
+        assert_eq!(wrap("a bcdefg", 6), vec!["a", "bcd", "efg"]);
+        // Characters wider than a line still get a line each.
+        assert_eq!(wrap("ab", 1), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn keeps_empty_paragraphs() {
+        assert_eq!(wrap("a\n\nb", 10), vec!["a", "", "b"]);
+        assert_eq!(wrap("", 10), vec![""]);
+    }
+
+    #[test]
+    fn strips_html() {
+        assert_eq!(strip_html("a<br>b<BR/>c<p>d</p><b>e</b>"), "a\nb\nc\nd\ne");
+        assert_eq!(strip_html("<p align=\"center\">x<br />y"), "\nx\ny");
+        assert_eq!(strip_html("<basefont color=#ffffff>plain"), "plain");
+    }
+}
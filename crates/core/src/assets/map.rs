@@ -1,14 +1,19 @@
+use std::collections::HashMap;
 use std::future::Future;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::Path;
 
-use byteorder::{LittleEndian as Endian, ReadBytesExt};
+use anyhow::anyhow;
+use byteorder::{LittleEndian as Endian, ReadBytesExt, WriteBytesExt};
 use glam::IVec3;
 
-use crate::assets::mul::MulReader;
+use crate::assets::mul::{MulFormat, MulReader, read_optional, write_mul};
 
 pub const CHUNK_SIZE: usize = 8;
 pub const CHUNK_AREA: usize = CHUNK_SIZE * CHUNK_SIZE;
+pub const MAP_BLOCK_LENGTH: usize = 4 + CHUNK_AREA * 3;
+pub const STATIC_INDEX_ENTRY_LENGTH: usize = 12;
+pub const STATIC_RECORD_LENGTH: usize = 7;
 
 #[derive(Debug, Clone)]
 pub struct MapChunk {
@@ -32,6 +37,41 @@ impl MapChunk {
     }
 }
 
+fn read_block_list(contents: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    contents.chunks_exact(4)
+        .map(|mut entry| entry.read_u32::<Endian>().unwrap())
+}
+
+pub fn read_map_chunk(reader: &mut impl Read) -> anyhow::Result<MapChunk> {
+    reader.read_u32::<Endian>()?;
+
+    let mut chunk = MapChunk::default();
+    for tile_index in 0..CHUNK_AREA {
+        chunk.tile_ids[tile_index] = reader.read_u16::<Endian>()?;
+        chunk.heights[tile_index] = reader.read_i8()?;
+    }
+
+    Ok(chunk)
+}
+
+/// Load replacement chunks from mapdifl/mapdif, keyed by block number.
+pub async fn load_map_patches(data_path: &Path, index: usize) -> anyhow::Result<HashMap<u32, MapChunk>> {
+    let mut diff = HashMap::new();
+    let list = read_optional(&data_path.join(format!("mapdifl{}.mul", index))).await?;
+    let data = read_optional(&data_path.join(format!("mapdif{}.mul", index))).await?;
+    let (list, data) = match (list, data) {
+        (Some(list), Some(data)) => (list, data),
+        _ => return Ok(diff),
+    };
+
+    let mut reader = &data[..];
+    for block in read_block_list(&list) {
+        diff.insert(block, read_map_chunk(&mut reader)?);
+    }
+
+    Ok(diff)
+}
+
 pub async fn load_map<C: FnMut(usize, usize, MapChunk) -> F, F: Future<Output=anyhow::Result<()>>>(
     data_path: &Path,
     index: usize,
@@ -40,20 +80,16 @@ pub async fn load_map<C: FnMut(usize, usize, MapChunk) -> F, F: Future<Output=an
     mut callback: C,
 ) -> anyhow::Result<()> {
     let mut reader = MulReader::open(data_path, &format!("map{}", index)).await?;
+    let mut diff = load_map_patches(data_path, index).await?;
 
     let width_blocks = (width + CHUNK_SIZE - 1) / CHUNK_SIZE;
     let height_blocks = (height + CHUNK_SIZE - 1) / CHUNK_SIZE;
 
     for x in 0..width_blocks {
         for y in 0..height_blocks {
-            reader.read_u32::<Endian>()?;
-
-            let mut chunk = MapChunk::default();
-            for tile_index in 0..CHUNK_AREA {
-                chunk.tile_ids[tile_index] = reader.read_u16::<Endian>()?;
-                chunk.heights[tile_index] = reader.read_i8()?;
-            }
-
+            let chunk = read_map_chunk(&mut reader)?;
+            let block = (x * height_blocks + y) as u32;
+            let chunk = diff.remove(&block).unwrap_or(chunk);
             (callback)(x, y, chunk).await?;
         }
     }
@@ -68,6 +104,83 @@ pub struct Static {
     pub hue: u16,
 }
 
+/// Decode a single block of statics.
+pub fn read_static_block(mut bytes: &[u8], block_x: usize, block_y: usize) -> anyhow::Result<Vec<Static>> {
+    let x_base = (block_x * CHUNK_SIZE) as i32;
+    let y_base = (block_y * CHUNK_SIZE) as i32;
+    let mut statics = Vec::with_capacity(bytes.len() / STATIC_RECORD_LENGTH);
+
+    while bytes.len() >= STATIC_RECORD_LENGTH {
+        let graphic_id = bytes.read_u16::<Endian>()?;
+        let x_off = bytes.read_i8()? as i32;
+        let y_off = bytes.read_i8()? as i32;
+        let z = bytes.read_i8()? as i32;
+        let hue = bytes.read_u16::<Endian>()?;
+
+        let x = x_base + x_off;
+        let y = y_base + y_off;
+        statics.push(Static {
+            position: IVec3::new(x, y, z),
+            graphic_id,
+            hue,
+        });
+    }
+
+    Ok(statics)
+}
+
+/// Load replacement static blocks from stadifl/stadifi/stadif.
+///
+/// Returns the diff data along with the location of each replaced block within it.
+async fn load_statics_diff(data_path: &Path, index: usize) -> anyhow::Result<(Vec<u8>, HashMap<u32, Option<(usize, usize)>>)> {
+    let mut blocks = HashMap::new();
+    let list = read_optional(&data_path.join(format!("stadifl{}.mul", index))).await?;
+    let diff_index = read_optional(&data_path.join(format!("stadifi{}.mul", index))).await?;
+    let data = read_optional(&data_path.join(format!("stadif{}.mul", index))).await?;
+    let (list, diff_index, data) = match (list, diff_index, data) {
+        (Some(list), Some(diff_index), Some(data)) => (list, diff_index, data),
+        _ => return Ok((Vec::new(), blocks)),
+    };
+
+    let mut index_reader = &diff_index[..];
+    for block in read_block_list(&list) {
+        let offset = index_reader.read_u32::<Endian>()?;
+        let length = index_reader.read_u32::<Endian>()?;
+        index_reader.read_u32::<Endian>()?;
+
+        // An empty diff entry removes all statics from the block.
+        if offset == 0xffffffff || length == 0xffffffff {
+            blocks.insert(block, None);
+        } else {
+            blocks.insert(block, Some((offset as usize, length as usize)));
+        }
+    }
+
+    Ok((data, blocks))
+}
+
+/// Load replacement static blocks from stadifl/stadifi/stadif, keyed by block number.
+pub async fn load_statics_patches(data_path: &Path, index: usize, height: usize) -> anyhow::Result<HashMap<u32, Vec<Static>>> {
+    let height_blocks = height.div_ceil(CHUNK_SIZE);
+    let (data, blocks) = load_statics_diff(data_path, index).await?;
+    let mut patches = HashMap::with_capacity(blocks.len());
+
+    for (block, entry) in blocks {
+        let statics = match entry {
+            Some((offset, length)) => {
+                let bytes = data.get(offset..offset + length)
+                    .ok_or_else(|| anyhow!("statics patch for block {block} is out of bounds"))?;
+                let block_index = block as usize;
+                read_static_block(bytes, block_index / height_blocks, block_index % height_blocks)?
+            }
+            None => Vec::new(),
+        };
+        patches.insert(block, statics);
+    }
+
+    Ok(patches)
+}
+
 pub async fn load_statics<C: FnMut(Static) -> F, F: Future<Output=anyhow::Result<()>>>(
     data_path: &Path,
     index: usize,
@@ -82,6 +195,7 @@ pub async fn load_statics<C: FnMut(Static) -> F, F: Future<Output=anyhow::Result
         data_reader.read_to_end(&mut data)?;
         data
     };
+    let (diff_data, diff_blocks) = load_statics_diff(data_path, index).await?;
 
     let width_blocks = (width + CHUNK_SIZE - 1) / CHUNK_SIZE;
     let height_blocks = (height + CHUNK_SIZE - 1) / CHUNK_SIZE;
@@ -91,35 +205,246 @@ pub async fn load_statics<C: FnMut(Static) -> F, F: Future<Output=anyhow::Result
             let offset = index_reader.read_u32::<Endian>()?;
             let length = index_reader.read_u32::<Endian>()?;
             index_reader.read_u32::<Endian>()?;
-            if offset == 0xffffffff || length == 0xffffffff {
-                continue;
+
+            let block = (block_x * height_blocks + block_y) as u32;
+            let (source, entry) = match diff_blocks.get(&block) {
+                Some(entry) => (&diff_data, *entry),
+                None if offset == 0xffffffff || length == 0xffffffff => continue,
+                None => (&data, Some((offset as usize, length as usize))),
+            };
+            let (start, length) = match entry {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let bytes = source.get(start..start + length)
+                .ok_or_else(|| anyhow!("statics for block {block_x},{block_y} are out of bounds"))?;
+            for item in read_static_block(bytes, block_x, block_y)? {
+                (callback)(item).await?;
             }
+        }
+    }
+
+    Ok(())
+}
+
+
+fn write_chunk(out: &mut impl Write, chunk: &MapChunk) -> anyhow::Result<()> {
+    out.write_u32::<Endian>(0)?;
+    for tile_index in 0..CHUNK_AREA {
+        out.write_u16::<Endian>(chunk.tile_ids[tile_index])?;
+        out.write_i8(chunk.heights[tile_index])?;
+    }
+    Ok(())
+}
+
+/// Builder for map MUL files, in the layout read by [`load_map`].
+#[derive(Debug, Clone)]
+pub struct MapWriter {
+    width_blocks: usize,
+    height_blocks: usize,
+    data: Vec<u8>,
+}
+
+impl MapWriter {
+    pub fn new(width: usize, height: usize) -> MapWriter {
+        let width_blocks = width.div_ceil(CHUNK_SIZE);
+        let height_blocks = height.div_ceil(CHUNK_SIZE);
+        let mut data = Vec::with_capacity(width_blocks * height_blocks * MAP_BLOCK_LENGTH);
+        let empty = MapChunk::default();
+        for _ in 0..(width_blocks * height_blocks) {
+            write_chunk(&mut data, &empty).unwrap();
+        }
 
-            let x_base = (block_x * CHUNK_SIZE) as i32;
-            let y_base = (block_y * CHUNK_SIZE) as i32;
+        MapWriter {
+            width_blocks,
+            height_blocks,
+            data,
+        }
+    }
+
+    pub fn set_chunk(&mut self, chunk_x: usize, chunk_y: usize, chunk: &MapChunk) -> anyhow::Result<()> {
+        if chunk_x >= self.width_blocks || chunk_y >= self.height_blocks {
+            return Err(anyhow!("chunk {chunk_x},{chunk_y} is outside of the map"));
+        }
+
+        let offset = (chunk_x * self.height_blocks + chunk_y) * MAP_BLOCK_LENGTH;
+        let mut out = &mut self.data[offset..offset + MAP_BLOCK_LENGTH];
+        write_chunk(&mut out, chunk)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
 
-            let start = offset as usize;
-            let end = start + (length as usize);
+    pub async fn save(&self, data_path: &Path, index: usize, format: MulFormat) -> anyhow::Result<()> {
+        write_mul(data_path, &format!("map{}", index), &self.data, format).await
+    }
+}
 
-            let mut bytes = &data[start..end];
+/// Builder for staidx and statics MUL files, in the layout read by [`load_statics`].
+#[derive(Debug, Clone)]
+pub struct StaticsWriter {
+    width_blocks: usize,
+    height_blocks: usize,
+    blocks: Vec<Vec<Static>>,
+}
+
+impl StaticsWriter {
+    pub fn new(width: usize, height: usize) -> StaticsWriter {
+        let width_blocks = width.div_ceil(CHUNK_SIZE);
+        let height_blocks = height.div_ceil(CHUNK_SIZE);
+        StaticsWriter {
+            width_blocks,
+            height_blocks,
+            blocks: vec![Vec::new(); width_blocks * height_blocks],
+        }
+    }
+
+    pub fn push(&mut self, item: Static) -> anyhow::Result<()> {
+        let position = item.position;
+        if position.x < 0 || position.y < 0 {
+            return Err(anyhow!("static at {position} is outside of the map"));
+        }
+
+        let block_x = position.x as usize / CHUNK_SIZE;
+        let block_y = position.y as usize / CHUNK_SIZE;
+        if block_x >= self.width_blocks || block_y >= self.height_blocks {
+            return Err(anyhow!("static at {position} is outside of the map"));
+        }
+        if !(i8::MIN as i32..=i8::MAX as i32).contains(&position.z) {
+            return Err(anyhow!("static at {position} has out of range Z"));
+        }
+
+        self.blocks[block_x * self.height_blocks + block_y].push(item);
+        Ok(())
+    }
+
+    /// Encode the index and data files.
+    pub fn to_bytes(&self) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let mut index = Vec::with_capacity(self.blocks.len() * 12);
+        let mut data = Vec::new();
+
+        for block in &self.blocks {
+            if block.is_empty() {
+                index.write_u32::<Endian>(0xffffffff)?;
+                index.write_u32::<Endian>(0xffffffff)?;
+                index.write_u32::<Endian>(0)?;
+                continue;
+            }
 
-            while bytes.len() > 0 {
-                let graphic_id = bytes.read_u16::<Endian>()?;
-                let x_off = bytes.read_i8()? as i32;
-                let y_off = bytes.read_i8()? as i32;
-                let z = bytes.read_i8()? as i32;
-                let hue = bytes.read_u16::<Endian>()?;
+            index.write_u32::<Endian>(data.len() as u32)?;
+            index.write_u32::<Endian>((block.len() * STATIC_RECORD_LENGTH) as u32)?;
+            index.write_u32::<Endian>(0)?;
 
-                let x = x_base + x_off;
-                let y = y_base + y_off;
-                (callback)(Static {
-                    position: IVec3::new(x, y, z),
-                    graphic_id,
-                    hue,
-                }).await?;
+            for item in block {
+                data.write_u16::<Endian>(item.graphic_id)?;
+                data.write_u8((item.position.x as usize % CHUNK_SIZE) as u8)?;
+                data.write_u8((item.position.y as usize % CHUNK_SIZE) as u8)?;
+                data.write_i8(item.position.z as i8)?;
+                data.write_u16::<Endian>(item.hue)?;
             }
         }
+
+        Ok((index, data))
     }
 
-    Ok(())
+    pub async fn save(&self, data_path: &Path, index: usize, format: MulFormat) -> anyhow::Result<()> {
+        let (index_bytes, data) = self.to_bytes()?;
+        write_mul(data_path, &format!("staidx{}", index), &index_bytes, format).await?;
+        write_mul(data_path, &format!("statics{}", index), &data, format).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("yewoh-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn chunk(seed: u16) -> MapChunk {
+        let mut chunk = MapChunk::default();
+        for index in 0..CHUNK_AREA {
+            chunk.tile_ids[index] = seed + index as u16;
+            chunk.heights[index] = (index as i8).wrapping_sub(seed as i8);
+        }
+        chunk
+    }
+
+    #[test]
+    fn map_writer_round_trips_chunks() {
+        let mut writer = MapWriter::new(24, 16);
+        writer.set_chunk(2, 1, &chunk(10)).unwrap();
+        assert!(writer.set_chunk(3, 0, &chunk(10)).is_err());
+        assert!(writer.set_chunk(0, 2, &chunk(10)).is_err());
+
+        let bytes = writer.as_bytes();
+        assert_eq!(bytes.len(), 3 * 2 * MAP_BLOCK_LENGTH);
+
+        let offset = (2 * 2 + 1) * MAP_BLOCK_LENGTH;
+        let read = read_map_chunk(&mut &bytes[offset..]).unwrap();
+        let expected = chunk(10);
+        assert_eq!(read.tile_ids, expected.tile_ids);
+        assert_eq!(read.heights, expected.heights);
+
+        let empty = read_map_chunk(&mut &bytes[..]).unwrap();
+        assert_eq!(empty.tile_ids, [0; CHUNK_AREA]);
+    }
+
+    #[tokio::test]
+    async fn statics_writer_round_trips_statics() {
+        let statics = [
+            Static { position: IVec3::new(0, 0, 0), graphic_id: 1, hue: 0 },
+            Static { position: IVec3::new(9, 3, -5), graphic_id: 2, hue: 3 },
+            Static { position: IVec3::new(9, 3, 127), graphic_id: 4, hue: 0 },
+            Static { position: IVec3::new(23, 15, 10), graphic_id: 5, hue: 6 },
+        ];
+
+        let mut writer = StaticsWriter::new(24, 16);
+        for item in &statics {
+            writer.push(item.clone()).unwrap();
+        }
+        assert!(writer.push(Static { position: IVec3::new(24, 0, 0), graphic_id: 1, hue: 0 }).is_err());
+        assert!(writer.push(Static { position: IVec3::new(-1, 0, 0), graphic_id: 1, hue: 0 }).is_err());
+        assert!(writer.push(Static { position: IVec3::new(0, 0, 128), graphic_id: 1, hue: 0 }).is_err());
+
+        for (index, format) in [MulFormat::Mul, MulFormat::Uop].into_iter().enumerate() {
+            let path = temp_dir(&format!("statics-{index}"));
+            writer.save(&path, 0, format).await.unwrap();
+
+            let mut read = Vec::new();
+            load_statics(&path, 0, 24, 16, |item| {
+                read.push((item.position, item.graphic_id, item.hue));
+                async { Ok(()) }
+            }).await.unwrap();
+            std::fs::remove_dir_all(&path).unwrap();
+
+            let expected = statics.iter()
+                .map(|s| (s.position, s.graphic_id, s.hue))
+                .collect::<Vec<_>>();
+            assert_eq!(read, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn out_of_bounds_statics_are_an_error() {
+        let path = temp_dir("statics-bounds");
+        let mut index = Vec::new();
+        index.write_u32::<Endian>(0).unwrap();
+        index.write_u32::<Endian>(2 * STATIC_RECORD_LENGTH as u32).unwrap();
+        index.write_u32::<Endian>(0).unwrap();
+        std::fs::write(path.join("staidx0.mul"), index).unwrap();
+        std::fs::write(path.join("statics0.mul"), [0u8; STATIC_RECORD_LENGTH]).unwrap();
+
+        let result = load_statics(&path, 0, 8, 8, |_| async { Ok(()) }).await;
+        std::fs::remove_dir_all(&path).unwrap();
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("block 0,0"), "{err}");
+    }
 }
@@ -2,8 +2,18 @@ pub mod uop;
 
 pub mod mul;
 
+pub mod verdata;
+
 pub mod map;
 
 pub mod tiles;
 
 pub mod multi;
+
+pub mod animations;
+
+pub mod sounds;
+
+pub mod radar;
+
+pub mod fonts;
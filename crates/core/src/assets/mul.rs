@@ -1,8 +1,11 @@
-use std::io::{Cursor, Read};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
-use tokio::fs::File;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use tokio::fs::{self, File};
 use tokio::io::AsyncReadExt;
-use crate::assets::uop::UopBuffer;
+use crate::assets::uop::{hash, read_uop_index, UopBuffer, UopWriter};
 
 struct UopMul {
     uop: UopBuffer<Vec<u8>>,
@@ -67,3 +70,128 @@ impl MulReader {
         }
     }
 }
+
+/// Read a whole file, treating a missing file as `None`.
+pub(crate) async fn read_optional(path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    match fs::read(path).await {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Block size used when splitting legacy MUL files into UOP entries.
+const LEGACY_MUL_BLOCK_SIZE: usize = 0xc4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MulFormat {
+    #[default]
+    Mul,
+    Uop,
+}
+
+/// Write a MUL file in the same layout [`MulReader::open`] expects to read.
+pub async fn write_mul(data_path: &Path, name: &str, contents: &[u8], format: MulFormat) -> anyhow::Result<()> {
+    match format {
+        MulFormat::Mul => {
+            fs::write(data_path.join(format!("{}.mul", name)), contents).await?;
+        }
+        MulFormat::Uop => {
+            let mut writer = UopWriter::new();
+            for (index, block) in contents.chunks(LEGACY_MUL_BLOCK_SIZE).enumerate() {
+                let path = format!("build/{}legacymul/{:08}.dat", name, index);
+                writer.insert(&path, block.to_vec());
+            }
+            fs::write(data_path.join(format!("{}LegacyMUL.uop", name)), writer.to_bytes()?).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MulSegment {
+    start: u64,
+    file_offset: u64,
+    length: u64,
+}
+
+/// Random access to a MUL file without loading it into memory.
+///
+/// This supports the same raw and `LegacyMUL.uop` layouts as [`MulReader`], as long as
+/// the UOP entries are uncompressed.
+#[derive(Debug)]
+pub struct MulFile {
+    file: Mutex<std::fs::File>,
+    segments: Vec<MulSegment>,
+    len: u64,
+}
+
+impl MulFile {
+    pub fn open(data_path: &Path, name: &str) -> anyhow::Result<MulFile> {
+        let uop_path = data_path.join(format!("{}LegacyMUL.uop", name));
+        if let Ok(mut file) = std::fs::File::open(&uop_path) {
+            let index = read_uop_index(&mut file)?;
+            let mut segments = Vec::new();
+            let mut len = 0;
+
+            for block_index in 0.. {
+                let path = format!("build/{}legacymul/{:08}.dat", name, block_index);
+                let location = match index.get(&hash(path.as_bytes())) {
+                    Some(x) => x,
+                    None => break,
+                };
+
+                if location.is_compressed {
+                    return Err(anyhow!("compressed entry {path} cannot be read in place"));
+                }
+
+                segments.push(MulSegment {
+                    start: len,
+                    file_offset: location.data_offset,
+                    length: location.decompressed_length,
+                });
+                len += location.decompressed_length;
+            }
+
+            Ok(MulFile { file: Mutex::new(file), segments, len })
+        } else {
+            let path = data_path.join(format!("{}.mul", name));
+            let file = std::fs::File::open(&path)?;
+            let len = file.metadata()?.len();
+            let segments = vec![MulSegment { start: 0, file_offset: 0, length: len }];
+            Ok(MulFile { file: Mutex::new(file), segments, len })
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn read_at(&self, mut offset: u64, mut buf: &mut [u8]) -> anyhow::Result<()> {
+        if offset + buf.len() as u64 > self.len {
+            return Err(anyhow!("read past end of MUL file"));
+        }
+
+        let mut file = self.file.lock().unwrap();
+        let first = self.segments.partition_point(|s| s.start + s.length <= offset);
+        for segment in &self.segments[first..] {
+            if buf.is_empty() {
+                break;
+            }
+
+            let segment_offset = offset - segment.start;
+            let count = ((segment.length - segment_offset) as usize).min(buf.len());
+            file.seek(SeekFrom::Start(segment.file_offset + segment_offset))?;
+            file.read_exact(&mut buf[..count])?;
+            buf = &mut buf[count..];
+            offset += count as u64;
+        }
+
+        Ok(())
+    }
+}
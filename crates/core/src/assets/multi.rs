@@ -2,13 +2,16 @@ use std::path::Path;
 
 use byteorder::{LittleEndian as Endian, ReadBytesExt};
 use glam::IVec3;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use tokio::fs;
 
-use crate::assets::tiles::TileFlags;
+use crate::assets::mul::read_optional;
+use crate::assets::tiles::{TileData, TileFlags};
 use crate::assets::uop::UopBuffer;
+use crate::assets::verdata::{load_verdata, Verdata, VerdataFile};
 
 const MAX_MULTIS: usize = 9000;
+const LEGACY_COMPONENT_SIZE: usize = 12;
+const HIGH_SEAS_COMPONENT_SIZE: usize = 16;
 
 #[derive(Debug, Clone)]
 pub struct MultiPrefabComponent {
@@ -19,29 +22,42 @@ pub struct MultiPrefabComponent {
     pub tooltip_ids: Vec<u32>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct MultiPrefab {
     pub components: Vec<MultiPrefabComponent>,
 }
 
+impl MultiPrefab {
+    /// The minimum and maximum component offsets, inclusive, or `None` if there are no
+    /// components.
+    pub fn bounds(&self) -> Option<(IVec3, IVec3)> {
+        let first = self.components.first()?.position;
+        Some(self.components.iter()
+            .fold((first, first), |(min, max), c| (min.min(c.position), max.max(c.position))))
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct MultiData {
     pub prefabs: Vec<MultiPrefab>,
 }
 
-pub async fn load_multi_data(data_path: &Path) -> anyhow::Result<MultiData> {
-    let uop_path = data_path.join("MultiCollection.uop");
-    let mut file = File::open(&uop_path).await?;
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents).await?;
-    let uop = UopBuffer::try_from_backing(contents)?;
-    let mut prefabs = Vec::with_capacity(uop.len());
+fn tile_flags(tile_data: &TileData, graphic: u16) -> TileFlags {
+    tile_data.items.get(graphic as usize)
+        .map_or(TileFlags::empty(), |info| info.flags)
+}
+
+fn load_uop_multi_data(uop: &UopBuffer<Vec<u8>>, tile_data: &TileData) -> anyhow::Result<MultiData> {
+    let mut prefabs = Vec::with_capacity(MAX_MULTIS);
 
     for i in 0..MAX_MULTIS {
         let path = format!("build/multicollection/{:06}.bin", i);
         let mut entry = match uop.get(&path) {
             Some(x) => x,
-            None => continue,
+            None => {
+                prefabs.push(MultiPrefab::default());
+                continue;
+            }
         };
 
         let id = entry.read_u32::<Endian>()? as usize;
@@ -71,7 +87,7 @@ pub async fn load_multi_data(data_path: &Path) -> anyhow::Result<MultiData> {
                 graphic,
                 position,
                 component_flags,
-                tile_flags: TileFlags::empty(),
+                tile_flags: tile_flags(tile_data, graphic),
                 tooltip_ids,
             });
         }
@@ -83,3 +99,225 @@ pub async fn load_multi_data(data_path: &Path) -> anyhow::Result<MultiData> {
 
     Ok(MultiData { prefabs })
 }
+
+fn iter_index(index: &[u8]) -> impl Iterator<Item = Option<(usize, usize)>> + '_ {
+    index.chunks_exact(12)
+        .map(|mut entry| {
+            let offset = entry.read_u32::<Endian>().ok()?;
+            let length = entry.read_u32::<Endian>().ok()?;
+            if offset == 0xffffffff || length == 0xffffffff {
+                None
+            } else {
+                Some((offset as usize, length as usize))
+            }
+        })
+}
+
+/// Work out whether multi.mul uses the High Seas component layout, which added an extra
+/// 4 bytes to each component.
+fn detect_component_size(index: &[u8]) -> usize {
+    let mut legacy_votes = 0;
+    let mut high_seas_votes = 0;
+
+    for (_, length) in iter_index(index).flatten() {
+        let legacy = length % LEGACY_COMPONENT_SIZE == 0;
+        let high_seas = length % HIGH_SEAS_COMPONENT_SIZE == 0;
+        match (legacy, high_seas) {
+            (true, false) => legacy_votes += 1,
+            (false, true) => high_seas_votes += 1,
+            _ => {}
+        }
+    }
+
+    if high_seas_votes > legacy_votes {
+        HIGH_SEAS_COMPONENT_SIZE
+    } else {
+        LEGACY_COMPONENT_SIZE
+    }
+}
+
+fn read_mul_prefab(mut bytes: &[u8], component_size: usize, tile_data: &TileData) -> anyhow::Result<MultiPrefab> {
+    let count = bytes.len() / component_size;
+    let mut components = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let graphic = bytes.read_u16::<Endian>()?;
+        let x = bytes.read_i16::<Endian>()? as i32;
+        let y = bytes.read_i16::<Endian>()? as i32;
+        let z = bytes.read_i16::<Endian>()? as i32;
+        let component_flags = bytes.read_u32::<Endian>()? as u16;
+        if component_size == HIGH_SEAS_COMPONENT_SIZE {
+            bytes.read_u32::<Endian>()?;
+        }
+
+        components.push(MultiPrefabComponent {
+            graphic,
+            position: IVec3::new(x, y, z),
+            tile_flags: tile_flags(tile_data, graphic),
+            component_flags,
+            tooltip_ids: Vec::new(),
+        });
+    }
+
+    Ok(MultiPrefab {
+        components,
+    })
+}
+
+fn apply_verdata(multi_data: &mut MultiData, verdata: &Verdata, component_size: usize, tile_data: &TileData) -> anyhow::Result<()> {
+    for patch in verdata.patches(VerdataFile::Multi) {
+        let id = patch.block as usize;
+        if id >= MAX_MULTIS {
+            log::warn!("multi patch for out of range multi {id}");
+            continue;
+        }
+
+        // Verdata patches predate High Seas, but allow either layout.
+        let component_size = if patch.data.len() % component_size == 0 {
+            component_size
+        } else {
+            LEGACY_COMPONENT_SIZE
+        };
+
+        if multi_data.prefabs.len() <= id {
+            multi_data.prefabs.resize_with(id + 1, Default::default);
+        }
+        multi_data.prefabs[id] = read_mul_prefab(patch.data, component_size, tile_data)?;
+    }
+
+    Ok(())
+}
+
+fn load_mul_multi_data(index: &[u8], data: &[u8], component_size: usize, tile_data: &TileData) -> anyhow::Result<MultiData> {
+    let mut prefabs = Vec::with_capacity(index.len() / 12);
+
+    for entry in iter_index(index) {
+        let (offset, length) = match entry {
+            Some(x) => x,
+            None => {
+                prefabs.push(MultiPrefab::default());
+                continue;
+            }
+        };
+
+        let bytes = match data.get(offset..offset + length) {
+            Some(x) => x,
+            None => {
+                log::warn!("multi {} is out of bounds", prefabs.len());
+                prefabs.push(MultiPrefab::default());
+                continue;
+            }
+        };
+
+        prefabs.push(read_mul_prefab(bytes, component_size, tile_data)?);
+    }
+
+    Ok(MultiData { prefabs })
+}
+
+pub async fn load_multi_data(data_path: &Path, tile_data: &TileData) -> anyhow::Result<MultiData> {
+    let verdata = load_verdata(data_path).await?;
+
+    if let Some(contents) = read_optional(&data_path.join("MultiCollection.uop")).await? {
+        let uop = UopBuffer::try_from_backing(contents)?;
+        let mut multi_data = load_uop_multi_data(&uop, tile_data)?;
+        apply_verdata(&mut multi_data, &verdata, LEGACY_COMPONENT_SIZE, tile_data)?;
+        return Ok(multi_data);
+    }
+
+    let index = fs::read(data_path.join("multi.idx")).await?;
+    let data = fs::read(data_path.join("multi.mul")).await?;
+    let component_size = detect_component_size(&index);
+    let mut multi_data = load_mul_multi_data(&index, &data, component_size, tile_data)?;
+    apply_verdata(&mut multi_data, &verdata, component_size, tile_data)?;
+    Ok(multi_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+
+    use crate::assets::tiles::ItemInfo;
+
+    use super::*;
+
+    fn index(lengths: &[u32]) -> Vec<u8> {
+        let mut index = Vec::new();
+        for &length in lengths {
+            let offset = if length == 0xffffffff { 0xffffffff } else { 0 };
+            index.write_u32::<Endian>(offset).unwrap();
+            index.write_u32::<Endian>(length).unwrap();
+            index.write_u32::<Endian>(0).unwrap();
+        }
+        index
+    }
+
+    #[test]
+    fn detects_legacy_components() {
+        let index = index(&[24, 36, 60, 32, 0xffffffff]);
+        assert_eq!(detect_component_size(&index), LEGACY_COMPONENT_SIZE);
+    }
+
+    #[test]
+    fn detects_high_seas_components() {
+        let index = index(&[32, 64, 80, 36, 0xffffffff]);
+        assert_eq!(detect_component_size(&index), HIGH_SEAS_COMPONENT_SIZE);
+    }
+
+    #[test]
+    fn ignores_ambiguous_lengths() {
+        // Lengths which fit either layout don't outvote the one which only fits High Seas.
+        assert_eq!(detect_component_size(&index(&[48, 96, 144, 192, 32])), HIGH_SEAS_COMPONENT_SIZE);
+        // With nothing to go on, the legacy layout is assumed.
+        assert_eq!(detect_component_size(&index(&[48, 96])), LEGACY_COMPONENT_SIZE);
+    }
+
+    #[test]
+    fn reads_prefabs() {
+        let item = |flags| ItemInfo {
+            name: String::new(),
+            flags,
+            weight: 0,
+            quality: 0,
+            misc_data: 0,
+            quantity: 0,
+            animation: 0,
+            hue: 0,
+            stacking_offset: 0,
+            value: 0,
+            height: 0,
+        };
+        let tile_data = TileData {
+            land: Vec::new(),
+            items: vec![item(TileFlags::empty()), item(TileFlags::IMPASSABLE | TileFlags::WALL)],
+        };
+
+        for component_size in [LEGACY_COMPONENT_SIZE, HIGH_SEAS_COMPONENT_SIZE] {
+            let mut bytes = Vec::new();
+            for (graphic, x, y, z, flags) in [(1u16, -2i16, 3i16, 7i16, 1u32), (5, 0, -1, 0, 0)] {
+                bytes.write_u16::<Endian>(graphic).unwrap();
+                bytes.write_i16::<Endian>(x).unwrap();
+                bytes.write_i16::<Endian>(y).unwrap();
+                bytes.write_i16::<Endian>(z).unwrap();
+                bytes.write_u32::<Endian>(flags).unwrap();
+                if component_size == HIGH_SEAS_COMPONENT_SIZE {
+                    bytes.write_u32::<Endian>(0xdeadbeef).unwrap();
+                }
+            }
+
+            let prefab = read_mul_prefab(&bytes, component_size, &tile_data).unwrap();
+            assert_eq!(prefab.components.len(), 2);
+            let first = &prefab.components[0];
+            assert_eq!(first.graphic, 1);
+            assert_eq!(first.position, IVec3::new(-2, 3, 7));
+            assert_eq!(first.component_flags, 1);
+            assert_eq!(first.tile_flags.bits(), (TileFlags::IMPASSABLE | TileFlags::WALL).bits());
+            let second = &prefab.components[1];
+            assert_eq!(second.graphic, 5);
+            assert_eq!(second.position, IVec3::new(0, -1, 0));
+            // Graphics missing from tiledata have no flags.
+            assert!(second.tile_flags.is_empty());
+            assert_eq!(prefab.bounds(), Some((IVec3::new(-2, -1, 0), IVec3::new(0, 3, 7))));
+        }
+    }
+}
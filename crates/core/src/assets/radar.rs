@@ -0,0 +1,128 @@
+use std::future::ready;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::anyhow;
+use byteorder::{LittleEndian as Endian, ReadBytesExt};
+
+use crate::assets::map::{CHUNK_SIZE, load_map, load_statics};
+use crate::assets::mul::MulReader;
+
+const NUM_LAND_TILES: usize = 0x4000;
+
+/// Convert an ARGB1555 colour into 8-bit RGB.
+pub fn color_to_rgb(color: u16) -> [u8; 3] {
+    let expand = |c: u16| ((c & 0x1f) << 3 | (c & 0x1f) >> 2) as u8;
+    [expand(color >> 10), expand(color >> 5), expand(color)]
+}
+
+/// Minimap colours for land tiles and items, from radarcol.mul.
+#[derive(Debug, Clone, Default)]
+pub struct RadarColors {
+    pub land: Vec<u16>,
+    pub items: Vec<u16>,
+}
+
+impl RadarColors {
+    pub fn land_color(&self, id: u16) -> u16 {
+        self.land.get(id as usize).copied().unwrap_or(0)
+    }
+
+    pub fn item_color(&self, id: u16) -> u16 {
+        self.items.get(id as usize).copied().unwrap_or(0)
+    }
+}
+
+pub async fn load_radar_colors(data_path: &Path) -> anyhow::Result<RadarColors> {
+    let mut contents = Vec::new();
+    MulReader::open(data_path, "radarcol").await?.read_to_end(&mut contents)?;
+
+    let mut reader = &contents[..];
+    let mut colors = Vec::with_capacity(contents.len() / 2);
+    while reader.len() >= 2 {
+        colors.push(reader.read_u16::<Endian>()?);
+    }
+
+    let items = colors.split_off(NUM_LAND_TILES.min(colors.len()));
+    Ok(RadarColors { land: colors, items })
+}
+
+/// An 8-bit RGB minimap image.
+#[derive(Debug, Clone, Default)]
+pub struct Minimap {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 3]>,
+}
+
+/// Render a whole map into a minimap.
+///
+/// Each tile takes the colour of the highest static on it, or the land if there are no
+/// statics above the ground. Each pixel of the output covers `scale` x `scale` tiles,
+/// averaged together.
+pub async fn render_minimap(
+    data_path: &Path,
+    index: usize,
+    width: usize,
+    height: usize,
+    colors: &RadarColors,
+    scale: usize,
+) -> anyhow::Result<Minimap> {
+    if scale == 0 {
+        return Err(anyhow!("minimap scale must be at least 1"));
+    }
+
+    let mut tile_colors = vec![0u16; width * height];
+    let mut tile_z = vec![i8::MIN; width * height];
+
+    load_map(data_path, index, width, height, |block_x, block_y, chunk| {
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let tile_x = block_x * CHUNK_SIZE + x;
+                let tile_y = block_y * CHUNK_SIZE + y;
+                if tile_x >= width || tile_y >= height {
+                    continue;
+                }
+
+                let (tile_id, z) = chunk.get(x, y);
+                let offset = tile_y * width + tile_x;
+                tile_colors[offset] = colors.land_color(tile_id);
+                tile_z[offset] = z;
+            }
+        }
+        ready(Ok(()))
+    }).await?;
+
+    load_statics(data_path, index, width, height, |item| {
+        let (x, y, z) = (item.position.x as usize, item.position.y as usize, item.position.z);
+        if x < width && y < height {
+            let offset = y * width + x;
+            if z >= tile_z[offset] as i32 {
+                tile_colors[offset] = colors.item_color(item.graphic_id);
+                tile_z[offset] = z as i8;
+            }
+        }
+        ready(Ok(()))
+    }).await?;
+
+    let out_width = width.div_ceil(scale);
+    let out_height = height.div_ceil(scale);
+    let mut sums = vec![([0u32; 3], 0u32); out_width * out_height];
+    for (offset, color) in tile_colors.iter().enumerate() {
+        let (x, y) = (offset % width, offset / width);
+        let (sum, count) = &mut sums[(y / scale) * out_width + x / scale];
+        for (channel, value) in sum.iter_mut().zip(color_to_rgb(*color)) {
+            *channel += value as u32;
+        }
+        *count += 1;
+    }
+
+    let pixels = sums.into_iter()
+        .map(|(sum, count)| sum.map(|c| (c / count.max(1)) as u8))
+        .collect();
+    Ok(Minimap {
+        width: out_width,
+        height: out_height,
+        pixels,
+    })
+}
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::anyhow;
+use byteorder::{LittleEndian as Endian, ReadBytesExt, WriteBytesExt};
+use tokio::fs;
+
+use crate::assets::mul::read_optional;
+use crate::assets::uop::UopBuffer;
+
+pub const SOUND_SAMPLE_RATE: u32 = 22050;
+pub const SOUND_CHANNELS: u16 = 1;
+pub const SOUND_BITS_PER_SAMPLE: u16 = 16;
+
+const MAX_SOUNDS: usize = 0x1000;
+const NAME_LENGTH: usize = 40;
+
+#[derive(Debug, Clone)]
+pub struct Sound {
+    pub name: String,
+    /// Signed 16-bit mono PCM samples at [`SOUND_SAMPLE_RATE`].
+    pub samples: Vec<i16>,
+}
+
+impl Sound {
+    fn from_bytes(mut bytes: &[u8]) -> anyhow::Result<Sound> {
+        if bytes.len() < NAME_LENGTH {
+            return Err(anyhow!("truncated sound entry"));
+        }
+
+        let (name, rest) = bytes.split_at(NAME_LENGTH);
+        let name_length = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        let name = String::from_utf8_lossy(&name[..name_length]).into_owned();
+        bytes = rest;
+
+        let mut samples = Vec::with_capacity(bytes.len() / 2);
+        while bytes.len() >= 2 {
+            samples.push(bytes.read_i16::<Endian>()?);
+        }
+
+        Ok(Sound { name, samples })
+    }
+
+    /// Encode this sound as a RIFF WAV file.
+    pub fn to_wav(&self) -> Vec<u8> {
+        let data_length = (self.samples.len() * 2) as u32;
+        let block_align = SOUND_CHANNELS * SOUND_BITS_PER_SAMPLE / 8;
+        let byte_rate = SOUND_SAMPLE_RATE * block_align as u32;
+
+        let mut out = Vec::with_capacity(44 + data_length as usize);
+        out.write_all(b"RIFF").unwrap();
+        out.write_u32::<Endian>(36 + data_length).unwrap();
+        out.write_all(b"WAVEfmt ").unwrap();
+        out.write_u32::<Endian>(16).unwrap();
+        out.write_u16::<Endian>(1).unwrap();
+        out.write_u16::<Endian>(SOUND_CHANNELS).unwrap();
+        out.write_u32::<Endian>(SOUND_SAMPLE_RATE).unwrap();
+        out.write_u32::<Endian>(byte_rate).unwrap();
+        out.write_u16::<Endian>(block_align).unwrap();
+        out.write_u16::<Endian>(SOUND_BITS_PER_SAMPLE).unwrap();
+        out.write_all(b"data").unwrap();
+        out.write_u32::<Endian>(data_length).unwrap();
+        for sample in &self.samples {
+            out.write_i16::<Endian>(*sample).unwrap();
+        }
+        out
+    }
+}
+
+enum SoundSource {
+    Uop(UopBuffer<Vec<u8>>),
+    Mul(Vec<u8>),
+}
+
+pub struct Sounds {
+    source: SoundSource,
+    entries: BTreeMap<u16, (usize, usize)>,
+}
+
+impl Sounds {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, id: u16) -> bool {
+        self.entries.contains_key(&id)
+    }
+
+    pub fn iter_ids(&self) -> impl Iterator<Item = u16> + '_ {
+        self.entries.keys().copied()
+    }
+
+    pub fn get(&self, id: u16) -> anyhow::Result<Option<Sound>> {
+        let (offset, length) = match self.entries.get(&id) {
+            Some(x) => *x,
+            None => return Ok(None),
+        };
+
+        match &self.source {
+            SoundSource::Uop(uop) => {
+                let mut entry = uop.get(&uop_path(id))
+                    .ok_or_else(|| anyhow!("missing sound {id}"))?;
+                let mut contents = Vec::with_capacity(entry.len());
+                entry.read_to_end(&mut contents)?;
+                Ok(Some(Sound::from_bytes(&contents)?))
+            }
+            SoundSource::Mul(data) => {
+                let bytes = data.get(offset..offset + length)
+                    .ok_or_else(|| anyhow!("sound {id} is out of bounds"))?;
+                Ok(Some(Sound::from_bytes(bytes)?))
+            }
+        }
+    }
+}
+
+fn uop_path(id: u16) -> String {
+    format!("build/soundlegacymul/{:08}.dat", id)
+}
+
+pub async fn load_sounds(data_path: &Path) -> anyhow::Result<Sounds> {
+    if let Some(contents) = read_optional(&data_path.join("soundLegacyMUL.uop")).await? {
+        let uop = UopBuffer::try_from_backing(contents)?;
+        let mut entries = BTreeMap::new();
+
+        for id in 0..MAX_SOUNDS as u16 {
+            if let Some(entry) = uop.get(&uop_path(id)) {
+                if entry.len() > NAME_LENGTH {
+                    entries.insert(id, (0, entry.len()));
+                }
+            }
+        }
+
+        return Ok(Sounds {
+            source: SoundSource::Uop(uop),
+            entries,
+        });
+    }
+
+    let index = fs::read(data_path.join("soundidx.mul")).await?;
+    let data = fs::read(data_path.join("sound.mul")).await?;
+    let mut entries = BTreeMap::new();
+
+    for (id, mut entry) in index.chunks_exact(12).enumerate() {
+        let offset = entry.read_u32::<Endian>()?;
+        let length = entry.read_u32::<Endian>()?;
+        if offset == 0xffffffff || length == 0xffffffff || (length as usize) <= NAME_LENGTH {
+            continue;
+        }
+
+        entries.insert(id as u16, (offset as usize, length as usize));
+    }
+
+    Ok(Sounds {
+        source: SoundSource::Mul(data),
+        entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, samples: &[i16]) -> Vec<u8> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(NAME_LENGTH, 0);
+        for sample in samples {
+            bytes.write_i16::<Endian>(*sample).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_entries() {
+        let sound = Sound::from_bytes(&entry("bird1.wav", &[0, 1, -1, i16::MAX, i16::MIN])).unwrap();
+        assert_eq!(sound.name, "bird1.wav");
+        assert_eq!(sound.samples, vec![0, 1, -1, i16::MAX, i16::MIN]);
+
+        // A name filling the whole field has no terminator.
+        let long_name = "x".repeat(NAME_LENGTH);
+        let sound = Sound::from_bytes(&entry(&long_name, &[])).unwrap();
+        assert_eq!(sound.name, long_name);
+        assert!(sound.samples.is_empty());
+
+        assert!(Sound::from_bytes(&[0; NAME_LENGTH - 1]).is_err());
+    }
+
+    #[test]
+    fn reads_mul_entries() {
+        let first = entry("a.wav", &[1, 2]);
+        let second = entry("b.wav", &[3]);
+        let mut data = first.clone();
+        data.extend(&second);
+
+        let sounds = Sounds {
+            source: SoundSource::Mul(data),
+            entries: [(2, (0, first.len())), (5, (first.len(), second.len()))].into_iter().collect(),
+        };
+        assert_eq!(sounds.iter_ids().collect::<Vec<_>>(), vec![2, 5]);
+        assert_eq!(sounds.get(5).unwrap().unwrap().samples, vec![3]);
+        assert!(sounds.get(3).unwrap().is_none());
+    }
+
+    #[test]
+    fn encodes_wav() {
+        let sound = Sound { name: "test.wav".to_string(), samples: vec![1, -2, 300] };
+        let wav = sound.to_wav();
+        assert_eq!(wav.len(), 44 + 6);
+
+        let mut header = &wav[..];
+        let mut tag = [0u8; 4];
+        let mut read_tag = |header: &mut &[u8]| {
+            header.read_exact(&mut tag).unwrap();
+            tag
+        };
+        assert_eq!(&read_tag(&mut header), b"RIFF");
+        assert_eq!(header.read_u32::<Endian>().unwrap(), 36 + 6);
+        assert_eq!(&read_tag(&mut header), b"WAVE");
+        assert_eq!(&read_tag(&mut header), b"fmt ");
+        assert_eq!(header.read_u32::<Endian>().unwrap(), 16);
+        assert_eq!(header.read_u16::<Endian>().unwrap(), 1);
+        assert_eq!(header.read_u16::<Endian>().unwrap(), 1);
+        assert_eq!(header.read_u32::<Endian>().unwrap(), 22050);
+        assert_eq!(header.read_u32::<Endian>().unwrap(), 44100);
+        assert_eq!(header.read_u16::<Endian>().unwrap(), 2);
+        assert_eq!(header.read_u16::<Endian>().unwrap(), 16);
+        assert_eq!(&read_tag(&mut header), b"data");
+        assert_eq!(header.read_u32::<Endian>().unwrap(), 6);
+        assert_eq!(header, &[1, 0, 0xfe, 0xff, 0x2c, 0x01]);
+    }
+}
@@ -1,10 +1,13 @@
 use std::io::Read;
 use std::path::Path;
 
+use anyhow::anyhow;
 use bitflags::bitflags;
 use byteorder::{LittleEndian as Endian, ReadBytesExt};
 
 use crate::assets::mul::MulReader;
+use crate::assets::verdata::{load_verdata, Verdata, VerdataFile};
+use crate::protocol::{ClientVersion, VERSION_HIGH_SEAS};
 
 bitflags! {
     #[derive(Debug, Clone, Copy)]
@@ -53,13 +56,87 @@ pub struct ItemInfo {
     pub name: String,
     pub flags: TileFlags,
     pub weight: u8,
+    /// The equipment layer for wearables, or the light index for light sources.
     pub quality: u8,
-    pub animation: u16,
+    pub misc_data: u16,
     pub quantity: u8,
+    pub animation: u16,
+    pub hue: u8,
+    pub stacking_offset: u8,
     pub value: u8,
     pub height: u8,
 }
 
+impl ItemInfo {
+    /// The light index of this item, if it is a light source.
+    pub fn light_index(&self) -> Option<u8> {
+        self.flags.contains(TileFlags::LIGHT_SOURCE).then_some(self.quality)
+    }
+}
+
+/// The layout of tiledata.mul records.
+///
+/// High Seas extended the tile flags from 32 to 64 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileDataFormat {
+    Legacy,
+    HighSeas,
+}
+
+impl TileDataFormat {
+    pub fn for_client_version(client_version: ClientVersion) -> TileDataFormat {
+        if client_version >= VERSION_HIGH_SEAS {
+            TileDataFormat::HighSeas
+        } else {
+            TileDataFormat::Legacy
+        }
+    }
+
+    fn wide_flags(self) -> bool {
+        self == TileDataFormat::HighSeas
+    }
+
+    fn land_info_size(self) -> usize {
+        if self.wide_flags() { WIDE_LAND_INFO_SIZE } else { LEGACY_LAND_INFO_SIZE }
+    }
+
+    fn item_info_size(self) -> usize {
+        if self.wide_flags() { WIDE_ITEM_INFO_SIZE } else { LEGACY_ITEM_INFO_SIZE }
+    }
+
+    fn land_group_size(self) -> usize {
+        4 + TILE_GROUP_SIZE * self.land_info_size()
+    }
+
+    fn item_group_size(self) -> usize {
+        4 + TILE_GROUP_SIZE * self.item_info_size()
+    }
+
+    fn items_size(self, file_size: usize) -> Option<usize> {
+        let land_size = (NUM_LAND_TILES / TILE_GROUP_SIZE) * self.land_group_size();
+        file_size.checked_sub(land_size)
+    }
+
+    /// Work out the number of item tiles in a file of the given size, if it matches this
+    /// format exactly.
+    fn item_count(self, file_size: usize) -> Option<usize> {
+        let items_size = self.items_size(file_size)?;
+        (items_size % self.item_group_size() == 0)
+            .then(|| items_size / self.item_group_size() * TILE_GROUP_SIZE)
+    }
+
+    /// The number of complete item groups which fit in a file of the given size.
+    fn max_item_count(self, file_size: usize) -> usize {
+        self.items_size(file_size).unwrap_or(0) / self.item_group_size() * TILE_GROUP_SIZE
+    }
+
+    /// Guess the format of tiledata.mul from its size.
+    pub fn detect(file_size: usize) -> Option<TileDataFormat> {
+        [TileDataFormat::HighSeas, TileDataFormat::Legacy].into_iter()
+            .find(|format| format.item_count(file_size).is_some())
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct TileData {
     pub land: Vec<LandInfo>,
@@ -67,7 +144,11 @@ pub struct TileData {
 }
 
 const NUM_LAND_TILES: usize = 0x4000;
-const NUM_ITEMS: usize = 0x10000;
+const TILE_GROUP_SIZE: usize = 32;
+const LEGACY_LAND_INFO_SIZE: usize = 26;
+const LEGACY_ITEM_INFO_SIZE: usize = 37;
+const WIDE_LAND_INFO_SIZE: usize = 30;
+const WIDE_ITEM_INFO_SIZE: usize = 41;
 
 fn read_str_fixed(reader: &mut impl Read, len: usize) -> anyhow::Result<String> {
     let mut result = vec![0u8; len];
@@ -80,54 +161,286 @@ fn read_str_fixed(reader: &mut impl Read, len: usize) -> anyhow::Result<String>
     Ok(String::from_utf8(result)?)
 }
 
+fn read_flags(reader: &mut impl Read, wide_flags: bool) -> anyhow::Result<TileFlags> {
+    let bits = if wide_flags {
+        reader.read_u64::<Endian>()?
+    } else {
+        reader.read_u32::<Endian>()? as u64
+    };
+    Ok(TileFlags::from_bits_truncate(bits))
+}
+
+fn read_land_info(reader: &mut impl Read, format: TileDataFormat) -> anyhow::Result<LandInfo> {
+    let flags = read_flags(reader, format.wide_flags())?;
+    let texture_id = reader.read_u16::<Endian>()?;
+    let name = read_str_fixed(reader, 20)?;
+    Ok(LandInfo {
+        name,
+        flags,
+        texture_id
+    })
+}
+
+fn read_item_info(reader: &mut impl Read, format: TileDataFormat) -> anyhow::Result<ItemInfo> {
+    let flags = read_flags(reader, format.wide_flags())?;
+    let weight = reader.read_u8()?;
+    let quality = reader.read_u8()?;
+    let misc_data = reader.read_u16::<Endian>()?;
+    reader.read_u8()?;
+    let quantity = reader.read_u8()?;
+    let animation = reader.read_u16::<Endian>()?;
+    reader.read_u8()?;
+    let hue = reader.read_u8()?;
+    let stacking_offset = reader.read_u8()?;
+    let value = reader.read_u8()?;
+    let height = reader.read_u8()?;
+    let name = read_str_fixed(reader, 20)?;
+    Ok(ItemInfo {
+        name,
+        flags,
+        weight,
+        quality,
+        misc_data,
+        quantity,
+        animation,
+        hue,
+        stacking_offset,
+        value,
+        height
+    })
+}
+
+fn patch_format(patch_size: usize, high_seas_size: usize) -> TileDataFormat {
+    if patch_size >= high_seas_size {
+        TileDataFormat::HighSeas
+    } else {
+        TileDataFormat::Legacy
+    }
+}
+
+fn read_group<T>(
+    data: &[u8],
+    format: TileDataFormat,
+    read: impl Fn(&mut &[u8], TileDataFormat) -> anyhow::Result<T>,
+) -> anyhow::Result<Vec<T>> {
+    let mut reader = data;
+    reader.read_u32::<Endian>()?;
+    (0..TILE_GROUP_SIZE).map(|_| read(&mut reader, format)).collect()
+}
+
+/// Apply tiledata patches from verdata.
+///
+/// Each patch replaces a group of 32 tiles including the group header. Land groups come
+/// first, followed by item groups. Patches which can't be read are skipped.
+fn apply_verdata(tile_data: &mut TileData, verdata: &Verdata) {
+    const LAND_GROUPS: usize = NUM_LAND_TILES / TILE_GROUP_SIZE;
+
+    for patch in verdata.patches(VerdataFile::TileData) {
+        let group = patch.block as usize;
+        let result = if group < LAND_GROUPS {
+            let format = patch_format(patch.data.len(), TileDataFormat::HighSeas.land_group_size());
+            read_group(patch.data, format, |r, f| read_land_info(r, f)).map(|tiles| {
+                let first = group * TILE_GROUP_SIZE;
+                tile_data.land.splice(first..first + TILE_GROUP_SIZE, tiles);
+            })
+        } else {
+            let first = (group - LAND_GROUPS) * TILE_GROUP_SIZE;
+            if first + TILE_GROUP_SIZE > tile_data.items.len() {
+                log::warn!("tiledata patch for out of range item group {group}");
+                continue;
+            }
+
+            let format = patch_format(patch.data.len(), TileDataFormat::HighSeas.item_group_size());
+            read_group(patch.data, format, |r, f| read_item_info(r, f)).map(|tiles| {
+                tile_data.items.splice(first..first + TILE_GROUP_SIZE, tiles);
+            })
+        };
+
+        if let Err(err) = result {
+            log::warn!("skipping unreadable tiledata patch for group {group}: {err}");
+        }
+    }
+}
+
+/// Load tiledata, detecting the record layout from the size of the file.
 pub async fn load_tile_data(data_path: &Path) -> anyhow::Result<TileData> {
-    let mut reader = MulReader::open(data_path, "tiledata").await?;
+    load_tile_data_with_format(data_path, None).await
+}
+
+/// Load tiledata, optionally with the expected record layout.
+///
+/// The format hint is only used if the file size doesn't match either layout.
+pub async fn load_tile_data_with_format(data_path: &Path, format_hint: Option<TileDataFormat>) -> anyhow::Result<TileData> {
+    let mut contents = Vec::new();
+    MulReader::open(data_path, "tiledata").await?.read_to_end(&mut contents)?;
 
+    let mut tile_data = read_tile_data(&contents, format_hint)?;
+    let verdata = load_verdata(data_path).await?;
+    apply_verdata(&mut tile_data, &verdata);
+    Ok(tile_data)
+}
+
+/// Decode the contents of tiledata.mul, without any verdata patches.
+///
+/// See [`load_tile_data_with_format`] for how the format hint is used.
+pub fn read_tile_data(contents: &[u8], format_hint: Option<TileDataFormat>) -> anyhow::Result<TileData> {
+    let format = TileDataFormat::detect(contents.len())
+        .or(format_hint)
+        .ok_or_else(|| anyhow!("unrecognised tiledata layout ({} bytes)", contents.len()))?;
+    let num_items = format.item_count(contents.len())
+        .unwrap_or_else(|| format.max_item_count(contents.len()));
+
+    let mut reader = contents;
     let mut land = Vec::with_capacity(NUM_LAND_TILES);
-    let mut items = Vec::with_capacity(NUM_ITEMS);
+    let mut items = Vec::with_capacity(num_items);
 
     for index in 0..NUM_LAND_TILES {
-        if index & 0x1f == 0 {
+        if index % TILE_GROUP_SIZE == 0 {
             reader.read_u32::<Endian>()?;
         }
 
-        let flags = TileFlags::from_bits_truncate(reader.read_u64::<Endian>()?);
-        let texture_id = reader.read_u16::<Endian>()?;
-        let name = read_str_fixed(&mut reader, 20)?;
-        land.push(LandInfo {
-            name,
-            flags,
-            texture_id
-        });
+        land.push(read_land_info(&mut reader, format)?);
     }
 
-    for index in 0..NUM_ITEMS {
-        if index & 0x1f == 0 {
+    for index in 0..num_items {
+        if index % TILE_GROUP_SIZE == 0 {
             reader.read_u32::<Endian>()?;
         }
 
-        let flags = TileFlags::from_bits_truncate(reader.read_u64::<Endian>()?);
-        let weight = reader.read_u8()?;
-        let quality = reader.read_u8()?;
-        let animation = reader.read_u16::<Endian>()?;
-        reader.read_u8()?;
-        let quantity = reader.read_u8()?;
-        reader.read_u32::<Endian>()?;
-        reader.read_u8()?;
-        let value = reader.read_u8()?;
-        let height = reader.read_u8()?;
-        let name = read_str_fixed(&mut reader, 20)?;
-        items.push(ItemInfo {
-            name,
-            flags,
-            weight,
-            quality,
-            animation,
-            quantity,
-            value,
-            height
-        });
+        items.push(read_item_info(&mut reader, format)?);
     }
 
     Ok(TileData { land, items })
 }
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+
+    use super::*;
+
+    fn write_flags(out: &mut Vec<u8>, format: TileDataFormat, flags: u64) {
+        if format.wide_flags() {
+            out.write_u64::<Endian>(flags).unwrap();
+        } else {
+            out.write_u32::<Endian>(flags as u32).unwrap();
+        }
+    }
+
+    fn write_name(out: &mut Vec<u8>, name: &str) {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(20, 0);
+        out.extend(bytes);
+    }
+
+    fn write_land(out: &mut Vec<u8>, format: TileDataFormat, index: usize) {
+        write_flags(out, format, TileFlags::WET.bits() | (1 << 40));
+        out.write_u16::<Endian>(index as u16).unwrap();
+        write_name(out, "water");
+    }
+
+    fn write_item(out: &mut Vec<u8>, format: TileDataFormat, index: usize) {
+        write_flags(out, format, TileFlags::LIGHT_SOURCE.bits() | (1 << 40));
+        out.extend([2, 7]);
+        out.write_u16::<Endian>(index as u16).unwrap();
+        out.extend([0, 1]);
+        out.write_u16::<Endian>(0x1234).unwrap();
+        out.extend([0, 5, 6, 8, 9]);
+        write_name(out, &format!("lamp {index}"));
+    }
+
+    fn write_group(out: &mut Vec<u8>, write: impl Fn(&mut Vec<u8>, usize), first: usize) {
+        out.write_u32::<Endian>(0).unwrap();
+        for i in 0..TILE_GROUP_SIZE {
+            write(out, first + i);
+        }
+    }
+
+    fn tile_data_file(format: TileDataFormat, item_groups: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        for group in 0..NUM_LAND_TILES / TILE_GROUP_SIZE {
+            write_group(&mut out, |out, i| write_land(out, format, i), group * TILE_GROUP_SIZE);
+        }
+        for group in 0..item_groups {
+            write_group(&mut out, |out, i| write_item(out, format, i), group * TILE_GROUP_SIZE);
+        }
+        out
+    }
+
+    #[test]
+    fn detects_format_from_size() {
+        for format in [TileDataFormat::Legacy, TileDataFormat::HighSeas] {
+            for item_groups in [2, 0x200, 0x800] {
+                let size = tile_data_file(format, item_groups).len();
+                assert_eq!(TileDataFormat::detect(size), Some(format));
+                assert_eq!(format.item_count(size), Some(item_groups * TILE_GROUP_SIZE));
+            }
+        }
+
+        let size = tile_data_file(TileDataFormat::Legacy, 2).len();
+        assert_eq!(TileDataFormat::detect(size + 1), None);
+        assert_eq!(TileDataFormat::detect(100), None);
+    }
+
+    #[test]
+    fn reads_both_layouts() {
+        for format in [TileDataFormat::Legacy, TileDataFormat::HighSeas] {
+            let tile_data = read_tile_data(&tile_data_file(format, 2), None).unwrap();
+            assert_eq!(tile_data.land.len(), NUM_LAND_TILES);
+            assert_eq!(tile_data.items.len(), 2 * TILE_GROUP_SIZE);
+
+            let land = &tile_data.land[NUM_LAND_TILES - 1];
+            assert_eq!(land.name, "water");
+            assert_eq!(land.texture_id, (NUM_LAND_TILES - 1) as u16);
+            assert_eq!(land.flags.bits(), TileFlags::WET.bits());
+
+            let item = &tile_data.items[40];
+            assert_eq!(item.name, "lamp 40");
+            assert_eq!(item.flags.bits(), TileFlags::LIGHT_SOURCE.bits());
+            assert_eq!((item.weight, item.quality, item.misc_data, item.quantity), (2, 7, 40, 1));
+            assert_eq!(item.animation, 0x1234);
+            assert_eq!((item.hue, item.stacking_offset, item.value, item.height), (5, 6, 8, 9));
+            assert_eq!(item.light_index(), Some(7));
+        }
+    }
+
+    #[test]
+    fn format_hint_allows_truncated_files() {
+        let mut contents = tile_data_file(TileDataFormat::Legacy, 2);
+        contents.truncate(contents.len() - 10);
+        assert!(read_tile_data(&contents, None).is_err());
+
+        let tile_data = read_tile_data(&contents, Some(TileDataFormat::Legacy)).unwrap();
+        assert_eq!(tile_data.items.len(), TILE_GROUP_SIZE);
+    }
+
+    #[test]
+    fn skips_unreadable_patches() {
+        let land_groups = (NUM_LAND_TILES / TILE_GROUP_SIZE) as u32;
+        let mut good = Vec::new();
+        write_group(&mut good, |out, i| write_item(out, TileDataFormat::HighSeas, i + 100), 0);
+        let bad = vec![0u8; 10];
+
+        let mut verdata = Vec::new();
+        verdata.write_u32::<Endian>(2).unwrap();
+        let data_start = 4 + 2 * 20;
+        for (block, offset, length) in [
+            (land_groups, data_start, good.len()),
+            (land_groups + 1, data_start + good.len(), bad.len()),
+        ] {
+            verdata.write_u32::<Endian>(VerdataFile::TileData as u32).unwrap();
+            verdata.write_u32::<Endian>(block).unwrap();
+            verdata.write_u32::<Endian>(offset as u32).unwrap();
+            verdata.write_u32::<Endian>(length as u32).unwrap();
+            verdata.write_u32::<Endian>(0).unwrap();
+        }
+        verdata.extend(&good);
+        verdata.extend(&bad);
+        let verdata = Verdata::from_bytes(verdata).unwrap();
+
+        let mut tile_data = read_tile_data(&tile_data_file(TileDataFormat::Legacy, 2), None).unwrap();
+        apply_verdata(&mut tile_data, &verdata);
+        assert_eq!(tile_data.items[0].name, "lamp 100");
+        assert_eq!(tile_data.items[TILE_GROUP_SIZE].name, format!("lamp {}", TILE_GROUP_SIZE));
+    }
+}
@@ -1,12 +1,14 @@
 use std::collections::HashMap;
 use std::fmt;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::num::Wrapping;
 use std::ops::Deref;
 
 use anyhow::anyhow;
-use byteorder::{LittleEndian as Endian, ByteOrder, ReadBytesExt};
+use byteorder::{LittleEndian as Endian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use flate2::Compression;
 use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
 
 const FILE_MAGIC: &[u8] = b"MYP\0";
 
@@ -59,10 +61,11 @@ pub fn hash(mut src: &[u8]) -> u64 {
         src = &src[12..];
     }
 
-    if src.len() > 0 {
+    if !src.is_empty() {
+        // The last few bytes are zero-padded to 12.
         a += partial_read_u32(src);
-        b += partial_read_u32(&src[4..]);
-        c += partial_read_u32(&src[8..]);
+        b += partial_read_u32(src.get(4..).unwrap_or(&[]));
+        c += partial_read_u32(src.get(8..).unwrap_or(&[]));
 
         c = (c ^ b) - ((b << 14) | (b >> 18));
         a = (a ^ c) - ((c << 11) | (c >> 21));
@@ -177,6 +180,57 @@ impl<T: Deref<Target=[u8]>> UopBuffer<T> {
     }
 }
 
+/// The location of an entry within a UOP file.
+#[derive(Debug, Clone, Copy)]
+pub struct UopEntryLocation {
+    pub data_offset: u64,
+    pub compressed_length: u64,
+    pub decompressed_length: u64,
+    pub is_compressed: bool,
+}
+
+/// Read only the entry tables of a UOP file, so that entries can be read on demand
+/// without loading the whole file.
+pub fn read_uop_index(reader: &mut (impl Read + Seek)) -> anyhow::Result<HashMap<u64, UopEntryLocation>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != FILE_MAGIC {
+        return Err(anyhow!("Invalid UOP file header"));
+    }
+
+    let version = reader.read_u32::<Endian>()?;
+    if version > 5 {
+        return Err(anyhow!("Unsupported UOP version {version}"));
+    }
+    let _format_timestamp = reader.read_u32::<Endian>()?;
+    let mut next_block_offset = reader.read_u64::<Endian>()?;
+    let mut entries = HashMap::new();
+
+    while next_block_offset != 0 {
+        reader.seek(SeekFrom::Start(next_block_offset))?;
+        let file_count = reader.read_u32::<Endian>()?;
+        next_block_offset = reader.read_u64::<Endian>()?;
+
+        for _ in 0..file_count {
+            let offset = reader.read_u64::<Endian>()?;
+            let header_length = reader.read_u32::<Endian>()? as u64;
+            let compressed_length = reader.read_u32::<Endian>()? as u64;
+            let decompressed_length = reader.read_u32::<Endian>()? as u64;
+            let key_hash = reader.read_u64::<Endian>()?;
+            let _value_crc = reader.read_u32::<Endian>()?;
+            let is_compressed = reader.read_u16::<Endian>()?;
+            entries.insert(key_hash, UopEntryLocation {
+                data_offset: offset + header_length,
+                compressed_length,
+                decompressed_length,
+                is_compressed: is_compressed == 1,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
 impl<T: Clone> Clone for UopBuffer<T> {
     fn clone(&self) -> Self {
         Self {
@@ -227,3 +281,232 @@ impl<'a> Read for Entry<'a> {
         }
     }
 }
+
+const DEFAULT_VERSION: u32 = 5;
+const DEFAULT_FORMAT_TIMESTAMP: u32 = 0xfd23ec43;
+const DEFAULT_BLOCK_SIZE: usize = 1000;
+const HEADER_LENGTH: usize = 0x200;
+const BLOCK_HEADER_LENGTH: usize = 12;
+const BLOCK_ENTRY_LENGTH: usize = 34;
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    (b << 16) | a
+}
+
+struct PendingEntry {
+    key_hash: u64,
+    decompressed_length: usize,
+    data: Vec<u8>,
+    is_compressed: bool,
+}
+
+/// Builder for UOP containers.
+///
+/// Entries are keyed by the same path hash used by [`UopBuffer::get`].
+pub struct UopWriter {
+    block_size: usize,
+    entries: Vec<PendingEntry>,
+}
+
+impl Default for UopWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UopWriter {
+    pub fn new() -> UopWriter {
+        UopWriter {
+            block_size: DEFAULT_BLOCK_SIZE,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn insert(&mut self, key: &str, data: Vec<u8>) {
+        self.insert_by_hash(hash(key.as_bytes()), data);
+    }
+
+    pub fn insert_by_hash(&mut self, key_hash: u64, data: Vec<u8>) {
+        self.entries.push(PendingEntry {
+            key_hash,
+            decompressed_length: data.len(),
+            data,
+            is_compressed: false,
+        });
+    }
+
+    pub fn insert_compressed(&mut self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        self.entries.push(PendingEntry {
+            key_hash: hash(key.as_bytes()),
+            decompressed_length: data.len(),
+            data: encoder.finish()?,
+            is_compressed: true,
+        });
+        Ok(())
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> anyhow::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        header.write_all(FILE_MAGIC)?;
+        header.write_u32::<Endian>(DEFAULT_VERSION)?;
+        header.write_u32::<Endian>(DEFAULT_FORMAT_TIMESTAMP)?;
+        header.write_u64::<Endian>(if self.entries.is_empty() { 0 } else { HEADER_LENGTH as u64 })?;
+        header.write_u32::<Endian>(self.block_size as u32)?;
+        header.write_u32::<Endian>(self.entries.len() as u32)?;
+        header.resize(HEADER_LENGTH, 0);
+        out.write_all(&header)?;
+
+        // Each block table is immediately followed by the data for its entries.
+        let mut offset = HEADER_LENGTH;
+        let block_count = self.entries.len().div_ceil(self.block_size);
+        for (block_index, block) in self.entries.chunks(self.block_size).enumerate() {
+            let table_length = BLOCK_HEADER_LENGTH + self.block_size * BLOCK_ENTRY_LENGTH;
+            let data_length: usize = block.iter().map(|e| e.data.len()).sum();
+            let next_block_offset = if block_index + 1 < block_count {
+                (offset + table_length + data_length) as u64
+            } else {
+                0
+            };
+
+            let mut table = Vec::with_capacity(table_length);
+            table.write_u32::<Endian>(block.len() as u32)?;
+            table.write_u64::<Endian>(next_block_offset)?;
+
+            let mut data_offset = offset + table_length;
+            for entry in block {
+                table.write_u64::<Endian>(data_offset as u64)?;
+                table.write_u32::<Endian>(0)?;
+                table.write_u32::<Endian>(entry.data.len() as u32)?;
+                table.write_u32::<Endian>(entry.decompressed_length as u32)?;
+                table.write_u64::<Endian>(entry.key_hash)?;
+                table.write_u32::<Endian>(adler32(&entry.data))?;
+                table.write_u16::<Endian>(entry.is_compressed as u16)?;
+                data_offset += entry.data.len();
+            }
+            table.resize(table_length, 0);
+            out.write_all(&table)?;
+
+            for entry in block {
+                out.write_all(&entry.data)?;
+            }
+
+            offset = data_offset;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.write(&mut out)?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn key(index: usize) -> String {
+        format!("build/map0legacymul/{:08}.dat", index)
+    }
+
+    #[test]
+    fn adler32_matches_reference() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn round_trips_entries_across_blocks() {
+        let count = DEFAULT_BLOCK_SIZE + 1;
+        let mut writer = UopWriter::new();
+        for index in 0..count {
+            writer.insert(&key(index), format!("entry {index}").into_bytes());
+        }
+        writer.insert_compressed(&key(count), &[7u8; 256]).unwrap();
+        let bytes = writer.to_bytes().unwrap();
+
+        let index = read_uop_index(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(index.len(), count + 1);
+        for i in [0, DEFAULT_BLOCK_SIZE - 1, DEFAULT_BLOCK_SIZE] {
+            let expected = format!("entry {i}").into_bytes();
+            let location = index[&hash(key(i).as_bytes())];
+            assert!(!location.is_compressed);
+            assert_eq!(location.decompressed_length, expected.len() as u64);
+            let start = location.data_offset as usize;
+            assert_eq!(&bytes[start..start + expected.len()], &expected[..]);
+        }
+
+        let buffer = UopBuffer::try_from_backing(&bytes[..]).unwrap();
+        let mut decompressed = Vec::new();
+        let mut entry = buffer.get(&key(count)).unwrap();
+        assert_eq!(entry.len(), 256);
+        entry.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, vec![7u8; 256]);
+    }
+
+    #[test]
+    fn writes_block_tables() {
+        let mut writer = UopWriter::new();
+        for index in 0..(DEFAULT_BLOCK_SIZE + 1) {
+            writer.insert(&key(index), vec![index as u8; 3]);
+        }
+        let bytes = writer.to_bytes().unwrap();
+
+        let mut header = &bytes[FILE_MAGIC.len()..];
+        assert_eq!(header.read_u32::<Endian>().unwrap(), DEFAULT_VERSION);
+        assert_eq!(header.read_u32::<Endian>().unwrap(), DEFAULT_FORMAT_TIMESTAMP);
+        assert_eq!(header.read_u64::<Endian>().unwrap(), HEADER_LENGTH as u64);
+        assert_eq!(header.read_u32::<Endian>().unwrap(), DEFAULT_BLOCK_SIZE as u32);
+        assert_eq!(header.read_u32::<Endian>().unwrap(), DEFAULT_BLOCK_SIZE as u32 + 1);
+
+        // The first block is full and links to a second with the last entry.
+        let mut table = &bytes[HEADER_LENGTH..];
+        assert_eq!(table.read_u32::<Endian>().unwrap(), DEFAULT_BLOCK_SIZE as u32);
+        let next_block = table.read_u64::<Endian>().unwrap() as usize;
+        let table_length = BLOCK_HEADER_LENGTH + DEFAULT_BLOCK_SIZE * BLOCK_ENTRY_LENGTH;
+        assert_eq!(next_block, HEADER_LENGTH + table_length + DEFAULT_BLOCK_SIZE * 3);
+
+        let _offset = table.read_u64::<Endian>().unwrap();
+        assert_eq!(table.read_u32::<Endian>().unwrap(), 0);
+        assert_eq!(table.read_u32::<Endian>().unwrap(), 3);
+        assert_eq!(table.read_u32::<Endian>().unwrap(), 3);
+        assert_eq!(table.read_u64::<Endian>().unwrap(), hash(key(0).as_bytes()));
+        assert_eq!(table.read_u32::<Endian>().unwrap(), adler32(&[0, 0, 0]));
+        assert_eq!(table.read_u16::<Endian>().unwrap(), 0);
+
+        let mut last = &bytes[next_block..];
+        assert_eq!(last.read_u32::<Endian>().unwrap(), 1);
+        assert_eq!(last.read_u64::<Endian>().unwrap(), 0);
+        let offset = last.read_u64::<Endian>().unwrap() as usize;
+        assert_eq!(offset, next_block + table_length);
+        last = &last[12..];
+        assert_eq!(last.read_u64::<Endian>().unwrap(), hash(key(DEFAULT_BLOCK_SIZE).as_bytes()));
+        assert_eq!(&bytes[offset..], &[DEFAULT_BLOCK_SIZE as u8; 3]);
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use byteorder::{LittleEndian as Endian, ReadBytesExt};
+
+use crate::assets::mul::read_optional;
+
+/// Files which can be patched by verdata.mul.
+///
+/// Patches for indexed MUL files are keyed by the data file, with the block being the
+/// index entry they replace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u32)]
+pub enum VerdataFile {
+    Map0 = 0,
+    StaticIndex0 = 1,
+    Statics0 = 2,
+    ArtIndex = 3,
+    Art = 4,
+    AnimationIndex = 5,
+    Animation = 6,
+    SoundIndex = 7,
+    Sound = 8,
+    TextureIndex = 9,
+    Textures = 10,
+    GumpIndex = 11,
+    Gumps = 12,
+    MultiIndex = 13,
+    Multi = 14,
+    SkillsIndex = 15,
+    Skills = 16,
+    TileData = 30,
+    AnimData = 31,
+    Hues = 32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct VerdataEntry {
+    offset: usize,
+    length: usize,
+    extra: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VerdataPatch<'a> {
+    pub block: u32,
+    pub data: &'a [u8],
+    pub extra: u32,
+}
+
+/// Patch entries from verdata.mul.
+///
+/// Classic installs use this to override individual entries of other MUL files. Missing
+/// verdata is treated as an empty set of patches.
+#[derive(Debug, Clone, Default)]
+pub struct Verdata {
+    contents: Vec<u8>,
+    entries: BTreeMap<(u32, u32), VerdataEntry>,
+}
+
+impl Verdata {
+    pub fn from_bytes(contents: Vec<u8>) -> anyhow::Result<Verdata> {
+        let mut reader = &contents[..];
+        let count = reader.read_u32::<Endian>()? as usize;
+        let mut entries = BTreeMap::new();
+
+        for _ in 0..count {
+            let file = reader.read_u32::<Endian>()?;
+            let block = reader.read_u32::<Endian>()?;
+            let offset = reader.read_u32::<Endian>()?;
+            let length = reader.read_u32::<Endian>()?;
+            let extra = reader.read_u32::<Endian>()?;
+
+            if offset == 0xffffffff || length == 0xffffffff {
+                continue;
+            }
+
+            let (offset, length) = (offset as usize, length as usize);
+            if offset + length > contents.len() {
+                log::warn!("verdata patch for file {file} block {block} is out of bounds");
+                continue;
+            }
+
+            // Later entries take precedence over earlier ones.
+            entries.insert((file, block), VerdataEntry { offset, length, extra });
+        }
+
+        Ok(Verdata { contents, entries })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, file: VerdataFile, block: u32) -> Option<VerdataPatch<'_>> {
+        self.entries.get(&(file as u32, block))
+            .map(|entry| self.to_patch(block, entry))
+    }
+
+    pub fn patches(&self, file: VerdataFile) -> impl Iterator<Item = VerdataPatch<'_>> + '_ {
+        let file = file as u32;
+        self.entries.range((file, 0)..=(file, u32::MAX))
+            .map(|((_, block), entry)| self.to_patch(*block, entry))
+    }
+
+    fn to_patch(&self, block: u32, entry: &VerdataEntry) -> VerdataPatch<'_> {
+        VerdataPatch {
+            block,
+            data: &self.contents[entry.offset..entry.offset + entry.length],
+            extra: entry.extra,
+        }
+    }
+}
+
+pub async fn load_verdata(data_path: &Path) -> anyhow::Result<Verdata> {
+    match read_optional(&data_path.join("verdata.mul")).await? {
+        Some(contents) => Verdata::from_bytes(contents),
+        None => Ok(Verdata::default()),
+    }
+}
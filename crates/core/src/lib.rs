@@ -60,7 +60,7 @@ pub enum EntityKind {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, FromRepr, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, FromRepr, Serialize, Deserialize)]
 pub enum Notoriety {
     #[default]
     Innocent = 1,
@@ -2,6 +2,7 @@ use std::io::Write;
 
 use anyhow::anyhow;
 use byteorder::{ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
 use strum_macros::FromRepr;
 
 use crate::EntityId;
@@ -97,9 +98,22 @@ pub enum SkillsResponseKind {
     SingleUpdateWithCap = 0xdf,
 }
 
+impl SkillsResponseKind {
+    /// Whether this response lists every skill. Full lists use 1-based skill IDs and are
+    /// terminated by a zero ID.
+    pub fn is_full(self) -> bool {
+        matches!(self, SkillsResponseKind::Full | SkillsResponseKind::FullWithCaps)
+    }
+
+    pub fn has_caps(self) -> bool {
+        matches!(self, SkillsResponseKind::FullWithCaps | SkillsResponseKind::SingleUpdateWithCap)
+    }
+}
+
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, Eq, PartialEq, FromRepr)]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, FromRepr, Serialize, Deserialize)]
 pub enum SkillLock {
+    #[default]
     Up = 0,
     Down = 1,
     Locked = 2,
@@ -107,6 +121,7 @@ pub enum SkillLock {
 
 #[derive(Debug, Clone)]
 pub struct SkillEntry {
+    /// The 0-based skill ID.
     pub id: u16,
     pub value: u16,
     pub raw_value: u16,
@@ -148,13 +163,20 @@ impl Packet for Skills {
                 .ok_or_else(|| anyhow!("invalid skills response"))?;
             let mut skills = Vec::new();
 
-            while payload.len() > 2 {
-                let id = payload.read_u16::<Endian>()?;
+            while !payload.is_empty() {
+                let mut id = payload.read_u16::<Endian>()?;
+                if kind.is_full() {
+                    if id == 0 {
+                        break;
+                    }
+                    id -= 1;
+                }
+
                 let value = payload.read_u16::<Endian>()?;
                 let raw_value = payload.read_u16::<Endian>()?;
                 let lock = SkillLock::from_repr(payload.read_u8()?)
                     .ok_or_else(|| anyhow!("invalid skill lock"))?;
-                let cap = if kind == SkillsResponseKind::FullWithCaps || kind == SkillsResponseKind::SingleUpdateWithCap {
+                let cap = if kind.has_caps() {
                     payload.read_u16::<Endian>()?
                 } else {
                     0
@@ -168,10 +190,6 @@ impl Packet for Skills {
                 });
             }
 
-            if kind == SkillsResponseKind::Full {
-                payload.skip(2)?;
-            }
-
             Ok(Self::Response(SkillsResponse {
                 kind,
                 skills,
@@ -189,17 +207,17 @@ impl Packet for Skills {
                 writer.write_u8(response.kind as u8)?;
 
                 for skill in response.skills.iter() {
-                    writer.write_u16::<Endian>(skill.id)?;
+                    let id = if response.kind.is_full() { skill.id + 1 } else { skill.id };
+                    writer.write_u16::<Endian>(id)?;
                     writer.write_u16::<Endian>(skill.value)?;
                     writer.write_u16::<Endian>(skill.raw_value)?;
                     writer.write_u8(skill.lock as u8)?;
-                    if response.kind == SkillsResponseKind::FullWithCaps
-                        || response.kind == SkillsResponseKind::SingleUpdateWithCap {
+                    if response.kind.has_caps() {
                         writer.write_u16::<Endian>(skill.cap)?;
                     }
                 }
 
-                if response.kind == SkillsResponseKind::Full {
+                if response.kind.is_full() {
                     writer.write_u16::<Endian>(0)?;
                 }
             }
@@ -209,6 +227,68 @@ impl Packet for Skills {
     }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ActionRequest {
+    UseSkill(u16),
+    CastSpell(u16),
+    OpenDoor,
+    Other {
+        kind: u8,
+        argument: String,
+    },
+}
+
+impl ActionRequest {
+    const USE_SKILL: u8 = 0x24;
+    const CAST_SPELL_FROM_BOOK: u8 = 0x27;
+    const OPEN_DOOR: u8 = 0x58;
+    const CAST_SPELL: u8 = 0x56;
+}
+
+impl Packet for ActionRequest {
+    fn packet_kind() -> u8 { 0x12 }
+
+    fn fixed_length(_client_version: ClientVersion) -> Option<usize> { None }
+
+    fn decode(_client_version: ClientVersion, _from_client: bool, mut payload: &[u8]) -> anyhow::Result<Self> {
+        let kind = payload.read_u8()?;
+        let argument = payload.read_str_nul()?;
+        // Arguments are decimal numbers, optionally followed by other space-separated values.
+        let first_number = || argument.split(' ').next()
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or_else(|| anyhow!("invalid action argument '{argument}'"));
+
+        Ok(match kind {
+            Self::USE_SKILL => Self::UseSkill(first_number()?),
+            Self::CAST_SPELL | Self::CAST_SPELL_FROM_BOOK => Self::CastSpell(first_number()?),
+            Self::OPEN_DOOR => Self::OpenDoor,
+            kind => Self::Other { kind, argument },
+        })
+    }
+
+    fn encode(&self, _client_version: ClientVersion, _to_client: bool, writer: &mut impl Write) -> anyhow::Result<()> {
+        match self {
+            Self::UseSkill(skill_id) => {
+                writer.write_u8(Self::USE_SKILL)?;
+                writer.write_str_nul(&format!("{skill_id} 0"))?;
+            }
+            Self::CastSpell(spell_id) => {
+                writer.write_u8(Self::CAST_SPELL)?;
+                writer.write_str_nul(&spell_id.to_string())?;
+            }
+            Self::OpenDoor => {
+                writer.write_u8(Self::OPEN_DOOR)?;
+                writer.write_str_nul("")?;
+            }
+            Self::Other { kind, argument } => {
+                writer.write_u8(*kind)?;
+                writer.write_str_nul(argument)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AttackRequest {
     pub target_id: EntityId,
@@ -370,3 +450,54 @@ impl Packet for CharacterPredefinedAnimation {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u16) -> SkillEntry {
+        SkillEntry {
+            id,
+            value: 500,
+            raw_value: 500,
+            lock: SkillLock::Locked,
+            cap: 1000,
+        }
+    }
+
+    #[test]
+    fn full_skill_lists_use_one_based_ids() {
+        let version = ClientVersion::new(7, 0, 0, 0);
+        let packet = Skills::Response(SkillsResponse {
+            kind: SkillsResponseKind::FullWithCaps,
+            skills: vec![entry(0), entry(21)],
+        });
+
+        let mut bytes = Vec::new();
+        packet.encode(version, true, &mut bytes).unwrap();
+        assert_eq!(bytes, [
+            0x02,
+            0x00, 0x01, 0x01, 0xf4, 0x01, 0xf4, 0x02, 0x03, 0xe8,
+            0x00, 0x16, 0x01, 0xf4, 0x01, 0xf4, 0x02, 0x03, 0xe8,
+            0x00, 0x00,
+        ]);
+
+        let decoded = match Skills::decode(version, false, &bytes).unwrap() {
+            Skills::Response(x) => x,
+            _ => panic!("expected a skills response"),
+        };
+        assert_eq!(decoded.skills.iter().map(|s| s.id).collect::<Vec<_>>(), [0, 21]);
+    }
+
+    #[test]
+    fn single_skill_updates_use_zero_based_ids() {
+        let packet = Skills::Response(SkillsResponse {
+            kind: SkillsResponseKind::SingleUpdateWithCap,
+            skills: vec![entry(21)],
+        });
+
+        let mut bytes = Vec::new();
+        packet.encode(ClientVersion::new(7, 0, 0, 0), true, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xdf, 0x00, 0x15, 0x01, 0xf4, 0x01, 0xf4, 0x02, 0x03, 0xe8]);
+    }
+}
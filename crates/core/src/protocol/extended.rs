@@ -45,9 +45,18 @@ pub struct ContextMenuResponse {
     pub id: u16,
 }
 
+#[derive(Debug, Clone)]
+pub struct CloseGump {
+    pub type_id: u32,
+    pub button_id: u32,
+}
+
 #[derive(Debug, Clone)]
 pub enum ExtendedCommand {
     Unknown(u16),
+    FastWalkKeys([u32; 6]),
+    AddFastWalkKey(u32),
+    CloseGump(CloseGump),
     ScreenSize(ScreenSize),
     ChangeMap(u8),
     Language(String),
@@ -60,6 +69,9 @@ pub enum ExtendedCommand {
 }
 
 impl ExtendedCommand {
+    const FAST_WALK_KEYS: u16 = 0x1;
+    const ADD_FAST_WALK_KEY: u16 = 0x2;
+    const CLOSE_GUMP: u16 = 0x4;
     const SCREEN_SIZE: u16 = 0x5;
     const CHANGE_MAP: u16 = 0x8;
     const LANGUAGE: u16 = 0xb;
@@ -74,6 +86,9 @@ impl ExtendedCommand {
     pub fn kind(&self) -> u16 {
         match self {
             ExtendedCommand::Unknown(_) => panic!("Tried to send unknown extended command"),
+            ExtendedCommand::FastWalkKeys(_) => Self::FAST_WALK_KEYS,
+            ExtendedCommand::AddFastWalkKey(_) => Self::ADD_FAST_WALK_KEY,
+            ExtendedCommand::CloseGump(_) => Self::CLOSE_GUMP,
             ExtendedCommand::ScreenSize(_) => Self::SCREEN_SIZE,
             ExtendedCommand::ChangeMap(_) => Self::CHANGE_MAP,
             ExtendedCommand::Language(_) => Self::LANGUAGE,
@@ -94,6 +109,18 @@ impl Packet for ExtendedCommand {
     fn decode(_client_version: ClientVersion, _from_client: bool, mut payload: &[u8]) -> anyhow::Result<Self> {
         let kind = payload.read_u16::<Endian>()?;
         match kind {
+            Self::FAST_WALK_KEYS => {
+                let mut keys = [0u32; 6];
+                for key in keys.iter_mut() {
+                    *key = payload.read_u32::<Endian>()?;
+                }
+                Ok(ExtendedCommand::FastWalkKeys(keys))
+            }
+            Self::ADD_FAST_WALK_KEY => Ok(ExtendedCommand::AddFastWalkKey(payload.read_u32::<Endian>()?)),
+            Self::CLOSE_GUMP => Ok(ExtendedCommand::CloseGump(CloseGump {
+                type_id: payload.read_u32::<Endian>()?,
+                button_id: payload.read_u32::<Endian>()?,
+            })),
             Self::SCREEN_SIZE => Ok(ExtendedCommand::ScreenSize(ScreenSize {
                 width: payload.read_u32::<Endian>()?,
                 height: payload.read_u32::<Endian>()?,
@@ -158,6 +185,17 @@ impl Packet for ExtendedCommand {
         match self {
             ExtendedCommand::Unknown(_) =>
                 return Err(anyhow!("tried to send unknown extended command")),
+            ExtendedCommand::FastWalkKeys(keys) => {
+                for key in keys {
+                    writer.write_u32::<Endian>(*key)?;
+                }
+            }
+            ExtendedCommand::AddFastWalkKey(key) =>
+                writer.write_u32::<Endian>(*key)?,
+            ExtendedCommand::CloseGump(close) => {
+                writer.write_u32::<Endian>(close.type_id)?;
+                writer.write_u32::<Endian>(close.button_id)?;
+            }
             ExtendedCommand::ScreenSize(screen_size) => {
                 writer.write_u32::<Endian>(screen_size.width)?;
                 writer.write_u32::<Endian>(screen_size.height)?;
@@ -0,0 +1,525 @@
+use anyhow::{anyhow, bail};
+use glam::IVec2;
+
+use crate::EntityId;
+use crate::protocol::{CompressedGumpLayout, GumpLayout};
+
+/// Options which apply to a whole gump.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GumpOptions {
+    pub no_close: bool,
+    pub no_move: bool,
+    pub no_dispose: bool,
+    pub no_resize: bool,
+    pub master_gump: Option<u32>,
+}
+
+/// A single element of a gump layout, with any text references resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GumpCommand {
+    Tooltip { text_id: u32, args: String },
+    AlphaCutout { position: IVec2, size: IVec2 },
+    Image { image_id: u32, position: IVec2, hue: Option<u32> },
+    ImageSliced { image_id: u32, position: IVec2, size: IVec2 },
+    ImageTiled { image_id: u32, position: IVec2, size: IVec2 },
+    TileImage { graphic_id: u16, position: IVec2, hue: Option<u16> },
+    ItemProperty { entity_id: EntityId },
+    Sprite { image_id: u16, position: IVec2, size: IVec2, sprite_offset: IVec2 },
+    Text { text: String, hue: u16, position: IVec2 },
+    TextCropped { text: String, hue: u16, position: IVec2, size: IVec2 },
+    TextEntry {
+        id: u32,
+        default_text: String,
+        max_length: Option<usize>,
+        hue: u16,
+        position: IVec2,
+        size: IVec2,
+    },
+    Button {
+        up_texture_id: u32,
+        down_texture_id: u32,
+        button_id: u32,
+        page_id: u16,
+        close: bool,
+        position: IVec2,
+    },
+    TileButton {
+        up_texture_id: u32,
+        down_texture_id: u32,
+        graphic_id: u16,
+        hue: u16,
+        button_id: u32,
+        page_id: u16,
+        close: bool,
+        position: IVec2,
+        tile_offset: IVec2,
+    },
+    Checkbox { off_image_id: u32, on_image_id: u32, on: bool, switch_id: u32, position: IVec2 },
+    Radio { off_image_id: u32, on_image_id: u32, on: bool, switch_id: u32, position: IVec2 },
+    Html { text: String, background: bool, scrollbar: bool, position: IVec2, size: IVec2 },
+    HtmlLocalised {
+        text_id: u32,
+        args: Option<String>,
+        colour: Option<u32>,
+        background: bool,
+        scrollbar: bool,
+        position: IVec2,
+        size: IVec2,
+    },
+    /// A command this parser doesn't understand, kept so that layouts can still be inspected.
+    Unknown { name: String, args: Vec<String> },
+}
+
+/// A command, along with the radio group it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GumpElement {
+    pub group: Option<usize>,
+    pub command: GumpCommand,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GumpPage {
+    pub id: usize,
+    pub elements: Vec<GumpElement>,
+}
+
+/// A parsed gump layout.
+///
+/// Commands before the first `page` command are put in page 0, which is always shown.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedGump {
+    pub options: GumpOptions,
+    pub pages: Vec<GumpPage>,
+}
+
+impl ParsedGump {
+    pub fn page(&self, id: usize) -> Option<&GumpPage> {
+        self.pages.iter().find(|p| p.id == id)
+    }
+
+    /// All commands, in layout order.
+    pub fn commands(&self) -> impl Iterator<Item = &GumpCommand> + '_ {
+        self.pages.iter().flat_map(|p| p.elements.iter().map(|e| &e.command))
+    }
+
+    /// All resolved text shown by text, cropped text, HTML and text entry commands.
+    pub fn texts(&self) -> impl Iterator<Item = &str> + '_ {
+        self.commands().filter_map(|command| match command {
+            GumpCommand::Text { text, .. }
+            | GumpCommand::TextCropped { text, .. }
+            | GumpCommand::Html { text, .. }
+            | GumpCommand::TextEntry { default_text: text, .. } => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// All reply button IDs.
+    pub fn reply_buttons(&self) -> impl Iterator<Item = u32> + '_ {
+        self.commands().filter_map(|command| match command {
+            GumpCommand::Button { button_id, close: true, .. }
+            | GumpCommand::TileButton { button_id, close: true, .. } => Some(*button_id),
+            _ => None,
+        })
+    }
+}
+
+/// Split a layout into commands, each split into its arguments.
+///
+/// Arguments are separated by whitespace, except for text between `@` signs which is kept
+/// as one argument.
+fn tokenize(layout: &str) -> anyhow::Result<Vec<Vec<&str>>> {
+    let mut commands = Vec::new();
+    let mut rest = layout;
+
+    while let Some(start) = rest.find('{') {
+        if !rest[..start].trim().is_empty() {
+            bail!("unexpected text outside of gump command: {:?}", rest[..start].trim());
+        }
+
+        let body = &rest[start + 1..];
+        let mut args = Vec::new();
+        let mut index = 0;
+        let end = loop {
+            let remaining = &body[index..];
+            let trimmed = remaining.trim_start();
+            index += remaining.len() - trimmed.len();
+
+            match trimmed.chars().next() {
+                None => bail!("unterminated gump command"),
+                Some('}') => break index,
+                Some('@') => {
+                    let len = trimmed[1..].find('@')
+                        .ok_or_else(|| anyhow!("unterminated gump argument"))?;
+                    args.push(&trimmed[1..len + 1]);
+                    index += len + 2;
+                }
+                Some(_) => {
+                    let len = trimmed.find(|c: char| c.is_whitespace() || c == '}')
+                        .unwrap_or(trimmed.len());
+                    args.push(&trimmed[..len]);
+                    index += len;
+                }
+            }
+        };
+
+        if !args.is_empty() {
+            commands.push(args);
+        }
+        rest = &body[end + 1..];
+    }
+
+    if !rest.trim().is_empty() {
+        bail!("unexpected text outside of gump command: {:?}", rest.trim());
+    }
+
+    Ok(commands)
+}
+
+struct Args<'a, 'b> {
+    name: &'a str,
+    args: &'b [&'a str],
+    text: &'b [String],
+    index: usize,
+}
+
+impl<'a, 'b> Args<'a, 'b> {
+    fn next_str(&mut self) -> anyhow::Result<&'a str> {
+        let arg = self.args.get(self.index)
+            .ok_or_else(|| anyhow!("missing argument {} for '{}'", self.index + 1, self.name))?;
+        self.index += 1;
+        Ok(arg)
+    }
+
+    fn next<T: std::str::FromStr>(&mut self) -> anyhow::Result<T> {
+        let arg = self.next_str()?;
+        arg.parse().map_err(|_| anyhow!("invalid argument '{}' for '{}'", arg, self.name))
+    }
+
+    fn next_optional<T: std::str::FromStr>(&mut self) -> anyhow::Result<Option<T>> {
+        if self.index < self.args.len() {
+            self.next().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_bool(&mut self) -> anyhow::Result<bool> {
+        Ok(self.next::<u32>()? != 0)
+    }
+
+    fn next_vec(&mut self) -> anyhow::Result<IVec2> {
+        Ok(IVec2::new(self.next()?, self.next()?))
+    }
+
+    fn next_text(&mut self) -> anyhow::Result<String> {
+        let id = self.next::<usize>()?;
+        self.text.get(id)
+            .cloned()
+            .ok_or_else(|| anyhow!("text {} out of range for '{}'", id, self.name))
+    }
+}
+
+fn parse_command(mut args: Args) -> anyhow::Result<GumpCommand> {
+    let command = match args.name {
+        "tooltip" => GumpCommand::Tooltip {
+            text_id: args.next()?,
+            args: args.next_optional::<String>()?.unwrap_or_default(),
+        },
+        "checkertrans" => GumpCommand::AlphaCutout {
+            position: args.next_vec()?,
+            size: args.next_vec()?,
+        },
+        "gumppic" => {
+            let position = args.next_vec()?;
+            let image_id = args.next()?;
+            let hue = match args.next_optional::<String>()? {
+                Some(hue) => Some(hue.strip_prefix("hue=")
+                    .and_then(|h| h.parse().ok())
+                    .ok_or_else(|| anyhow!("invalid hue '{}' for 'gumppic'", hue))?),
+                None => None,
+            };
+            GumpCommand::Image { image_id, position, hue }
+        }
+        "resizepic" => {
+            let position = args.next_vec()?;
+            let image_id = args.next()?;
+            let size = args.next_vec()?;
+            GumpCommand::ImageSliced { image_id, position, size }
+        }
+        "gumppictiled" => {
+            let position = args.next_vec()?;
+            let size = args.next_vec()?;
+            let image_id = args.next()?;
+            GumpCommand::ImageTiled { image_id, position, size }
+        }
+        "tilepic" | "tilepichue" => {
+            let position = args.next_vec()?;
+            let graphic_id = args.next()?;
+            let hue = args.next_optional()?;
+            GumpCommand::TileImage { graphic_id, position, hue }
+        }
+        "itemproperty" => GumpCommand::ItemProperty {
+            entity_id: EntityId::from_u32(args.next()?),
+        },
+        "picinpic" => {
+            let position = args.next_vec()?;
+            let image_id = args.next()?;
+            let sprite_offset = args.next_vec()?;
+            let size = args.next_vec()?;
+            GumpCommand::Sprite { image_id, position, size, sprite_offset }
+        }
+        "text" => {
+            let position = args.next_vec()?;
+            let hue = args.next()?;
+            let text = args.next_text()?;
+            GumpCommand::Text { text, hue, position }
+        }
+        "croppedtext" => {
+            let position = args.next_vec()?;
+            let size = args.next_vec()?;
+            let hue = args.next()?;
+            let text = args.next_text()?;
+            GumpCommand::TextCropped { text, hue, position, size }
+        }
+        "textentry" | "textentrylimited" => {
+            let position = args.next_vec()?;
+            let size = args.next_vec()?;
+            let hue = args.next()?;
+            let id = args.next()?;
+            let default_text = args.next_text()?;
+            let max_length = if args.name == "textentrylimited" { Some(args.next()?) } else { None };
+            GumpCommand::TextEntry { id, default_text, max_length, hue, position, size }
+        }
+        "button" => {
+            let position = args.next_vec()?;
+            let up_texture_id = args.next()?;
+            let down_texture_id = args.next()?;
+            let close = args.next_bool()?;
+            let page_id = args.next()?;
+            let button_id = args.next()?;
+            GumpCommand::Button { up_texture_id, down_texture_id, button_id, page_id, close, position }
+        }
+        "buttontileart" => {
+            let position = args.next_vec()?;
+            let up_texture_id = args.next()?;
+            let down_texture_id = args.next()?;
+            let close = args.next_bool()?;
+            let page_id = args.next()?;
+            let button_id = args.next()?;
+            let graphic_id = args.next()?;
+            let hue = args.next()?;
+            let tile_offset = args.next_vec()?;
+            GumpCommand::TileButton {
+                up_texture_id,
+                down_texture_id,
+                graphic_id,
+                hue,
+                button_id,
+                page_id,
+                close,
+                position,
+                tile_offset,
+            }
+        }
+        "checkbox" | "radio" => {
+            let position = args.next_vec()?;
+            let off_image_id = args.next()?;
+            let on_image_id = args.next()?;
+            let on = args.next_bool()?;
+            let switch_id = args.next()?;
+            if args.name == "checkbox" {
+                GumpCommand::Checkbox { off_image_id, on_image_id, on, switch_id, position }
+            } else {
+                GumpCommand::Radio { off_image_id, on_image_id, on, switch_id, position }
+            }
+        }
+        "htmlgump" => {
+            let position = args.next_vec()?;
+            let size = args.next_vec()?;
+            let text = args.next_text()?;
+            let background = args.next_bool()?;
+            let scrollbar = args.next_bool()?;
+            GumpCommand::Html { text, background, scrollbar, position, size }
+        }
+        "xmfhtmlgump" | "xmfhtmlgumpcolor" => {
+            let position = args.next_vec()?;
+            let size = args.next_vec()?;
+            let text_id = args.next()?;
+            let background = args.next_bool()?;
+            let scrollbar = args.next_bool()?;
+            let colour = if args.name == "xmfhtmlgumpcolor" { Some(args.next()?) } else { None };
+            GumpCommand::HtmlLocalised { text_id, args: None, colour, background, scrollbar, position, size }
+        }
+        "xmfhtmltok" => {
+            // Unlike the other localised HTML commands, the text ID comes after the flags.
+            let position = args.next_vec()?;
+            let size = args.next_vec()?;
+            let background = args.next_bool()?;
+            let scrollbar = args.next_bool()?;
+            let colour = Some(args.next()?);
+            let text_id = args.next()?;
+            let params = Some(args.next()?);
+            GumpCommand::HtmlLocalised { text_id, args: params, colour, background, scrollbar, position, size }
+        }
+        _ => GumpCommand::Unknown {
+            name: args.name.to_string(),
+            args: args.args.iter().map(|s| s.to_string()).collect(),
+        },
+    };
+    Ok(command)
+}
+
+/// Parse a gump layout into pages of commands.
+pub fn parse_gump_layout(layout: &GumpLayout) -> anyhow::Result<ParsedGump> {
+    let mut gump = ParsedGump {
+        options: Default::default(),
+        pages: vec![GumpPage::default()],
+    };
+    let mut group = None;
+
+    for tokens in tokenize(&layout.layout)? {
+        let name = tokens[0].to_ascii_lowercase();
+        let mut args = Args {
+            name: &name,
+            args: &tokens[1..],
+            text: &layout.text,
+            index: 0,
+        };
+
+        match name.as_str() {
+            "noclose" => gump.options.no_close = true,
+            "nomove" => gump.options.no_move = true,
+            "nodispose" => gump.options.no_dispose = true,
+            "noresize" => gump.options.no_resize = true,
+            "mastergump" => gump.options.master_gump = Some(args.next()?),
+            "page" => {
+                let id = args.next()?;
+                group = None;
+                if id == 0 && gump.pages.len() == 1 && gump.pages[0].elements.is_empty() {
+                    continue;
+                }
+                gump.pages.push(GumpPage { id, elements: Vec::new() });
+            }
+            "group" => group = args.next_optional()?,
+            "endgroup" => group = None,
+            _ => {
+                let command = parse_command(args)?;
+                gump.pages.last_mut().unwrap().elements.push(GumpElement { group, command });
+            }
+        }
+    }
+
+    Ok(gump)
+}
+
+impl GumpLayout {
+    pub fn parse(&self) -> anyhow::Result<ParsedGump> {
+        parse_gump_layout(self)
+    }
+}
+
+impl CompressedGumpLayout {
+    /// Decompress and parse the layout.
+    pub fn parse(&self) -> anyhow::Result<ParsedGump> {
+        GumpLayout::try_from(self.clone())?.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(layout: &str, text: &[&str]) -> GumpLayout {
+        GumpLayout {
+            layout: layout.to_string(),
+            text: text.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn parses_pages_and_commands() {
+        let gump = layout(
+            "{ nomove }{ page 0 }{ resizepic 0 0 5054 420 440 }{ text 20 20 120 0 }\
+            { page 1 }{ button 20 80 247 248 1 0 3 }{ xmfhtmltok 1 2 3 4 0 1 32767 1015 @a b@ }",
+            &["Hello, world!"],
+        ).parse().unwrap();
+
+        assert!(gump.options.no_move);
+        assert_eq!(gump.pages.len(), 2);
+        assert_eq!(gump.pages[0].elements[1].command, GumpCommand::Text {
+            text: "Hello, world!".to_string(),
+            hue: 120,
+            position: IVec2::new(20, 20),
+        });
+        assert_eq!(gump.page(1).unwrap().elements[1].command, GumpCommand::HtmlLocalised {
+            text_id: 1015,
+            args: Some("a b".to_string()),
+            colour: Some(32767),
+            background: false,
+            scrollbar: true,
+            position: IVec2::new(1, 2),
+            size: IVec2::new(3, 4),
+        });
+        assert_eq!(gump.reply_buttons().collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn tracks_groups() {
+        let gump = layout(
+            "{ group 1 }{ radio 0 0 208 209 1 5 }{ group }{ radio 0 20 208 209 0 6 }",
+            &[],
+        ).parse().unwrap();
+
+        let groups: Vec<_> = gump.pages[0].elements.iter().map(|e| e.group).collect();
+        assert_eq!(groups, vec![Some(1), None]);
+    }
+
+    #[test]
+    fn parses_compressed_layouts() {
+        let source = layout("{ textentrylimited 1 2 100 20 0 7 0 16 }{ foo 1 2 }", &["Name"]);
+        let gump = CompressedGumpLayout::try_from(source).unwrap().parse().unwrap();
+
+        assert_eq!(gump.texts().collect::<Vec<_>>(), vec!["Name"]);
+        assert_eq!(gump.pages[0].elements[1].command, GumpCommand::Unknown {
+            name: "foo".to_string(),
+            args: vec!["1".to_string(), "2".to_string()],
+        });
+    }
+
+    #[test]
+    fn parses_runuo_localised_html() {
+        // As written by RunUO/ServUO's GumpHtmlLocalized.
+        let gump = layout(
+            "{ page 0 }{ resizepic 0 0 5054 300 150 }\
+            { xmfhtmlgumpcolor 10 10 280 20 1011036 0 0 32767 }\
+            { xmfhtmltok 10 40 280 60 1 0 16927 1062435 @#1011036\t5@ }",
+            &[],
+        ).parse().unwrap();
+
+        let elements = &gump.pages[0].elements;
+        assert_eq!(elements[1].command, GumpCommand::HtmlLocalised {
+            text_id: 1011036,
+            args: None,
+            colour: Some(32767),
+            background: false,
+            scrollbar: false,
+            position: IVec2::new(10, 10),
+            size: IVec2::new(280, 20),
+        });
+        assert_eq!(elements[2].command, GumpCommand::HtmlLocalised {
+            text_id: 1062435,
+            args: Some("#1011036\t5".to_string()),
+            colour: Some(16927),
+            background: true,
+            scrollbar: false,
+            position: IVec2::new(10, 40),
+            size: IVec2::new(280, 60),
+        });
+    }
+
+    #[test]
+    fn rejects_bad_text_references() {
+        assert!(layout("{ text 0 0 0 1 }", &["only one"]).parse().is_err());
+        assert!(layout("{ text 0 0 0", &[]).parse().is_err());
+    }
+}
@@ -3,7 +3,7 @@ use std::fmt;
 use std::fmt::Debug;
 use std::io::Write;
 use std::mem::{MaybeUninit, size_of, transmute};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use anyhow::anyhow;
 use byteorder::ByteOrder;
@@ -13,7 +13,7 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
 
-pub use client_version::{ClientFlags, ClientVersion, ExtendedClientVersion};
+pub use client_version::{ClientFlags, ClientVersion, ExtendedClientVersion, VERSION_HIGH_SEAS};
 pub use format::{PacketReadExt, PacketWriteExt};
 pub use login::*;
 pub use map::*;
@@ -23,6 +23,7 @@ pub use entity::*;
 pub use chat::*;
 pub use sound::*;
 pub use ui::*;
+pub use gump_layout::*;
 pub use character::*;
 
 use crate::protocol::compression::{HuffmanVecWriter};
@@ -48,6 +49,8 @@ mod entity;
 
 mod ui;
 
+mod gump_layout;
+
 mod sound;
 
 mod chat;
@@ -61,7 +64,7 @@ pub trait Packet where Self: Sized {
     fn decode(client_version: ClientVersion, from_client: bool, payload: &[u8]) -> anyhow::Result<Self>;
     fn encode(&self, client_version: ClientVersion, to_client: bool, writer: &mut impl Write) -> anyhow::Result<()>;
 
-    fn into_arc(self) -> Arc<AnyPacket> { Arc::from(AnyPacket::from(self)) }
+    fn into_arc(self) -> Arc<SharedPacket> { Arc::new(SharedPacket::from(AnyPacket::from(self))) }
 }
 
 #[derive(Clone)]
@@ -219,6 +222,7 @@ fn packet_registry() -> &'static PacketRegistry {
             PacketRegistration::for_type::<CharacterProfile>(),
             PacketRegistration::for_type::<Skills>(),
             PacketRegistration::for_type::<AttackRequest>(),
+            PacketRegistration::for_type::<ActionRequest>(),
             PacketRegistration::for_type::<SetAttackTarget>(),
             PacketRegistration::for_type::<Swing>(),
             PacketRegistration::for_type::<DamageDealt>(),
@@ -330,6 +334,106 @@ impl<T: Packet> From<T> for AnyPacket {
     }
 }
 
+/// Frame a packet with its kind and, for variable length packets, its length.
+fn frame_packet(client_version: ClientVersion, to_client: bool, packet: &AnyPacket, buffer: &mut Vec<u8>)
+    -> anyhow::Result<()> {
+    frame_with(packet.packet_kind(), packet.fixed_length(client_version), buffer,
+        |buffer| packet.encode(client_version, to_client, buffer))
+}
+
+fn frame_with(packet_kind: u8, fixed_length: Option<usize>, buffer: &mut Vec<u8>,
+    encode: impl FnOnce(&mut Vec<u8>) -> anyhow::Result<()>) -> anyhow::Result<()> {
+    let start = buffer.len();
+    if let Some(length) = fixed_length {
+        buffer.reserve(length);
+        buffer.push(packet_kind);
+        encode(buffer)?;
+        assert_eq!(length, buffer.len() - start, "Fixed length packet wrote wrong size");
+    } else {
+        buffer.extend([packet_kind, 0, 0]);
+        encode(buffer)?;
+        let packet_len = (buffer.len() - start) as u16;
+        Endian::write_u16(&mut buffer[start + 1..start + 3], packet_len);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct EncodedPacket {
+    client_version: ClientVersion,
+    to_client: bool,
+    compressed: bool,
+    bytes: Vec<u8>,
+}
+
+/// A packet which can be sent to many clients at once.
+///
+/// The framed and compressed bytes are cached for each client version the packet is sent to, so
+/// that only encryption needs to happen separately for each connection.
+pub struct SharedPacket {
+    packet: AnyPacket,
+    encoded: Mutex<Vec<EncodedPacket>>,
+}
+
+impl SharedPacket {
+    pub fn packet(&self) -> &AnyPacket { &self.packet }
+
+    pub fn into_packet(self) -> AnyPacket { self.packet }
+
+    /// Append the encoded packet to `buffer`, encoding it only if it hasn't already been
+    /// encoded with the same settings.
+    pub fn write_encoded(&self, client_version: ClientVersion, to_client: bool, compressed: bool,
+        buffer: &mut Vec<u8>) -> anyhow::Result<()> {
+        let mut encoded = self.encoded.lock().unwrap();
+        let existing = encoded.iter().find(|e| e.client_version == client_version
+            && e.to_client == to_client && e.compressed == compressed);
+        if let Some(existing) = existing {
+            buffer.extend_from_slice(&existing.bytes);
+            return Ok(());
+        }
+
+        let mut bytes = Vec::new();
+        frame_packet(client_version, to_client, &self.packet, &mut bytes)?;
+        if compressed {
+            let mut compressed_bytes = Vec::with_capacity(bytes.len());
+            let mut writer = HuffmanVecWriter::new(&mut compressed_bytes);
+            writer.write_all(&bytes)?;
+            writer.finish();
+            bytes = compressed_bytes;
+        }
+
+        buffer.extend_from_slice(&bytes);
+        encoded.push(EncodedPacket {
+            client_version,
+            to_client,
+            compressed,
+            bytes,
+        });
+        Ok(())
+    }
+}
+
+impl std::ops::Deref for SharedPacket {
+    type Target = AnyPacket;
+
+    fn deref(&self) -> &Self::Target { &self.packet }
+}
+
+impl Debug for SharedPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.packet.fmt(f)
+    }
+}
+
+impl From<AnyPacket> for SharedPacket {
+    fn from(packet: AnyPacket) -> Self {
+        Self {
+            packet,
+            encoded: Mutex::new(Vec::new()),
+        }
+    }
+}
+
 pub struct Reader {
     reader: BufReader<OwnedReadHalf>,
     buffer: Vec<u8>,
@@ -433,8 +537,6 @@ impl Writer {
     }
 
     async fn send_raw(&mut self) -> anyhow::Result<()> {
-        self.has_sent = true;
-
         if self.compress {
             std::mem::swap(&mut self.buffer, &mut self.compress_buffer);
             let mut writer = HuffmanVecWriter::new(&mut self.buffer);
@@ -443,6 +545,12 @@ impl Writer {
             self.compress_buffer.clear();
         }
 
+        self.send_compressed().await
+    }
+
+    async fn send_compressed(&mut self) -> anyhow::Result<()> {
+        self.has_sent = true;
+
         if let Some(encryption) = self.encryption.as_mut() {
             if self.to_client {
                 encryption.crypt_server_to_client(&mut self.buffer);
@@ -457,36 +565,23 @@ impl Writer {
     }
 
     pub async fn send<T: Packet>(&mut self, client_version: ClientVersion, packet: &T) -> anyhow::Result<()> {
-        if let Some(length) = T::fixed_length(client_version) {
-            self.buffer.reserve(length);
-            self.buffer.push(T::packet_kind());
-            packet.encode(client_version, self.to_client, &mut self.buffer)?;
-            assert_eq!(length, self.buffer.len(), "Fixed length packet wrote wrong size");
-        } else {
-            self.buffer.extend([T::packet_kind(), 0, 0]);
-            packet.encode(client_version, self.to_client, &mut self.buffer)?;
-            let packet_len = self.buffer.len() as u16;
-            Endian::write_u16(&mut self.buffer[1..3], packet_len);
-        }
-
+        let to_client = self.to_client;
+        frame_with(T::packet_kind(), T::fixed_length(client_version), &mut self.buffer,
+            |buffer| packet.encode(client_version, to_client, buffer))?;
         self.send_raw().await
     }
 
     pub async fn send_any(&mut self, client_version: ClientVersion, packet: &AnyPacket) -> anyhow::Result<()> {
-        if let Some(length) = packet.fixed_length(client_version) {
-            self.buffer.reserve(length);
-            self.buffer.push(packet.packet_kind());
-            packet.encode(client_version, self.to_client, &mut self.buffer)?;
-            assert_eq!(length, self.buffer.len(), "Fixed length packet wrote wrong size");
-        } else {
-            self.buffer.extend([packet.packet_kind(), 0, 0]);
-            packet.encode(client_version, self.to_client, &mut self.buffer)?;
-            let packet_len = self.buffer.len() as u16;
-            Endian::write_u16(&mut self.buffer[1..3], packet_len);
-        }
-
+        frame_packet(client_version, self.to_client, packet, &mut self.buffer)?;
         self.send_raw().await
     }
+
+    /// Send a shared packet, reusing its cached encoding if it has already been sent to another
+    /// client with the same version.
+    pub async fn send_shared(&mut self, client_version: ClientVersion, packet: &SharedPacket) -> anyhow::Result<()> {
+        packet.write_encoded(client_version, self.to_client, self.compress, &mut self.buffer)?;
+        self.send_compressed().await
+    }
 }
 
 pub fn new_io(stream: TcpStream, is_server: bool) -> (Reader, Writer) {
@@ -506,3 +601,36 @@ pub fn new_io(stream: TcpStream, is_server: bool) -> (Reader, Writer) {
         encryption: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_packets_encode_once_per_version() {
+        let old_version = ClientVersion::new(5, 0, 0, 0);
+        let new_version = ClientVersion::new(7, 0, 0, 0);
+        let packet = SetTime { hour: 1, minute: 2, second: 3 }.into_arc();
+
+        let mut expected = Vec::new();
+        frame_packet(new_version, true, &packet, &mut expected).unwrap();
+
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        packet.write_encoded(new_version, true, false, &mut first).unwrap();
+        packet.write_encoded(new_version, true, false, &mut second).unwrap();
+        assert_eq!(first, expected);
+        assert_eq!(second, expected);
+
+        let mut compressed = Vec::new();
+        let mut writer = HuffmanVecWriter::new(&mut compressed);
+        writer.write_all(&expected).unwrap();
+        writer.finish();
+
+        let mut cached = Vec::new();
+        packet.write_encoded(new_version, true, true, &mut cached).unwrap();
+        packet.write_encoded(old_version, true, true, &mut Vec::new()).unwrap();
+        assert_eq!(cached, compressed);
+        assert_eq!(packet.encoded.lock().unwrap().len(), 3);
+    }
+}
@@ -280,13 +280,19 @@ impl Packet for OpenGumpCompressed {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct GumpTextField {
+    pub id: u16,
+    pub text: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct GumpResult {
     pub id: u32,
     pub type_id: u32,
     pub button_id: u32,
     pub on_switches: Vec<u32>,
-    pub text_fields: Vec<String>,
+    pub text_fields: Vec<GumpTextField>,
 }
 
 impl Packet for GumpResult {
@@ -307,7 +313,9 @@ impl Packet for GumpResult {
         let text_field_count = payload.read_u32::<Endian>()? as usize;
         let mut text_fields = Vec::with_capacity(text_field_count);
         for _ in 0..text_field_count {
-            text_fields.push(payload.read_utf16_pascal()?);
+            let id = payload.read_u16::<Endian>()?;
+            let text = payload.read_utf16_pascal()?;
+            text_fields.push(GumpTextField { id, text });
         }
 
         Ok(Self { id, type_id, button_id, on_switches, text_fields })
@@ -322,8 +330,9 @@ impl Packet for GumpResult {
             writer.write_u32::<Endian>(*id)?;
         }
         writer.write_u32::<Endian>(self.text_fields.len() as u32)?;
-        for content in self.text_fields.iter() {
-            writer.write_utf16_pascal(content)?;
+        for field in self.text_fields.iter() {
+            writer.write_u16::<Endian>(field.id)?;
+            writer.write_utf16_pascal(&field.text)?;
         }
         Ok(())
     }
@@ -25,6 +25,7 @@ use crate::data::prefab::{PrefabCollection, PrefabCommandsExt};
 use crate::data::static_data::StaticData;
 use crate::entities::{PrefabInstance, UniqueId};
 use crate::persistence::PersistenceCommandsExt;
+use crate::skills::SkillValues;
 
 pub mod repository;
 
@@ -229,7 +230,7 @@ pub fn handle_delete_character<T: AccountRepository>(
 }
 
 pub fn create_new_character(
-    prefabs: &PrefabCollection, commands: &mut Commands, info: CharacterInfo,
+    prefabs: &PrefabCollection, static_data: &StaticData, commands: &mut Commands, info: CharacterInfo,
 ) -> Entity {
     let race_name = match info.race {
         Race::Human => "human",
@@ -248,6 +249,7 @@ pub fn create_new_character(
         Some(x) => x.clone(),
         None => panic!("missing prefab for {prefab_name}"),
     };
+    let skills = SkillValues::from_initial_skills(&info.skills, &static_data.skills);
 
     commands.spawn_empty()
         .insert_prefab(prefab)
@@ -308,6 +310,7 @@ pub fn create_new_character(
                 direction: Direction::North,
             },
             info.stats,
+            skills,
         ))
         .make_persistent()
         .assign_network_id()
@@ -317,6 +320,7 @@ pub fn create_new_character(
 pub fn handle_spawn_character<T: AccountRepository>(
     runtime: Res<AsyncRuntime>,
     prefabs: Res<PrefabCollection>,
+    static_data: Res<StaticData>,
     mut pending: ResMut<PendingCharacterInfo>,
     pending_list: ResMut<PendingCharacterLists>,
     mut commands: Commands,
@@ -367,7 +371,7 @@ pub fn handle_spawn_character<T: AccountRepository>(
             }
             CharacterToSpawn::NewCharacter(id, info) => {
                 log::info!("Creating new character: {}", &id);
-                let primary_entity = create_new_character(&prefabs, &mut commands, info);
+                let primary_entity = create_new_character(&prefabs, &static_data, &mut commands, info);
                 all_players.insert(id, primary_entity);
                 commands.entity(primary_entity)
                     .insert(UniqueId { id })
@@ -2,7 +2,7 @@ use async_trait::async_trait;
 use bevy_ecs::system::Resource;
 use uuid::Uuid;
 
-use yewoh::protocol::{CreateCharacter, DeleteCharacter, Race};
+use yewoh::protocol::{CreateCharacter, DeleteCharacter, InitialSkill, Race};
 use yewoh_server::world::entity::Stats;
 
 #[derive(Debug, Clone)]
@@ -17,6 +17,7 @@ pub struct CharacterInfo {
     pub shirt_hue: u16,
     pub pants_hue: u16,
     pub stats: Stats,
+    pub skills: [InitialSkill; 4],
 }
 
 impl CharacterInfo {
@@ -46,6 +47,7 @@ impl CharacterInfo {
                 max_stamina: 500,
                 ..Default::default()
             },
+            skills: request.skills,
         }
     }
 }
@@ -1,14 +1,15 @@
 use bevy_ecs::prelude::*;
 use bevy_reflect::prelude::*;
 
-use yewoh::protocol::{CharacterAnimation, CharacterProfile, ContextMenuEntry, DamageDealt, EntityFlags, MoveConfirm, MoveEntityReject, MoveReject, OpenPaperDoll, ProfileResponse, SkillEntry, SkillLock, Skills, SkillsResponse, SkillsResponseKind, Swing, WarMode};
+use yewoh::protocol::{CharacterAnimation, CharacterProfile, ContextMenuEntry, DamageDealt, EntityFlags, MoveConfirm, MoveEntityReject, OpenPaperDoll, ProfileResponse, Swing, WarMode};
 use yewoh::types::FixedString;
 use yewoh_server::world::entity::{AttackTarget, Character, CharacterEquipped, Container, EquippedBy, Flags, Location, Notorious, ParentContainer};
-use yewoh_server::world::events::{ContextMenuEvent, DoubleClickEvent, DropEvent, EquipEvent, MoveEvent, PickUpEvent, ProfileEvent, ReceivedPacketEvent, RequestSkillsEvent, SingleClickEvent};
+use yewoh_server::world::events::{ContextMenuEvent, DoubleClickEvent, DropEvent, EquipEvent, MoveEvent, PickUpEvent, ProfileEvent, ReceivedPacketEvent, SingleClickEvent};
 use yewoh_server::world::input::ContextMenuRequest;
 use yewoh_server::world::map::TileDataResource;
+use yewoh_server::world::map_storage::MapStorage;
 use yewoh_server::world::navigation::try_move_in_direction;
-use yewoh_server::world::net::{ContainerOpenedEvent, NetClient, NetEntity, NetEntityLookup, Possessing};
+use yewoh_server::world::net::{ContainerOpenedEvent, MovementState, NetClient, NetEntity, NetEntityLookup, Possessing, reject_move};
 use yewoh_server::world::spatial::EntitySurfaces;
 
 #[derive(Debug, Clone, Component, Reflect)]
@@ -23,18 +24,24 @@ pub struct Holder {
 
 pub fn handle_move(
     mut events: EventReader<MoveEvent>,
+    map: Res<MapStorage>,
     surfaces: Res<EntitySurfaces>,
     tile_data: Res<TileDataResource>,
-    connection_query: Query<(&NetClient, &Possessing)>,
+    mut connection_query: Query<(&NetClient, &Possessing, &mut MovementState)>,
     mut characters: Query<(&mut Location, &Notorious)>,
 ) {
     for MoveEvent { client_entity: connection, request } in events.iter() {
         let connection = *connection;
-        let (client, owned) = match connection_query.get(connection) {
+        let (client, owned, mut movement) = match connection_query.get_mut(connection) {
             Ok(x) => x,
             _ => continue,
         };
 
+        // Moves validated in the same tick as a reject were already reset on the client.
+        if movement.is_resynchronizing() {
+            continue;
+        }
+
         let primary_entity = owned.entity;
         let (mut map_position, notoriety) = match characters.get_mut(primary_entity) {
             Ok(x) => x,
@@ -44,20 +51,18 @@ pub fn handle_move(
         if map_position.direction != request.direction {
             map_position.direction = request.direction;
         } else {
-            match try_move_in_direction(&surfaces, &tile_data, *map_position, request.direction, Some(primary_entity)) {
+            match try_move_in_direction(&map, &surfaces, &tile_data, *map_position, request.direction, Some(primary_entity)) {
                 Ok(new_position) => {
                     *map_position = new_position;
                 }
                 Err(_) => {
-                    client.send_packet(MoveReject {
-                        sequence: request.sequence,
-                        position: map_position.position,
-                        direction: map_position.direction,
-                    }.into());
+                    reject_move(client, &mut movement, request, &map_position);
+                    continue;
                 }
             }
         }
 
+        movement.confirm_move(request.sequence);
         let notoriety = **notoriety;
         client.send_packet(MoveConfirm {
             sequence: request.sequence,
@@ -380,32 +385,6 @@ pub fn handle_profile_requests(
     }
 }
 
-pub fn handle_skills_requests(
-    clients: Query<&NetClient>,
-    mut requests: EventReader<RequestSkillsEvent>,
-) {
-    for request in requests.iter() {
-        let client_entity = request.client_entity;
-        let client = match clients.get(client_entity) {
-            Ok(x) => x,
-            _ => continue,
-        };
-
-        client.send_packet(Skills::Response(SkillsResponse {
-            kind: SkillsResponseKind::FullWithCaps,
-            skills: vec![
-                SkillEntry {
-                    id: 1,
-                    value: 724,
-                    raw_value: 701,
-                    lock: SkillLock::Up,
-                    cap: 1200,
-                }
-            ],
-        }).into());
-    }
-}
-
 pub fn handle_war_mode(
     mut commands: Commands,
     clients: Query<(&NetClient, &Possessing)>,
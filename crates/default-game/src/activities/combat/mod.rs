@@ -11,14 +11,18 @@ use yewoh::protocol::EquipmentSlot;
 use yewoh_server::world::entity::{AttackTarget, Character, Container, Flags, Graphic, Location, Quantity, Stats};
 use yewoh_server::world::events::AttackRequestedEvent;
 use yewoh_server::world::hierarchy::DespawnRecursiveExt;
-use yewoh_server::world::net::{NetClient, NetEntity, NetEntityAllocator, NetEntityLookup, Possessing};
+use yewoh_server::world::line_of_sight::can_see;
+use yewoh_server::world::map::TileDataResource;
+use yewoh_server::world::map_storage::MapStorage;
+use yewoh_server::world::net::{EntityVisibility, NetClient, NetEntity, NetEntityAllocator, NetEntityLookup, NetOwner, Possessing};
 use yewoh_server::world::ServerSet;
-use yewoh_server::world::spatial::NetClientPositions;
+use yewoh_server::world::spatial::{EntitySurfaces, NetClientPositions};
 
 use crate::activities::{CurrentActivity, progress_current_activity};
 use crate::characters::{Alive, CharacterDied, Corpse, CorpseSpawned, DamageDealt, HitAnimation, MeleeWeapon, Unarmed};
 use crate::characters::animation::AnimationStartedEvent;
 use crate::data::prefab::PrefabAppExt;
+use crate::networking::NetClientExt;
 
 mod prefabs;
 
@@ -64,13 +68,18 @@ pub fn update_weapon_stats(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn attack_current_target(
     mut damage_events: EventWriter<DamageDealt>,
     mut animation_events: EventWriter<AnimationStartedEvent>,
-    mut actors: Query<(Entity, &mut CurrentActivity, &mut AttackTarget, &Location, &MeleeWeapon), With<Alive>>,
+    map: Res<MapStorage>,
+    surfaces: Res<EntitySurfaces>,
+    tile_data: Res<TileDataResource>,
+    clients: Query<&NetClient>,
+    mut actors: Query<(Entity, &mut CurrentActivity, &mut AttackTarget, &Location, &MeleeWeapon, Option<&NetOwner>), With<Alive>>,
     mut targets: Query<(&Location, Option<&HitAnimation>), With<Alive>>,
 ) {
-    for (entity, mut current_activity, current_target, location, weapon) in &mut actors {
+    for (entity, mut current_activity, current_target, location, weapon, owner) in &mut actors {
         if !current_activity.is_idle() {
             continue;
         }
@@ -84,6 +93,15 @@ pub fn attack_current_target(
             continue;
         }
 
+        // Wait for the next swing before trying again, so the message isn't repeated every tick.
+        if !can_see(&map, &surfaces, &tile_data, location, target_location) {
+            if let Some(client) = owner.and_then(|owner| clients.get(owner.client_entity).ok()) {
+                client.send_system_message("You can't see that.".into());
+            }
+            *current_activity = CurrentActivity::Melee(Timer::new(weapon.delay, TimerMode::Once));
+            continue;
+        }
+
         animation_events.send(AnimationStartedEvent {
             animation: weapon.swing_animation.clone(),
             entity,
@@ -180,7 +198,8 @@ pub fn send_damage_notices(
     entity_lookup: Res<NetEntityLookup>,
     client_positions: Res<NetClientPositions>,
     mut damage_events: EventReader<DamageDealt>,
-    clients: Query<&NetClient>,
+    visibility: EntityVisibility,
+    clients: Query<(&NetClient, Option<&Possessing>)>,
 ) {
     for event in &mut damage_events {
         let target_id = match entity_lookup.ecs_to_net(event.target) {
@@ -191,18 +210,20 @@ pub fn send_damage_notices(
         let attacker_id = entity_lookup.ecs_to_net(event.source);
 
         for (entity, ..) in client_positions.tree.iter_at_point(event.location.map_id, event.location.position.truncate()) {
-            let client = match clients.get(entity) {
+            let (client, possessing) = match clients.get(entity) {
                 Ok(x) => x,
                 _ => continue,
             };
 
+            let observer = possessing.map(|p| p.entity);
+            if visibility.can_see(observer, event.target) {
+                client.send_packet(protocol::DamageDealt {
+                    target_id,
+                    damage: event.damage,
+                }.into());
+            }
 
-            client.send_packet(protocol::DamageDealt {
-                target_id,
-                damage: event.damage,
-            }.into());
-
-            if let Some(attacker_id) = attacker_id {
+            if let Some(attacker_id) = attacker_id.filter(|_| visibility.can_see(observer, event.source)) {
                 client.send_packet(protocol::Swing {
                     attacker_id,
                     target_id,
@@ -13,6 +13,7 @@ use serde_derive::Deserialize;
 use yewoh::Direction;
 use yewoh_server::world::entity::Location;
 use yewoh_server::world::map::TileDataResource;
+use yewoh_server::world::map_storage::MapStorage;
 use yewoh_server::world::navigation::try_move_in_direction;
 use yewoh_server::world::spatial::EntitySurfaces;
 
@@ -27,7 +28,7 @@ pub struct MoveTimer {
 }
 
 pub fn wander(
-    time: Res<Time>, tile_data: Res<TileDataResource>, surfaces: Res<EntitySurfaces>,
+    time: Res<Time>, tile_data: Res<TileDataResource>, map: Res<MapStorage>, surfaces: Res<EntitySurfaces>,
     mut npcs: Query<(Entity, &mut Location, &mut MoveTimer), With<Wander>>,
 ) {
     let mut rng = thread_rng();
@@ -38,7 +39,7 @@ pub fn wander(
         }
 
         let direction = Direction::from_repr(rng.gen_range(0..8)).unwrap();
-        if let Ok(new_position) = try_move_in_direction(&surfaces, &tile_data, *position, direction, Some(entity)) {
+        if let Ok(new_position) = try_move_in_direction(&map, &surfaces, &tile_data, *position, direction, Some(entity)) {
             *position = new_position;
         }
     }
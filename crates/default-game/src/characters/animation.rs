@@ -4,7 +4,7 @@ use bevy_ecs::query::With;
 use bevy_ecs::system::{Query, Res};
 use yewoh::protocol::{CharacterAnimation, CharacterPredefinedAnimation};
 use yewoh_server::world::entity::Location;
-use yewoh_server::world::net::{NetClient, NetEntityLookup, Synchronized};
+use yewoh_server::world::net::{EntityVisibility, NetClient, NetEntityLookup, Possessing, Synchronized};
 use yewoh_server::world::spatial::NetClientPositions;
 use crate::characters::Animation;
 
@@ -18,7 +18,8 @@ pub struct AnimationStartedEvent {
 pub fn send_animations(
     entity_lookup: Res<NetEntityLookup>,
     client_positions: Res<NetClientPositions>,
-    clients: Query<&NetClient, With<Synchronized>>,
+    visibility: EntityVisibility,
+    clients: Query<(&NetClient, Option<&Possessing>), With<Synchronized>>,
     mut events: EventReader<AnimationStartedEvent>,
 ) {
     for event in &mut events {
@@ -29,7 +30,7 @@ pub fn send_animations(
 
         for (client_entity, ..) in client_positions.tree.iter_at_point(event.location.map_id, event.location.position.truncate()) {
             let client = match clients.get(client_entity) {
-                Ok(x) => x,
+                Ok((client, possessing)) if visibility.can_see(possessing.map(|p| p.entity), event.entity) => client,
                 _ => continue,
             };
 
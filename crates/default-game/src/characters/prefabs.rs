@@ -9,6 +9,7 @@ use crate::activities::CurrentActivity;
 
 use crate::characters::{Alive, Animation, HitAnimation};
 use crate::data::prefab::{FromPrefabTemplate, Prefab, PrefabBundle};
+use crate::notoriety::{Aggressions, Assailants, BaseNotoriety};
 
 #[derive(Clone, Deserialize)]
 pub struct EquipmentPrefab {
@@ -62,6 +63,9 @@ impl PrefabBundle for CharacterPrefab {
                 Flags::default(),
                 Location::default(),
                 Notorious(self.notoriety),
+                BaseNotoriety(self.notoriety),
+                Aggressions::default(),
+                Assailants::default(),
                 Character {
                     body_type: self.body_type,
                     hue: self.hue,
@@ -0,0 +1,206 @@
+use std::path::PathBuf;
+
+use bevy_ecs::prelude::*;
+use clap::Parser;
+use glam::IVec2;
+
+use yewoh::assets::map::{Static as StaticRecord, StaticsWriter};
+use yewoh::assets::mul::MulFormat;
+use yewoh::assets::tiles::TileFlags;
+use yewoh::protocol::TargetType;
+use yewoh_server::async_runtime::AsyncRuntime;
+use yewoh_server::world::entity::{Character, Container, Graphic, Location, Multi};
+use yewoh_server::world::hierarchy::DespawnRecursiveExt;
+use yewoh_server::world::input::{WorldTargetRequest, WorldTargetResponse};
+use yewoh_server::world::map::{Impassable, Static, Surface, TileDataResource};
+use yewoh_server::world::map_storage::MapStorage;
+use yewoh_server::world::net::{MapInfos, NetClient, ViewState};
+use yewoh_server::world::spatial::EntityPositions;
+
+use crate::commands::{TextCommand, TextCommandQueue};
+use crate::hues;
+use crate::networking::NetClientExt;
+
+/// Where rebuilt client data files are written.
+#[derive(Debug, Clone, Resource)]
+pub struct StaticsExport {
+    pub output_path: PathBuf,
+}
+
+#[derive(Parser, Resource)]
+pub struct Freeze {
+    /// Radius around the target location to freeze.
+    #[arg(long, default_value = "0")]
+    range: i32,
+
+    /// Write LegacyMUL.uop containers instead of raw MUL files.
+    #[arg(long, default_value = "false")]
+    uop: bool,
+}
+
+impl TextCommand for Freeze {
+    fn aliases() -> &'static [&'static str] {
+        &["freeze"]
+    }
+}
+
+#[derive(Debug, Clone, Component)]
+pub struct FreezeRequest {
+    range: i32,
+    format: MulFormat,
+}
+
+pub fn start_freeze(
+    mut exec: TextCommandQueue<Freeze>,
+    mut commands: Commands,
+) {
+    for (from, request) in exec.iter() {
+        let format = if request.uop { MulFormat::Uop } else { MulFormat::Mul };
+        commands.spawn((
+            WorldTargetRequest {
+                client_entity: from,
+                target_type: TargetType::Neutral,
+            },
+            FreezeRequest {
+                range: request.range.max(0),
+                format,
+            },
+        ));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn freeze(
+    runtime: Res<AsyncRuntime>,
+    export: Option<Res<StaticsExport>>,
+    map_infos: Res<MapInfos>,
+    map: Res<MapStorage>,
+    tile_data: Res<TileDataResource>,
+    entity_positions: Res<EntityPositions>,
+    clients: Query<(&NetClient, &ViewState)>,
+    completed: Query<(Entity, &FreezeRequest, &WorldTargetRequest, &WorldTargetResponse)>,
+    items: Query<(&Location, &Graphic), (Without<Character>, Without<Static>, Without<Multi>, Without<Container>)>,
+    statics: Query<(&Location, &Graphic), With<Static>>,
+    mut commands: Commands,
+) {
+    for (entity, request, target_request, response) in completed.iter() {
+        commands.entity(entity).despawn();
+
+        let position = match response.position {
+            Some(x) => x,
+            None => continue,
+        };
+
+        let (client, view_state) = match clients.get(target_request.client_entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        let export = match &export {
+            Some(x) => x,
+            None => {
+                client.send_system_message_hue("No statics export path is configured".into(), hues::RED);
+                continue;
+            }
+        };
+
+        let map_id = view_state.map_id();
+        let map_info = match map_infos.maps.get(&map_id) {
+            Some(x) if !x.is_virtual => x,
+            _ => {
+                client.send_system_message_hue(format!("Map {map_id} has no statics"), hues::RED);
+                continue;
+            }
+        };
+
+        let range = IVec2::splat(request.range);
+        let center = position.truncate();
+        let frozen = entity_positions.tree.iter_aabb(map_id, center - range, center + range)
+            .filter_map(|(target, _)| items.get(target).ok().map(|(l, g)| (target, *l, *g)))
+            .filter(|(_, location, _)| location.map_id == map_id)
+            .collect::<Vec<_>>();
+        if frozen.is_empty() {
+            client.send_system_message_hue("No items to freeze".into(), hues::RED);
+            continue;
+        }
+
+        let base_statics = match map.statics(map_id) {
+            Some(x) => x,
+            None => {
+                client.send_system_message_hue(format!("Map {map_id} has no statics"), hues::RED);
+                continue;
+            }
+        };
+
+        // Statics which were frozen earlier are only present as entities until the server
+        // is restarted with the exported data.
+        let entity_statics = statics.iter()
+            .filter(|(location, _)| location.map_id == map_id)
+            .map(|(location, graphic)| (*location, *graphic));
+        let extra_statics = entity_statics.chain(frozen.iter().map(|(_, l, g)| (*l, *g)))
+            .map(|(location, graphic)| StaticRecord {
+                position: location.position,
+                graphic_id: graphic.id,
+                hue: graphic.hue,
+            })
+            .collect::<Vec<_>>();
+
+        for (target, location, graphic) in &frozen {
+            commands.entity(*target).despawn_recursive();
+
+            let mut new_static = commands.spawn((*location, *graphic, Static));
+            if let Some(info) = tile_data.items.get(graphic.id as usize) {
+                if info.flags.contains(TileFlags::SURFACE) {
+                    new_static.insert(Surface);
+                }
+
+                if info.flags.contains(TileFlags::IMPASSABLE) {
+                    new_static.insert(Impassable);
+                }
+            }
+        }
+
+        let count = frozen.len();
+        let client = client.clone();
+        let output_path = export.output_path.clone();
+        let format = request.format;
+        let map_size = map_info.size;
+        runtime.spawn(async move {
+            let result = async {
+                // Reading the base statics touches every block of the map, so keep it off the
+                // async workers.
+                let writer = tokio::task::spawn_blocking(move || {
+                    let mut writer = StaticsWriter::new(map_size.x as usize, map_size.y as usize);
+                    let mut failed = 0;
+                    base_statics.for_each(|record| {
+                        if writer.push(record.clone()).is_err() {
+                            failed += 1;
+                        }
+                    })?;
+                    for record in extra_statics {
+                        if writer.push(record).is_err() {
+                            failed += 1;
+                        }
+                    }
+                    if failed > 0 {
+                        log::warn!("skipped {failed} statics outside of map {map_id}");
+                    }
+                    anyhow::Ok(writer)
+                }).await??;
+
+                tokio::fs::create_dir_all(&output_path).await?;
+                writer.save(&output_path, map_id as usize, format).await
+            }.await;
+
+            match result {
+                Ok(_) => client.send_system_message(
+                    format!("Froze {count} items into statics for map {map_id}")),
+                Err(err) => {
+                    log::warn!("failed to write statics for map {map_id}: {err}");
+                    client.send_system_message_hue(
+                        format!("Failed to write statics for map {map_id}"), hues::RED);
+                }
+            }
+        });
+    }
+}
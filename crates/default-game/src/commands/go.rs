@@ -2,10 +2,13 @@ use bevy_ecs::prelude::*;
 use clap::{Parser, Subcommand};
 use glam::{IVec2, IVec3};
 
-use yewoh::protocol::OpenGump;
+use yewoh::assets::fonts::FontMetrics;
+use yewoh::protocol::GumpLayout;
 use yewoh_server::gump_builder::{GumpBuilder, GumpText};
+use yewoh_server::gump_ui::{ApproximateFont, GumpFont};
 use yewoh_server::world::entity::Location;
-use yewoh_server::world::net::{NetClient, Possessing};
+use yewoh_server::world::gump::{Gump, GumpResponse};
+use yewoh_server::world::net::Possessing;
 
 use crate::commands::{TextCommand, TextCommandQueue};
 
@@ -34,7 +37,18 @@ impl TextCommand for Go {
     }
 }
 
-fn show_go_gump(client: &NetClient) {
+const GO_GUMP_TYPE_ID: u32 = 2;
+
+const PLACES: &[(&str, u8, IVec3)] = &[
+    ("Britain", 1, IVec3::new(1495, 1629, 10)),
+    ("Minoc", 1, IVec3::new(2466, 544, 0)),
+    ("Trinsic", 1, IVec3::new(1828, 2809, 0)),
+];
+
+#[derive(Debug, Clone, Component)]
+pub struct GoGump;
+
+fn go_gump_layout(font: &impl FontMetrics) -> GumpLayout {
     let size = IVec2::new(200, 300);
     let padding = IVec2::new(16, 16);
     let row = 20;
@@ -45,7 +59,7 @@ fn show_go_gump(client: &NetClient) {
         .add_page(0)
         .add_image_sliced(0xdac, IVec2::ZERO, size)
         .add_html(
-            text.intern(format!("<center>Go to Location</center>")),
+            text.intern("<center>Go to Location</center>".to_string()),
             false,
             false,
             padding,
@@ -53,45 +67,25 @@ fn show_go_gump(client: &NetClient) {
         );
 
     let mut y = padding.y + row * 2;
-    layout
-        .add_page(1)
-        .add_button(0x15e1, 0x15e5, 0, 2, false, IVec2::new(padding.x, y))
-        .add_html(
-            text.intern(format!("<center>Place 1</center>")),
-            false,
-            false,
-            IVec2::new(padding.x + 10, y),
-            IVec2::new(size.x - padding.x * 2 - 8, row),
-        );
-    y += row;
-    layout
-        .add_page(2)
-        .add_button(0x15e1, 0x15e5, 0, 1, false, IVec2::new(padding.x, y))
-        .add_html(
-            text.intern(format!("<center>Place 2</center>")),
-            false,
-            false,
-            IVec2::new(padding.x + 10, y),
-            IVec2::new(size.x - padding.x * 2 - 8, row),
-        );
-    //y += row;
-
-    let layout = layout.into_layout(text);
-    client.send_packet(OpenGump {
-        id: 3,
-        type_id: 2,
-        position: IVec2::new(10, 10),
-        layout,
-    }.into());
+    for (index, (name, _, _)) in PLACES.iter().enumerate() {
+        layout
+            .add_button(0x15e1, 0x15e5, index as u32 + 1, 0, true, IVec2::new(padding.x, y))
+            .add_label(font, &mut text, name.to_string(), 0, IVec2::new(padding.x + 20, y));
+        y += row;
+    }
+
+    layout.into_layout(text)
 }
 
 pub fn go(
-    clients: Query<(&NetClient, &Possessing)>,
+    mut commands: Commands,
+    font: Option<Res<GumpFont>>,
+    clients: Query<&Possessing>,
     mut characters: Query<&mut Location>,
     mut exec: TextCommandQueue<Go>,
 ) {
     for (from, args) in exec.iter() {
-        let (client, owned) = match clients.get(from) {
+        let owned = match clients.get(from) {
             Ok(x) => x,
             _ => continue,
         };
@@ -103,7 +97,18 @@ pub fn go(
 
         match args.command {
             None => {
-                show_go_gump(client);
+                commands.spawn((
+                    GoGump,
+                    Gump {
+                        client_entity: from,
+                        type_id: GO_GUMP_TYPE_ID,
+                        position: IVec2::new(10, 10),
+                        layout: match &font {
+                            Some(font) => go_gump_layout(&font.0),
+                            None => go_gump_layout(&ApproximateFont),
+                        },
+                    },
+                ));
             }
             Some(Command::Coordinates(coords)) => {
                 position.map_id = coords.map;
@@ -112,3 +117,28 @@ pub fn go(
         }
     }
 }
+
+pub fn handle_go_gump(
+    clients: Query<&Possessing>,
+    mut characters: Query<&mut Location>,
+    gumps: Query<(&Gump, &GumpResponse), (With<GoGump>, Added<GumpResponse>)>,
+) {
+    for (gump, response) in &gumps {
+        let place = (response.button_id as usize).checked_sub(1)
+            .and_then(|index| PLACES.get(index));
+        let (_, map_id, position) = match place {
+            Some(x) => *x,
+            None => continue,
+        };
+
+        let mut location = match clients.get(gump.client_entity)
+            .ok()
+            .and_then(|owned| characters.get_mut(owned.entity).ok()) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        location.map_id = map_id;
+        location.position = position;
+    }
+}
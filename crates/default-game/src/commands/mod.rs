@@ -1,5 +1,6 @@
 use bevy_app::{App, CoreSet, Plugin};
 use bevy_ecs::schedule::IntoSystemConfigs;
+use yewoh_server::gump_ui::ReactiveGumpRegistrationExt;
 pub use registration::{
     TextCommands,
     TextCommand,
@@ -18,6 +19,10 @@ pub mod go;
 
 pub mod spawn;
 
+pub mod freeze;
+
+pub mod visibility;
+
 pub struct CommandsPlugin;
 
 impl Plugin for CommandsPlugin {
@@ -31,15 +36,24 @@ impl Plugin for CommandsPlugin {
             .add_text_command::<test::FryPan>()
             .add_text_command::<test::TestGump>()
             .add_text_command::<spawn::Spawn>()
+            .add_text_command::<freeze::Freeze>()
+            .add_text_command::<visibility::Invisible>()
+            .add_text_command::<visibility::SeeAll>()
+            .add_reactive_gump::<test::TestGumpState>()
             .add_systems((
                 info::info,
                 info::start_info,
                 go::go,
+                go::handle_go_gump,
                 test::echo,
                 test::frypan,
                 test::test_gump,
                 spawn::start_spawn,
                 spawn::spawn,
+                freeze::start_freeze,
+                freeze::freeze,
+                visibility::invisible,
+                visibility::see_all,
             ).in_base_set(CoreSet::Update));
     }
 }
@@ -1,11 +1,11 @@
 use bevy_ecs::prelude::*;
 use clap::Parser;
-use glam::IVec2;
 
 use yewoh::Direction;
-use yewoh::protocol::{EntityFlags, GumpLayout, OpenGump};
+use yewoh::protocol::EntityFlags;
 use yewoh_server::world::entity::{Flags, Graphic, Location};
-use yewoh_server::world::net::{NetClient, NetEntity, NetEntityAllocator, Possessing};
+use yewoh_server::gump_ui::{ReactiveGump, Ui};
+use yewoh_server::world::net::{NetEntity, NetEntityAllocator, Possessing};
 
 use crate::commands::{TextCommand, TextCommandQueue};
 
@@ -72,21 +72,49 @@ impl TextCommand for TestGump {
     }
 }
 
+#[derive(Debug, Clone, Default, Component)]
+pub struct TestGumpState {
+    pub clicks: u32,
+    pub name: String,
+    pub page: usize,
+}
+
+fn test_gump_view(state: &TestGumpState, ui: &mut Ui<TestGumpState>) {
+    ui.panel(5054, 16, |ui| {
+        ui.label(format!("Hello, {}!", if state.name.is_empty() { "world" } else { &state.name }));
+        ui.tabs(&["Counter", "List"], |ui, tab| match tab {
+            0 => {
+                ui.label(format!("Clicked {} times", state.clicks));
+                let name = ui.labelled_text_entry("Name", 60, 160, state.name.clone());
+                ui.row(|ui| {
+                    ui.button("Click", move |action| {
+                        action.state.clicks += 1;
+                        if let Some(name) = action.text(name) {
+                            action.state.name = name.to_string();
+                        }
+                    });
+                    ui.button("Close", |action| action.close());
+                });
+            }
+            _ => {
+                ui.paginated_list(
+                    25,
+                    5,
+                    state.page,
+                    |state, page| state.page = page,
+                    |ui, index| { ui.label(format!("Item {}", index + 1)); },
+                );
+            }
+        });
+    });
+}
+
 pub fn test_gump(
     mut exec: TextCommandQueue<TestGump>,
-    clients: Query<&NetClient>,
+    mut commands: Commands,
 ) {
     for (from, _) in exec.iter() {
-        if let Ok(client) = clients.get(from) {
-            client.send_packet(OpenGump {
-                id: 1,
-                type_id: 2,
-                position: IVec2::new(50, 50),
-                layout: GumpLayout {
-                    layout: "{ page 0 }{ resizepic 0 0 5054 420 440 }{ text 0 0 120 0 }".to_string(),
-                    text: vec!["Hello, world!".into()],
-                },
-            }.into());
-        }
+        let entity = commands.spawn(TestGumpState::default()).id();
+        commands.entity(entity).insert(ReactiveGump::new(from, entity, 1, test_gump_view));
     }
 }
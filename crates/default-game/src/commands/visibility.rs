@@ -0,0 +1,84 @@
+use bevy_ecs::prelude::*;
+use clap::Parser;
+
+use yewoh_server::world::entity::SeesEverything;
+use yewoh_server::world::net::{NetClient, Possessing};
+
+use crate::commands::{TextCommand, TextCommandQueue};
+use crate::networking::NetClientExt;
+
+/// Toggle staff invisibility: hide your character from everyone who can't see everything.
+#[derive(Parser, Resource)]
+pub struct Invisible;
+
+impl TextCommand for Invisible {
+    fn aliases() -> &'static [&'static str] {
+        &["invisible", "invis"]
+    }
+}
+
+/// Toggle seeing everything, including invisible and hidden entities.
+#[derive(Parser, Resource)]
+pub struct SeeAll;
+
+impl TextCommand for SeeAll {
+    fn aliases() -> &'static [&'static str] {
+        &["seeall"]
+    }
+}
+
+/// Marks a character which is invisible to everyone except staff.
+#[derive(Debug, Clone, Copy, Default, Component)]
+pub struct StaffInvisible;
+
+pub fn invisible(
+    mut exec: TextCommandQueue<Invisible>,
+    clients: Query<(&NetClient, &Possessing)>,
+    characters: Query<Option<&StaffInvisible>>,
+    mut commands: Commands,
+) {
+    for (from, _) in exec.iter() {
+        let (client, possessing) = match clients.get(from) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        match characters.get(possessing.entity) {
+            Ok(Some(_)) => {
+                commands.entity(possessing.entity).remove::<StaffInvisible>();
+                client.send_system_message("You are now visible.".into());
+            }
+            Ok(None) => {
+                commands.entity(possessing.entity).insert(StaffInvisible);
+                client.send_system_message("You are now invisible.".into());
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn see_all(
+    mut exec: TextCommandQueue<SeeAll>,
+    clients: Query<(&NetClient, &Possessing)>,
+    characters: Query<Option<&SeesEverything>>,
+    mut commands: Commands,
+) {
+    for (from, _) in exec.iter() {
+        let (client, possessing) = match clients.get(from) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        match characters.get(possessing.entity) {
+            Ok(Some(_)) => {
+                commands.entity(possessing.entity).remove::<SeesEverything>();
+                client.send_system_message("You no longer see everything.".into());
+            }
+            Ok(None) => {
+                commands.entity(possessing.entity).insert(SeesEverything);
+                client.send_system_message("You now see everything.".into());
+            }
+            _ => {}
+        }
+    }
+}
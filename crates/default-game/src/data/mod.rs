@@ -3,3 +3,4 @@ pub mod maps;
 pub mod skills;
 pub mod static_data;
 pub mod prefab;
+pub mod regions;
@@ -0,0 +1,44 @@
+use bitflags::bitflags;
+use glam::IVec2;
+use serde::{Deserialize, Serialize};
+
+bitflags! {
+    /// Rules which apply to everything inside a region.
+    #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+    pub struct RegionFlags : u32 {
+        const TOWN = 1 << 0;
+        const DUNGEON = 1 << 1;
+        const GUARDED = 1 << 2;
+        const NO_PVP = 1 << 3;
+        const NO_RECALL = 1 << 4;
+        const NO_GATE = 1 << 5;
+        const NO_MARK = 1 << 6;
+        const NO_HOUSING = 1 << 7;
+    }
+}
+
+/// A rectangle covered by a region. `max` is exclusive.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RegionArea {
+    pub min: IVec2,
+    pub max: IVec2,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Region {
+    pub name: String,
+    pub map_id: u8,
+    /// Where regions overlap, the one with the highest priority applies.
+    pub priority: i32,
+    pub areas: Vec<RegionArea>,
+    pub flags: RegionFlags,
+    pub music: Option<u16>,
+    /// A light level to use in place of the time of day.
+    pub light_level: Option<u8>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Regions {
+    pub regions: Vec<Region>,
+}
@@ -5,15 +5,15 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Skill {
-    name: String,
-    noun: String,
-    str_scale: f32,
-    dex_scale: f32,
-    int_scale: f32,
-    str_gain: f32,
-    dex_gain: f32,
-    int_gain: f32,
-    gain_scale: f32,
+    pub name: String,
+    pub noun: String,
+    pub str_scale: f32,
+    pub dex_scale: f32,
+    pub int_scale: f32,
+    pub str_gain: f32,
+    pub dex_gain: f32,
+    pub int_gain: f32,
+    pub gain_scale: f32,
 }
 
 impl Default for Skill {
@@ -5,7 +5,7 @@ use bevy_ecs::prelude::*;
 use yewoh_server::world::ServerSet;
 
 use crate::accounts::AccountsPlugin;
-use crate::actions::{handle_context_menu, handle_double_click, handle_drop, handle_equip, handle_move, handle_pick_up, handle_profile_requests, handle_single_click, handle_skills_requests, handle_war_mode};
+use crate::actions::{handle_context_menu, handle_double_click, handle_drop, handle_equip, handle_move, handle_pick_up, handle_profile_requests, handle_single_click, handle_war_mode};
 use crate::activities::ActivitiesPlugin;
 use crate::ai::AiPlugin;
 use crate::characters::CharactersPlugin;
@@ -14,7 +14,10 @@ use crate::commands::CommandsPlugin;
 use crate::data::prefab::PrefabPlugin;
 use crate::entities::EntitiesPlugin;
 use crate::items::ItemsPlugin;
+use crate::notoriety::NotorietyPlugin;
 use crate::persistence::PersistencePlugin;
+use crate::regions::RegionsPlugin;
+use crate::skills::SkillsPlugin;
 use crate::spawners::SpawnersPlugin;
 use crate::time::send_time;
 
@@ -48,6 +51,12 @@ pub mod hues;
 
 pub mod entities;
 
+pub mod regions;
+
+pub mod notoriety;
+
+pub mod skills;
+
 #[derive(Default)]
 pub struct DefaultGamePlugins;
 
@@ -65,6 +74,9 @@ impl PluginGroup for DefaultGamePlugins {
             .add(ItemsPlugin)
             .add(SpawnersPlugin)
             .add(AiPlugin)
+            .add(RegionsPlugin)
+            .add(NotorietyPlugin)
+            .add(SkillsPlugin)
     }
 }
 
@@ -85,7 +97,6 @@ impl Plugin for DefaultGamePlugin {
                 handle_incoming_chat,
                 handle_context_menu,
                 handle_profile_requests,
-                handle_skills_requests,
             ).in_base_set(CoreSet::Update))
             .add_system(send_time.in_set(ServerSet::Send));
     }
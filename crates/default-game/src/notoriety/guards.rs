@@ -0,0 +1,73 @@
+use bevy_ecs::prelude::*;
+
+use yewoh::Notoriety;
+use yewoh_server::world::entity::{Location, Notorious, Stats};
+use yewoh_server::world::events::ChatRequestEvent;
+use yewoh_server::world::net::{NetClient, Possessing};
+use yewoh_server::world::spatial::{EntityPositions, view_aabb};
+
+use crate::characters::{Alive, CharacterDied};
+use crate::data::regions::RegionFlags;
+use crate::networking::NetClientExt;
+use crate::regions::RegionQuery;
+
+/// How far away guards will find criminals when called.
+pub const GUARD_RANGE: i32 = 14;
+
+fn is_guard_call(text: &str) -> bool {
+    text.to_lowercase().contains("guards")
+}
+
+/// Kill every criminal and murderer near a character who calls for the guards in a guarded
+/// region.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_guard_calls(
+    mut commands: Commands,
+    mut events: EventReader<ChatRequestEvent>,
+    mut died_events: EventWriter<CharacterDied>,
+    regions: RegionQuery,
+    positions: Res<EntityPositions>,
+    clients: Query<(&NetClient, &Possessing)>,
+    callers: Query<&Location>,
+    mut targets: Query<(&Location, &Notorious, &mut Stats), With<Alive>>,
+) {
+    for event in events.iter() {
+        if !is_guard_call(&event.request.text) {
+            continue;
+        }
+
+        let (client, possessing) = match clients.get(event.client_entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        let location = match callers.get(possessing.entity) {
+            Ok(x) => *x,
+            _ => continue,
+        };
+
+        if !regions.flags_at(&location).contains(RegionFlags::GUARDED) {
+            client.send_system_message("There are no guards here to answer your call.".into());
+            continue;
+        }
+
+        let (min, max) = view_aabb(location.position.truncate(), GUARD_RANGE);
+        for (entity, ..) in positions.tree.iter_aabb(location.map_id, min, max) {
+            let (target_location, notorious, mut stats) = match targets.get_mut(entity) {
+                Ok(x) => x,
+                _ => continue,
+            };
+
+            if !matches!(notorious.0, Notoriety::Criminal | Notoriety::Murderer)
+                || !regions.flags_at(target_location).contains(RegionFlags::GUARDED) {
+                continue;
+            }
+
+            stats.hp = 0;
+            commands.entity(entity).remove::<Alive>();
+            died_events.send(CharacterDied {
+                character: entity,
+            });
+        }
+    }
+}
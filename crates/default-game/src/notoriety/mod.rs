@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use bevy_time::{Time, Timer, TimerMode};
+use serde::{Deserialize, Serialize};
+
+use yewoh::Notoriety;
+use yewoh_server::world::entity::{AttackTarget, Character, EquippedBy, Notorious, NotorietyOverrides, ParentContainer};
+use yewoh_server::world::events::PickUpEvent;
+use yewoh_server::world::net::{NetOwner, Possessing};
+
+use crate::activities::combat::{apply_damage, attack_current_target};
+use crate::characters::{CharacterDied, DamageDealt};
+use crate::notoriety::guards::handle_guard_calls;
+use crate::persistence::SerializationSetupExt;
+
+pub mod guards;
+
+mod persistence;
+
+/// How long a character stays criminal after committing a crime.
+pub const CRIMINAL_DURATION: Duration = Duration::from_secs(2 * 60);
+
+/// How long a character can be attacked back by its victims.
+pub const AGGRESSION_DURATION: Duration = Duration::from_secs(2 * 60);
+
+/// The number of long term murders after which a character is a murderer.
+pub const MURDERER_THRESHOLD: u32 = 5;
+
+pub const SHORT_TERM_MURDER_DECAY: Duration = Duration::from_secs(8 * 60 * 60);
+
+pub const LONG_TERM_MURDER_DECAY: Duration = Duration::from_secs(40 * 60 * 60);
+
+/// The notoriety of a character which hasn't committed any crimes.
+///
+/// Characters with this component have their [`Notorious`] calculated from it. Only
+/// innocent characters can become criminals or murderers, and only crimes against
+/// player characters count.
+#[derive(Debug, Clone, Copy, Default, Component)]
+pub struct BaseNotoriety(pub Notoriety);
+
+/// Marks a character as a criminal until the timer runs out.
+#[derive(Debug, Clone, Component)]
+pub struct Criminal {
+    pub timer: Timer,
+}
+
+impl Default for Criminal {
+    fn default() -> Self {
+        Self { timer: Timer::new(CRIMINAL_DURATION, TimerMode::Once) }
+    }
+}
+
+/// The characters this character has attacked recently. Each of them sees this character
+/// as a criminal, so that they can defend themselves.
+#[derive(Debug, Clone, Default, Component)]
+pub struct Aggressions {
+    pub victims: HashMap<Entity, Timer>,
+}
+
+/// The characters which have recently attacked this character while it was innocent.
+///
+/// If this character dies, each of them is counted as a murderer.
+#[derive(Debug, Clone, Default, Component)]
+pub struct Assailants {
+    pub characters: HashMap<Entity, Timer>,
+}
+
+#[derive(Debug, Clone, Default, Component, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MurderCounts {
+    pub short_term: u32,
+    pub long_term: u32,
+    #[serde(with = "humantime_serde")]
+    pub short_term_elapsed: Duration,
+    #[serde(with = "humantime_serde")]
+    pub long_term_elapsed: Duration,
+}
+
+impl MurderCounts {
+    pub fn is_murderer(&self) -> bool {
+        self.long_term >= MURDERER_THRESHOLD
+    }
+
+    pub fn add_murder(&mut self) {
+        self.short_term += 1;
+        self.long_term += 1;
+    }
+
+    /// Advance the decay timers, forgiving one murder of each kind each time its decay
+    /// period passes.
+    pub fn tick(&mut self, delta: Duration) {
+        if self.short_term > 0 {
+            self.short_term_elapsed += delta;
+            if self.short_term_elapsed >= SHORT_TERM_MURDER_DECAY {
+                self.short_term -= 1;
+                self.short_term_elapsed = Duration::ZERO;
+            }
+        }
+
+        if self.long_term > 0 {
+            self.long_term_elapsed += delta;
+            if self.long_term_elapsed >= LONG_TERM_MURDER_DECAY {
+                self.long_term -= 1;
+                self.long_term_elapsed = Duration::ZERO;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CrimeKind {
+    Attack,
+    Steal,
+}
+
+#[derive(Debug, Clone)]
+pub struct CrimeEvent {
+    pub criminal: Entity,
+    pub victim: Entity,
+    pub kind: CrimeKind,
+}
+
+/// The notoriety `observer` should see for a character.
+pub fn notoriety_towards(observer: Entity, notoriety: Notoriety, aggressions: Option<&Aggressions>) -> Notoriety {
+    match aggressions {
+        Some(aggressions) if notoriety == Notoriety::Innocent && aggressions.victims.contains_key(&observer) =>
+            Notoriety::Criminal,
+        _ => notoriety,
+    }
+}
+
+fn tick_timers(timers: &mut HashMap<Entity, Timer>, delta: Duration) -> bool {
+    let len = timers.len();
+    timers.retain(|_, timer| !timer.tick(delta).finished());
+    timers.len() != len
+}
+
+pub fn add_murder_counts(
+    mut commands: Commands,
+    characters: Query<Entity, (With<NetOwner>, With<BaseNotoriety>, Without<MurderCounts>)>,
+) {
+    for entity in &characters {
+        commands.entity(entity).insert(MurderCounts::default());
+    }
+}
+
+pub fn detect_attack_crimes(
+    mut crime_events: EventWriter<CrimeEvent>,
+    mut characters: ParamSet<(
+        Query<(Entity, &AttackTarget), (Changed<AttackTarget>, With<MurderCounts>)>,
+        Query<(&Notorious, Option<&Aggressions>), With<NetOwner>>,
+        Query<&mut Aggressions>,
+    )>,
+) {
+    let attacks = characters.p0().iter()
+        .map(|(entity, target)| (entity, target.target))
+        .collect::<Vec<_>>();
+
+    for (entity, target) in attacks {
+        let targets = characters.p1();
+        if let Ok((notorious, target_aggressions)) = targets.get(target) {
+            if notoriety_towards(entity, notorious.0, target_aggressions) == Notoriety::Innocent {
+                crime_events.send(CrimeEvent {
+                    criminal: entity,
+                    victim: target,
+                    kind: CrimeKind::Attack,
+                });
+            }
+        }
+
+        if let Ok(mut aggressions) = characters.p2().get_mut(entity) {
+            aggressions.victims.insert(target, Timer::new(AGGRESSION_DURATION, TimerMode::Once));
+        }
+    }
+}
+
+pub fn detect_theft(
+    mut events: EventReader<PickUpEvent>,
+    mut crime_events: EventWriter<CrimeEvent>,
+    clients: Query<&Possessing>,
+    characters: Query<(&Notorious, Option<&Aggressions>), (With<Character>, With<NetOwner>)>,
+    contained: Query<&ParentContainer>,
+    equipped: Query<&EquippedBy>,
+    thieves: Query<(), With<MurderCounts>>,
+) {
+    for event in events.iter() {
+        let thief = match clients.get(event.client_entity) {
+            Ok(x) => x.entity,
+            _ => continue,
+        };
+
+        if !thieves.contains(thief) {
+            continue;
+        }
+
+        // Find the character carrying the item, if any.
+        let mut owner = event.target;
+        while let Ok(parent) = contained.get(owner) {
+            owner = parent.parent;
+        }
+        if let Ok(equipped) = equipped.get(owner) {
+            owner = equipped.parent;
+        }
+
+        if owner == thief {
+            continue;
+        }
+
+        let (notorious, aggressions) = match characters.get(owner) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        if notoriety_towards(thief, notorious.0, aggressions) == Notoriety::Innocent {
+            crime_events.send(CrimeEvent {
+                criminal: thief,
+                victim: owner,
+                kind: CrimeKind::Steal,
+            });
+        }
+    }
+}
+
+pub fn apply_crimes(
+    mut commands: Commands,
+    mut crime_events: EventReader<CrimeEvent>,
+    mut criminals: Query<Option<&mut Criminal>>,
+) {
+    for event in crime_events.iter() {
+        match criminals.get_mut(event.criminal) {
+            Ok(Some(mut criminal)) => criminal.timer.reset(),
+            Ok(None) => {
+                commands.entity(event.criminal).insert(Criminal::default());
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn record_assailants(
+    mut damage_events: EventReader<DamageDealt>,
+    mut victims: Query<(&Notorious, Option<&Aggressions>, &mut Assailants), With<NetOwner>>,
+    attackers: Query<(), With<MurderCounts>>,
+) {
+    for event in damage_events.iter() {
+        if event.source == event.target || !attackers.contains(event.source) {
+            continue;
+        }
+
+        let (notorious, aggressions, mut assailants) = match victims.get_mut(event.target) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        if notoriety_towards(event.source, notorious.0, aggressions) == Notoriety::Innocent {
+            assailants.characters.insert(event.source, Timer::new(AGGRESSION_DURATION, TimerMode::Once));
+        }
+    }
+}
+
+pub fn report_murders(
+    mut died_events: EventReader<CharacterDied>,
+    victims: Query<&Assailants, With<NetOwner>>,
+    mut murderers: Query<&mut MurderCounts>,
+) {
+    for event in died_events.iter() {
+        let assailants = match victims.get(event.character) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        for assailant in assailants.characters.keys() {
+            if let Ok(mut counts) = murderers.get_mut(*assailant) {
+                counts.add_murder();
+            }
+        }
+    }
+}
+
+pub fn update_notoriety_timers(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut criminals: Query<(Entity, &mut Criminal)>,
+    mut aggressions: Query<&mut Aggressions>,
+    mut assailants: Query<&mut Assailants>,
+    mut murder_counts: Query<&mut MurderCounts>,
+) {
+    let delta = time.delta();
+
+    for (entity, mut criminal) in &mut criminals {
+        if criminal.timer.tick(delta).finished() {
+            commands.entity(entity).remove::<Criminal>();
+        }
+    }
+
+    for mut aggressions in &mut aggressions {
+        if tick_timers(&mut aggressions.bypass_change_detection().victims, delta) {
+            aggressions.set_changed();
+        }
+    }
+
+    for mut assailants in &mut assailants {
+        tick_timers(&mut assailants.characters, delta);
+    }
+
+    for mut counts in &mut murder_counts {
+        if counts.short_term > 0 || counts.long_term > 0 {
+            counts.tick(delta);
+        }
+    }
+}
+
+pub fn update_notoriety(
+    mut characters: Query<(&BaseNotoriety, &mut Notorious, Option<&Criminal>, Option<&MurderCounts>)>,
+) {
+    for (base, mut notorious, criminal, murder_counts) in &mut characters {
+        let notoriety = if base.0 != Notoriety::Innocent {
+            base.0
+        } else if murder_counts.is_some_and(|c| c.is_murderer()) {
+            Notoriety::Murderer
+        } else if criminal.is_some() {
+            Notoriety::Criminal
+        } else {
+            Notoriety::Innocent
+        };
+
+        if notorious.0 != notoriety {
+            notorious.0 = notoriety;
+        }
+    }
+}
+
+pub fn update_notoriety_overrides(
+    mut commands: Commands,
+    mut characters: Query<
+        (Entity, &Notorious, &Aggressions, Option<&mut NotorietyOverrides>),
+        Or<(Changed<Notorious>, Changed<Aggressions>)>,
+    >,
+) {
+    for (entity, notorious, aggressions, overrides) in &mut characters {
+        let new_overrides = NotorietyOverrides {
+            overrides: aggressions.victims.keys()
+                .map(|victim| (*victim, notoriety_towards(*victim, notorious.0, Some(aggressions))))
+                .filter(|(_, notoriety)| *notoriety != notorious.0)
+                .collect(),
+        };
+
+        match overrides {
+            Some(mut overrides) => {
+                if *overrides != new_overrides {
+                    *overrides = new_overrides;
+                }
+            }
+            None => {
+                commands.entity(entity).insert(new_overrides);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct NotorietyPlugin;
+
+impl Plugin for NotorietyPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<CrimeEvent>()
+            .add_systems((
+                add_murder_counts,
+                detect_attack_crimes,
+                detect_theft,
+                apply_crimes.after(detect_attack_crimes).after(detect_theft),
+                record_assailants.after(attack_current_target).before(apply_damage),
+                report_murders.after(apply_damage).after(record_assailants),
+                update_notoriety_timers,
+                update_notoriety.after(apply_crimes).after(update_notoriety_timers),
+                update_notoriety_overrides.after(update_notoriety),
+                handle_guard_calls,
+            ))
+            .register_serializer::<persistence::MurderCountsSerializer>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::event::Events;
+    use bevy_ecs::schedule::Schedule;
+
+    use super::*;
+
+    #[test]
+    fn victims_see_aggressors_as_criminal() {
+        let victim = Entity::from_raw(1);
+        let bystander = Entity::from_raw(2);
+        let aggressions = Aggressions {
+            victims: [(victim, Timer::new(AGGRESSION_DURATION, TimerMode::Once))].into_iter().collect(),
+        };
+
+        assert_eq!(notoriety_towards(victim, Notoriety::Innocent, Some(&aggressions)), Notoriety::Criminal);
+        assert_eq!(notoriety_towards(bystander, Notoriety::Innocent, Some(&aggressions)), Notoriety::Innocent);
+        assert_eq!(notoriety_towards(victim, Notoriety::Murderer, Some(&aggressions)), Notoriety::Murderer);
+        assert_eq!(notoriety_towards(victim, Notoriety::Innocent, None), Notoriety::Innocent);
+    }
+
+    #[test]
+    fn murders_decay() {
+        let mut counts = MurderCounts::default();
+        for _ in 0..MURDERER_THRESHOLD {
+            counts.add_murder();
+        }
+        assert!(counts.is_murderer());
+
+        counts.tick(SHORT_TERM_MURDER_DECAY);
+        assert_eq!(counts.short_term, MURDERER_THRESHOLD - 1);
+        assert_eq!(counts.long_term, MURDERER_THRESHOLD);
+
+        counts.tick(LONG_TERM_MURDER_DECAY - SHORT_TERM_MURDER_DECAY);
+        assert_eq!(counts.long_term, MURDERER_THRESHOLD - 1);
+        assert!(!counts.is_murderer());
+    }
+
+    fn attack_crimes(victim_notoriety: Notoriety, victim_is_player: bool) -> Vec<CrimeEvent> {
+        let mut world = World::new();
+        world.init_resource::<Events<CrimeEvent>>();
+
+        let victim = world.spawn(Notorious(victim_notoriety)).id();
+        if victim_is_player {
+            world.entity_mut(victim).insert(NetOwner { client_entity: Entity::from_raw(100) });
+        }
+        let attacker = world.spawn((
+            AttackTarget { target: victim },
+            Aggressions::default(),
+            MurderCounts::default(),
+        )).id();
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(detect_attack_crimes);
+        schedule.run(&mut world);
+
+        assert!(world.get::<Aggressions>(attacker).unwrap().victims.contains_key(&victim));
+        let events = world.resource::<Events<CrimeEvent>>();
+        events.iter_current_update_events().cloned().collect()
+    }
+
+    #[test]
+    fn attacking_innocent_players_is_a_crime() {
+        let crimes = attack_crimes(Notoriety::Innocent, true);
+        assert_eq!(crimes.len(), 1);
+        assert_eq!(crimes[0].kind, CrimeKind::Attack);
+    }
+
+    #[test]
+    fn attacking_creatures_or_criminals_is_not_a_crime() {
+        assert!(attack_crimes(Notoriety::Innocent, false).is_empty());
+        assert!(attack_crimes(Notoriety::Criminal, true).is_empty());
+    }
+}
@@ -0,0 +1,42 @@
+use bevy_ecs::entity::Entity;
+use bevy_ecs::prelude::{FromWorld, World};
+use bevy_ecs::query::{With, WorldQuery};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::entities::Persistent;
+use crate::notoriety::MurderCounts;
+use crate::persistence::{BundleSerializer, DeserializeContext, SerializeContext};
+
+pub struct MurderCountsSerializer;
+
+impl FromWorld for MurderCountsSerializer {
+    fn from_world(_world: &mut World) -> Self {
+        Self
+    }
+}
+
+impl BundleSerializer for MurderCountsSerializer {
+    type Query = &'static MurderCounts;
+    type Filter = With<Persistent>;
+    type Bundle = MurderCounts;
+
+    fn id() -> &'static str {
+        "MurderCounts"
+    }
+
+    fn extract(item: <Self::Query as WorldQuery>::Item<'_>) -> Self::Bundle {
+        item.clone()
+    }
+
+    fn serialize<S: Serializer>(_ctx: &SerializeContext, s: S, bundle: &Self::Bundle) -> Result<S::Ok, S::Error> {
+        bundle.serialize(s)
+    }
+
+    fn deserialize<'de, D: Deserializer<'de>>(ctx: &mut DeserializeContext, d: D, entity: Entity) -> Result<(), D::Error> {
+        let counts = MurderCounts::deserialize(d)?;
+        ctx.world_mut()
+            .entity_mut(entity)
+            .insert(counts);
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use bevy_app::{App, CoreSet, Plugin};
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use glam::IVec2;
+
+use yewoh::protocol::PlayMusic;
+use yewoh_server::world::entity::{Character, Location};
+use yewoh_server::world::net::{NetClient, NetOwner, Possessing, Synchronizing};
+use yewoh_server::world::ServerSet;
+use yewoh_server::world::spatial::SpatialEntityTree;
+
+use crate::data::regions::RegionFlags;
+use crate::data::static_data::StaticData;
+
+/// A named area of the world, spawned from `regions.yaml`.
+#[derive(Debug, Clone, Component)]
+pub struct Region {
+    pub name: String,
+    pub map_id: u8,
+    pub priority: i32,
+    pub flags: RegionFlags,
+    pub music: Option<u16>,
+    pub light_level: Option<u8>,
+}
+
+/// One rectangle of a region.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct RegionArea {
+    pub region: Entity,
+}
+
+/// An index of region areas, with the region each belongs to as the metadata.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct RegionTree {
+    pub tree: SpatialEntityTree<Entity>,
+}
+
+/// The region a character is currently in.
+#[derive(Debug, Clone, Copy, Default, Component)]
+pub struct CurrentRegion {
+    pub region: Option<Entity>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegionEnteredEvent {
+    pub entity: Entity,
+    pub region: Entity,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegionLeftEvent {
+    pub entity: Entity,
+    pub region: Entity,
+}
+
+/// Looks up the regions covering a location.
+#[derive(SystemParam)]
+pub struct RegionQuery<'w, 's> {
+    tree: Res<'w, RegionTree>,
+    regions: Query<'w, 's, &'static Region>,
+}
+
+impl<'w, 's> RegionQuery<'w, 's> {
+    pub fn get(&self, region: Entity) -> Option<&Region> {
+        self.regions.get(region).ok()
+    }
+
+    /// All regions covering a position, in no particular order.
+    pub fn all_at(&self, map_id: u8, position: IVec2) -> impl Iterator<Item = (Entity, &Region)> + '_ {
+        self.tree.tree.iter_at_point(map_id, position)
+            .filter_map(|(_, region)| self.regions.get(*region).ok().map(|r| (*region, r)))
+    }
+
+    /// The region which applies at a position: the one with the highest priority.
+    pub fn at(&self, map_id: u8, position: IVec2) -> Option<(Entity, &Region)> {
+        self.all_at(map_id, position)
+            .max_by_key(|(entity, region)| (region.priority, *entity))
+    }
+
+    pub fn at_location(&self, location: &Location) -> Option<(Entity, &Region)> {
+        self.at(location.map_id, location.position.truncate())
+    }
+
+    /// The rules which apply at a location.
+    pub fn flags_at(&self, location: &Location) -> RegionFlags {
+        self.at_location(location).map_or(RegionFlags::empty(), |(_, region)| region.flags)
+    }
+}
+
+pub fn spawn_regions(
+    static_data: Res<StaticData>,
+    mut tree: ResMut<RegionTree>,
+    mut commands: Commands,
+) {
+    for definition in &static_data.regions.regions {
+        let region = commands.spawn(Region {
+            name: definition.name.clone(),
+            map_id: definition.map_id,
+            priority: definition.priority,
+            flags: definition.flags,
+            music: definition.music,
+            light_level: definition.light_level,
+        }).id();
+
+        for area in &definition.areas {
+            let area_entity = commands.spawn(RegionArea { region }).id();
+            tree.tree.insert_aabb(area_entity, region, definition.map_id, area.min, area.max);
+        }
+    }
+
+    log::info!("Spawned {} regions", static_data.regions.regions.len());
+}
+
+pub fn update_current_regions(
+    regions: RegionQuery,
+    mut characters: Query<(Entity, &Location, Option<&mut CurrentRegion>), (With<Character>, Changed<Location>)>,
+    mut commands: Commands,
+    mut entered_events: EventWriter<RegionEnteredEvent>,
+    mut left_events: EventWriter<RegionLeftEvent>,
+) {
+    for (entity, location, current) in &mut characters {
+        let region = regions.at_location(location).map(|(region, _)| region);
+        let previous = match current {
+            Some(mut current) => {
+                if current.region == region {
+                    continue;
+                }
+                std::mem::replace(&mut current.region, region)
+            }
+            None => {
+                commands.entity(entity).insert(CurrentRegion { region });
+                None
+            }
+        };
+
+        if let Some(region) = previous {
+            left_events.send(RegionLeftEvent { entity, region });
+        }
+
+        if let Some(region) = region {
+            entered_events.send(RegionEnteredEvent { entity, region });
+        }
+    }
+}
+
+pub fn send_region_music(
+    regions: Query<&Region>,
+    clients: Query<&NetClient>,
+    new_clients: Query<(&NetClient, &Possessing), With<Synchronizing>>,
+    characters: Query<(&CurrentRegion, Option<&NetOwner>)>,
+    mut entered_events: EventReader<RegionEnteredEvent>,
+) {
+    for event in entered_events.iter() {
+        let owner = match characters.get(event.entity) {
+            Ok((_, Some(owner))) => owner,
+            _ => continue,
+        };
+
+        if let (Ok(client), Ok(Region { music: Some(track_id), .. })) =
+            (clients.get(owner.client_entity), regions.get(event.region)) {
+            client.send_packet(PlayMusic { track_id: *track_id }.into());
+        }
+    }
+
+    for (client, possessing) in &new_clients {
+        let region = match characters.get(possessing.entity) {
+            Ok((CurrentRegion { region: Some(region) }, _)) => *region,
+            _ => continue,
+        };
+
+        if let Ok(Region { music: Some(track_id), .. }) = regions.get(region) {
+            client.send_packet(PlayMusic { track_id: *track_id }.into());
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RegionsPlugin;
+
+impl Plugin for RegionsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<RegionTree>()
+            .add_event::<RegionEnteredEvent>()
+            .add_event::<RegionLeftEvent>()
+            .add_system(spawn_regions
+                .run_if(resource_added::<StaticData>())
+                .in_base_set(CoreSet::First))
+            .add_system(update_current_regions.in_base_set(CoreSet::PostUpdate))
+            .add_system(send_region_music.in_set(ServerSet::Send));
+    }
+}
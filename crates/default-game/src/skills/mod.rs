@@ -0,0 +1,491 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use bevy_time::{Time, Timer, TimerMode};
+use rand::{Rng, thread_rng};
+use serde::{Deserialize, Serialize};
+
+use yewoh::protocol::{InitialSkill, SkillEntry, SkillLock, Skills, SkillsResponse, SkillsResponseKind};
+use yewoh_server::world::entity::{Character, SeesEverything};
+use yewoh_server::world::events::{ReceivedPacketEvent, RequestSkillsEvent, UseSkillEvent};
+use yewoh_server::world::net::{NetClient, NetOwner, Possessing};
+use yewoh_server::world::ServerSet;
+
+use crate::characters::Alive;
+use crate::data::skills::Skills as SkillInfos;
+use crate::data::static_data::StaticData;
+use crate::networking::NetClientExt;
+use crate::persistence::SerializationSetupExt;
+use crate::skills::stealth::StealthPlugin;
+
+pub mod stealth;
+
+mod persistence;
+
+/// The highest a single skill can be raised to by default, in tenths of a point.
+pub const DEFAULT_SKILL_CAP: u16 = 1000;
+
+/// The highest the sum of all of a character's skills can be raised to, in tenths of a point.
+pub const TOTAL_SKILL_CAP: u32 = 7000;
+
+/// The most points a new character can put into a single skill.
+pub const MAX_INITIAL_SKILL_POINTS: u8 = 50;
+
+/// The most skills a new character can start with.
+pub const MAX_INITIAL_SKILLS: usize = 4;
+
+/// The most points a new character can spend on skills, which is what current clients allow
+/// across four skills. Older clients only allow 100 across three.
+pub const INITIAL_SKILL_BUDGET: u16 = 120;
+
+/// How long a character has to wait between actively using skills.
+pub const SKILL_USE_DELAY: Duration = Duration::from_secs(10);
+
+/// How likely a skill is to increase on a successful check, before scaling by the skill's
+/// `gain_scale` and how close it is to its cap. Failed checks are half as likely to gain.
+pub const BASE_GAIN_CHANCE: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SkillValue {
+    pub value: u16,
+    pub cap: u16,
+    pub lock: SkillLock,
+}
+
+impl Default for SkillValue {
+    fn default() -> Self {
+        Self {
+            value: 0,
+            cap: DEFAULT_SKILL_CAP,
+            lock: SkillLock::Up,
+        }
+    }
+}
+
+/// The skills of a character, in tenths of a point, by skill ID.
+///
+/// Skills which aren't present have a value of zero.
+#[derive(Debug, Clone, Default, Component, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SkillValues {
+    pub skills: HashMap<u8, SkillValue>,
+}
+
+impl SkillValues {
+    /// Create the skills chosen at character creation.
+    ///
+    /// The client's choice isn't trusted: unknown and repeated skills are dropped, each skill is
+    /// limited to [`MAX_INITIAL_SKILL_POINTS`], and the skills are scaled down if they add up to
+    /// more than [`INITIAL_SKILL_BUDGET`].
+    pub fn from_initial_skills(initial_skills: &[InitialSkill], skill_infos: &SkillInfos) -> Self {
+        let mut seen = HashSet::new();
+        let chosen = initial_skills.iter()
+            .filter(|skill| skill.points > 0
+                && skill_infos.skills.contains_key(&skill.skill_id)
+                && seen.insert(skill.skill_id))
+            .take(MAX_INITIAL_SKILLS)
+            .map(|skill| (skill.skill_id, skill.points.min(MAX_INITIAL_SKILL_POINTS) as u16))
+            .collect::<Vec<_>>();
+
+        let total = chosen.iter().map(|(_, points)| *points).sum::<u16>();
+        Self {
+            skills: chosen.into_iter()
+                .map(|(skill_id, points)| {
+                    let points = if total > INITIAL_SKILL_BUDGET { points * INITIAL_SKILL_BUDGET / total } else { points };
+                    (skill_id, SkillValue {
+                        value: points * 10,
+                        ..Default::default()
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, skill_id: u8) -> SkillValue {
+        self.skills.get(&skill_id).copied().unwrap_or_default()
+    }
+
+    pub fn value(&self, skill_id: u8) -> u16 {
+        self.get(skill_id).value
+    }
+
+    pub fn total(&self) -> u32 {
+        self.skills.values().map(|skill| skill.value as u32).sum()
+    }
+
+    pub fn to_entry(&self, skill_id: u8) -> SkillEntry {
+        let skill = self.get(skill_id);
+        SkillEntry {
+            id: skill_id as u16,
+            value: skill.value,
+            raw_value: skill.value,
+            lock: skill.lock,
+            cap: skill.cap,
+        }
+    }
+
+    /// Raise a skill by a tenth of a point if its lock and cap allow it.
+    ///
+    /// If the total skill cap has been reached, a skill which is locked downwards is lowered to
+    /// make room. Returns the IDs of the skills which changed.
+    pub fn try_gain(&mut self, skill_id: u8) -> Vec<u8> {
+        let skill = self.get(skill_id);
+        if skill.lock != SkillLock::Up || skill.value >= skill.cap {
+            return Vec::new();
+        }
+
+        let mut changed = Vec::with_capacity(2);
+        if self.total() >= TOTAL_SKILL_CAP {
+            let lowered = self.skills.iter_mut()
+                .find(|(id, skill)| **id != skill_id && skill.lock == SkillLock::Down && skill.value > 0);
+            match lowered {
+                Some((id, skill)) => {
+                    skill.value -= 1;
+                    changed.push(*id);
+                }
+                None => return changed,
+            }
+        }
+
+        self.skills.entry(skill_id).or_default().value += 1;
+        changed.push(skill_id);
+        changed
+    }
+}
+
+/// Sent whenever one of a character's skill values changes.
+#[derive(Debug, Clone)]
+pub struct SkillChangedEvent {
+    pub character: Entity,
+    pub skill_id: u8,
+}
+
+/// Sent when a character actively uses a skill, after checking its skill delay.
+#[derive(Debug, Clone)]
+pub struct SkillUsedEvent {
+    pub client_entity: Entity,
+    pub character: Entity,
+    pub skill_id: u8,
+}
+
+/// Prevents a character from using another skill until the timer runs out.
+#[derive(Debug, Clone, Component)]
+pub struct SkillDelay {
+    pub timer: Timer,
+}
+
+impl Default for SkillDelay {
+    fn default() -> Self {
+        Self { timer: Timer::new(SKILL_USE_DELAY, TimerMode::Once) }
+    }
+}
+
+/// Performs skill checks, raising skills as they are used.
+#[derive(SystemParam)]
+pub struct SkillChecks<'w, 's> {
+    static_data: Res<'w, StaticData>,
+    characters: Query<'w, 's, &'static mut SkillValues>,
+    changed_events: EventWriter<'w, SkillChangedEvent>,
+}
+
+impl<'w, 's> SkillChecks<'w, 's> {
+    pub fn value(&self, character: Entity, skill_id: u8) -> u16 {
+        self.characters.get(character).map_or(0, |values| values.value(skill_id))
+    }
+
+    /// Check a skill against a difficulty range.
+    ///
+    /// Characters below `min` always fail and characters at or above `max` always succeed, in
+    /// between the chance of success scales linearly. Only checks within that range can raise
+    /// the skill.
+    pub fn check(&mut self, character: Entity, skill_id: u8, min: u16, max: u16) -> bool {
+        let mut values = match self.characters.get_mut(character) {
+            Ok(x) => x,
+            _ => return false,
+        };
+
+        let value = values.value(skill_id);
+        if value < min {
+            return false;
+        } else if value >= max {
+            return true;
+        }
+
+        let mut rng = thread_rng();
+        let chance = (value - min) as f32 / (max - min) as f32;
+        let success = rng.gen::<f32>() < chance;
+
+        let cap = values.get(skill_id).cap.max(1);
+        let gain_scale = self.static_data.skills.skills.get(&skill_id).map_or(1.0, |skill| skill.gain_scale);
+        let gain_chance = BASE_GAIN_CHANCE * gain_scale * (1.0 - value as f32 / cap as f32)
+            * if success { 1.0 } else { 0.5 };
+        if rng.gen::<f32>() < gain_chance {
+            for skill_id in values.try_gain(skill_id) {
+                self.changed_events.send(SkillChangedEvent { character, skill_id });
+            }
+        }
+
+        success
+    }
+}
+
+pub fn add_skill_values(
+    mut commands: Commands,
+    characters: Query<Entity, (With<NetOwner>, With<Character>, Without<SkillValues>)>,
+) {
+    for entity in &characters {
+        commands.entity(entity).insert(SkillValues::default());
+    }
+}
+
+/// Skills which can be actively used, registered by the plugins which handle them.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct UsableSkills {
+    pub skills: HashSet<u8>,
+}
+
+pub trait SkillsAppExt {
+    fn register_usable_skill(&mut self, skill_id: u8) -> &mut Self;
+}
+
+impl SkillsAppExt for App {
+    fn register_usable_skill(&mut self, skill_id: u8) -> &mut Self {
+        self.world.init_resource::<UsableSkills>();
+        self.world.resource_mut::<UsableSkills>().skills.insert(skill_id);
+        self
+    }
+}
+
+/// Send a character's skills, only to its owner or to staff.
+pub fn handle_skills_requests(
+    static_data: Res<StaticData>,
+    clients: Query<(&NetClient, &Possessing)>,
+    staff: Query<(), With<SeesEverything>>,
+    characters: Query<&SkillValues>,
+    mut requests: EventReader<RequestSkillsEvent>,
+) {
+    for request in requests.iter() {
+        let (client, possessing) = match clients.get(request.client_entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        if request.target != possessing.entity && !staff.contains(possessing.entity) {
+            continue;
+        }
+
+        let values = match characters.get(request.target) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        let mut skill_ids = static_data.skills.skills.keys().copied().collect::<Vec<_>>();
+        skill_ids.sort();
+
+        client.send_packet(Skills::Response(SkillsResponse {
+            kind: SkillsResponseKind::FullWithCaps,
+            skills: skill_ids.into_iter().map(|id| values.to_entry(id)).collect(),
+        }).into());
+    }
+}
+
+pub fn handle_skill_locks(
+    clients: Query<&Possessing>,
+    mut characters: Query<&mut SkillValues>,
+    mut new_packets: EventReader<ReceivedPacketEvent>,
+) {
+    for ReceivedPacketEvent { client_entity, packet } in new_packets.iter() {
+        let request = match packet.downcast::<Skills>() {
+            Some(Skills::Lock(x)) => x,
+            _ => continue,
+        };
+
+        let possessing = match clients.get(*client_entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        if let (Ok(mut values), Ok(skill_id)) = (characters.get_mut(possessing.entity), u8::try_from(request.id)) {
+            values.skills.entry(skill_id).or_default().lock = request.lock;
+        }
+    }
+}
+
+pub fn handle_use_skill(
+    mut commands: Commands,
+    usable_skills: Res<UsableSkills>,
+    mut events: EventReader<UseSkillEvent>,
+    mut used_events: EventWriter<SkillUsedEvent>,
+    clients: Query<(&NetClient, &Possessing)>,
+    characters: Query<Option<&SkillDelay>, (With<SkillValues>, With<Alive>)>,
+) {
+    for event in events.iter() {
+        let (client, possessing) = match clients.get(event.client_entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        let skill_id = match u8::try_from(event.skill_id) {
+            Ok(x) if usable_skills.skills.contains(&x) => x,
+            _ => {
+                client.send_system_message("That skill can't be used directly.".into());
+                continue;
+            }
+        };
+
+        match characters.get(possessing.entity) {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                client.send_system_message("You must wait to perform another action.".into());
+                continue;
+            }
+            _ => continue,
+        }
+
+        commands.entity(possessing.entity).insert(SkillDelay::default());
+        used_events.send(SkillUsedEvent {
+            client_entity: event.client_entity,
+            character: possessing.entity,
+            skill_id,
+        });
+    }
+}
+
+pub fn update_skill_delays(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut characters: Query<(Entity, &mut SkillDelay)>,
+) {
+    for (entity, mut delay) in &mut characters {
+        if delay.timer.tick(time.delta()).finished() {
+            commands.entity(entity).remove::<SkillDelay>();
+        }
+    }
+}
+
+pub fn send_skill_updates(
+    clients: Query<&NetClient>,
+    characters: Query<(&SkillValues, &NetOwner)>,
+    mut events: EventReader<SkillChangedEvent>,
+) {
+    for event in events.iter() {
+        let (values, owner) = match characters.get(event.character) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        if let Ok(client) = clients.get(owner.client_entity) {
+            client.send_packet(Skills::Response(SkillsResponse {
+                kind: SkillsResponseKind::SingleUpdateWithCap,
+                skills: vec![values.to_entry(event.skill_id)],
+            }).into());
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SkillsPlugin;
+
+impl Plugin for SkillsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<SkillChangedEvent>()
+            .add_event::<SkillUsedEvent>()
+            .init_resource::<UsableSkills>()
+            .add_plugin(StealthPlugin)
+            .add_systems((
+                add_skill_values,
+                handle_skills_requests,
+                handle_skill_locks,
+                handle_use_skill.after(update_skill_delays),
+                update_skill_delays,
+            ))
+            .add_system(send_skill_updates.in_set(ServerSet::Send))
+            .register_serializer::<persistence::SkillValuesSerializer>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::skills::Skill;
+
+    use super::*;
+
+    fn skill_infos() -> SkillInfos {
+        SkillInfos {
+            skills: (0..4).map(|id| (id, Skill::default())).collect(),
+        }
+    }
+
+    fn initial(skills: &[(u8, u8)]) -> SkillValues {
+        let skills = skills.iter()
+            .map(|&(skill_id, points)| InitialSkill { skill_id, points })
+            .collect::<Vec<_>>();
+        SkillValues::from_initial_skills(&skills, &skill_infos())
+    }
+
+    #[test]
+    fn initial_skills_are_validated() {
+        let values = initial(&[(0, 50), (1, 40), (2, 30), (3, 0)]);
+        assert_eq!(values.value(0), 500);
+        assert_eq!(values.value(1), 400);
+        assert_eq!(values.value(2), 300);
+        assert!(!values.skills.contains_key(&3));
+
+        // Unknown and repeated skills are dropped.
+        let values = initial(&[(0, 30), (0, 30), (200, 50), (1, 20)]);
+        assert_eq!(values.skills.len(), 2);
+        assert_eq!(values.value(0), 300);
+        assert_eq!(values.value(1), 200);
+
+        // Each skill is limited, and the total is scaled down to the budget.
+        let values = initial(&[(0, 255), (1, 255), (2, 255), (3, 255)]);
+        for skill_id in 0..4 {
+            assert_eq!(values.value(skill_id), 300);
+        }
+        assert_eq!(values.total(), INITIAL_SKILL_BUDGET as u32 * 10);
+    }
+
+    fn skill(value: u16, lock: SkillLock) -> SkillValue {
+        SkillValue { value, lock, ..Default::default() }
+    }
+
+    #[test]
+    fn gains_respect_locks_and_caps() {
+        let mut values = SkillValues::default();
+        values.skills.insert(0, skill(500, SkillLock::Up));
+        values.skills.insert(1, skill(500, SkillLock::Locked));
+        values.skills.insert(2, skill(500, SkillLock::Down));
+        values.skills.insert(3, skill(DEFAULT_SKILL_CAP, SkillLock::Up));
+
+        assert_eq!(values.try_gain(0), vec![0]);
+        assert_eq!(values.value(0), 501);
+        assert!(values.try_gain(1).is_empty());
+        assert!(values.try_gain(2).is_empty());
+        assert!(values.try_gain(3).is_empty());
+        assert_eq!(values.value(3), DEFAULT_SKILL_CAP);
+
+        // Skills which aren't present start at zero and can be raised.
+        assert_eq!(values.try_gain(4), vec![4]);
+        assert_eq!(values.value(4), 1);
+    }
+
+    #[test]
+    fn total_cap_lowers_skills_locked_down() {
+        let mut values = SkillValues::default();
+        values.skills.insert(0, skill(500, SkillLock::Up));
+        values.skills.insert(1, skill(TOTAL_SKILL_CAP as u16 - 500, SkillLock::Locked));
+        assert!(values.try_gain(0).is_empty());
+        assert_eq!(values.value(0), 500);
+
+        values.skills.get_mut(&1).unwrap().lock = SkillLock::Down;
+        assert_eq!(values.try_gain(0), vec![1, 0]);
+        assert_eq!(values.value(0), 501);
+        assert_eq!(values.value(1), TOTAL_SKILL_CAP as u16 - 501);
+        assert_eq!(values.total(), TOTAL_SKILL_CAP);
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::HashSet;
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use glam::IVec3;
+use rand::{Rng, thread_rng};
+
+use yewoh::protocol::EntityFlags;
+use yewoh_server::world::entity::{AttackTarget, Flags, Location, VisibleTo};
+use yewoh_server::world::events::{ChatRequestEvent, MoveEvent, PickUpEvent};
+use yewoh_server::world::net::{NetClient, NetOwner, Possessing};
+use yewoh_server::world::spatial::{EntityPositions, view_aabb};
+
+use crate::characters::DamageDealt;
+use crate::commands::visibility::StaffInvisible;
+use crate::networking::NetClientExt;
+use crate::skills::{SkillChecks, SkillsAppExt, SkillUsedEvent};
+
+pub const DETECT_HIDDEN: u8 = 14;
+
+pub const HIDING: u8 = 21;
+
+pub const STEALTH: u8 = 47;
+
+/// The Hiding skill a character needs before it can try to move while hidden.
+pub const MIN_HIDING_FOR_STEALTH: u16 = 300;
+
+/// Marks a character which has hidden itself.
+///
+/// Hidden characters can only be seen by themselves and by staff. Any action reveals them,
+/// including moving away from `position` without [`Stealthing`].
+#[derive(Debug, Clone, Copy, Component)]
+pub struct Hidden {
+    pub position: IVec3,
+}
+
+/// Lets a hidden character take a number of steps without being revealed.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct Stealthing {
+    pub steps: u16,
+}
+
+/// Ends a character's [`Hidden`] state, if any.
+#[derive(Debug, Clone)]
+pub struct RevealEvent {
+    pub character: Entity,
+}
+
+fn send_to_owner(clients: &Query<&NetClient>, owners: &Query<&NetOwner>, character: Entity, message: &str) {
+    if let Some(client) = owners.get(character).ok().and_then(|owner| clients.get(owner.client_entity).ok()) {
+        client.send_system_message(message.into());
+    }
+}
+
+pub fn use_hiding(
+    mut commands: Commands,
+    mut events: EventReader<SkillUsedEvent>,
+    mut reveal_events: EventWriter<RevealEvent>,
+    mut skill_checks: SkillChecks,
+    clients: Query<&NetClient>,
+    characters: Query<(&Location, Option<&AttackTarget>)>,
+) {
+    for event in events.iter().filter(|e| e.skill_id == HIDING) {
+        let (client, (location, attack_target)) = match (clients.get(event.client_entity), characters.get(event.character)) {
+            (Ok(client), Ok(character)) => (client, character),
+            _ => continue,
+        };
+
+        if attack_target.is_some() {
+            client.send_system_message("You can't seem to hide right now.".into());
+            continue;
+        }
+
+        if skill_checks.check(event.character, HIDING, 0, 1000) {
+            commands.entity(event.character)
+                .remove::<Stealthing>()
+                .insert(Hidden { position: location.position });
+            client.send_system_message("You have hidden yourself well.".into());
+        } else {
+            reveal_events.send(RevealEvent { character: event.character });
+            client.send_system_message("You can't seem to hide here.".into());
+        }
+    }
+}
+
+pub fn use_stealth(
+    mut commands: Commands,
+    mut events: EventReader<SkillUsedEvent>,
+    mut reveal_events: EventWriter<RevealEvent>,
+    mut skill_checks: SkillChecks,
+    clients: Query<&NetClient>,
+    characters: Query<(), With<Hidden>>,
+) {
+    for event in events.iter().filter(|e| e.skill_id == STEALTH) {
+        let client = match clients.get(event.client_entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        if !characters.contains(event.character) {
+            client.send_system_message("You must hide first.".into());
+            continue;
+        }
+
+        if skill_checks.value(event.character, HIDING) < MIN_HIDING_FOR_STEALTH {
+            client.send_system_message("You are not hidden well enough. Become better at hiding.".into());
+            continue;
+        }
+
+        if skill_checks.check(event.character, STEALTH, 0, 1000) {
+            let steps = (skill_checks.value(event.character, STEALTH) / 50).max(1);
+            commands.entity(event.character).insert(Stealthing { steps });
+            client.send_system_message("You begin to move quietly.".into());
+        } else {
+            reveal_events.send(RevealEvent { character: event.character });
+            client.send_system_message("You fail in your attempt to move unnoticed.".into());
+        }
+    }
+}
+
+/// Search the area around the character, revealing hidden characters whose Hiding loses a
+/// contest against the searcher's Detecting Hidden.
+pub fn use_detect_hidden(
+    mut events: EventReader<SkillUsedEvent>,
+    mut reveal_events: EventWriter<RevealEvent>,
+    mut skill_checks: SkillChecks,
+    positions: Res<EntityPositions>,
+    clients: Query<&NetClient>,
+    searchers: Query<&Location>,
+    hidden: Query<(), With<Hidden>>,
+) {
+    let mut rng = thread_rng();
+
+    for event in events.iter().filter(|e| e.skill_id == DETECT_HIDDEN) {
+        let (client, location) = match (clients.get(event.client_entity), searchers.get(event.character)) {
+            (Ok(client), Ok(location)) => (client, *location),
+            _ => continue,
+        };
+
+        let mut found = false;
+        if skill_checks.check(event.character, DETECT_HIDDEN, 0, 1000) {
+            let detect = skill_checks.value(event.character, DETECT_HIDDEN);
+            let range = (detect / 100).max(1) as i32;
+            let (min, max) = view_aabb(location.position.truncate(), range);
+
+            for (entity, ..) in positions.tree.iter_aabb(location.map_id, min, max) {
+                if entity == event.character || !hidden.contains(entity) {
+                    continue;
+                }
+
+                let hiding = skill_checks.value(entity, HIDING);
+                if rng.gen_range(0..=detect) >= rng.gen_range(0..=hiding) {
+                    reveal_events.send(RevealEvent { character: entity });
+                    found = true;
+                }
+            }
+        }
+
+        if !found {
+            client.send_system_message("You can see nothing hidden there.".into());
+        }
+    }
+}
+
+/// Reveal hidden characters when they speak, pick things up, fight or use other skills.
+pub fn reveal_on_actions(
+    mut reveal_events: EventWriter<RevealEvent>,
+    mut chat_events: EventReader<ChatRequestEvent>,
+    mut pick_up_events: EventReader<PickUpEvent>,
+    mut damage_events: EventReader<DamageDealt>,
+    mut skill_events: EventReader<SkillUsedEvent>,
+    clients: Query<&Possessing>,
+    attackers: Query<Entity, (Changed<AttackTarget>, With<Hidden>)>,
+) {
+    let client_characters = chat_events.iter().map(|e| e.client_entity)
+        .chain(pick_up_events.iter().map(|e| e.client_entity))
+        .filter_map(|client_entity| clients.get(client_entity).ok())
+        .map(|possessing| possessing.entity);
+    let fighters = damage_events.iter().flat_map(|e| [e.source, e.target]);
+    let skill_users = skill_events.iter()
+        .filter(|e| !matches!(e.skill_id, HIDING | STEALTH | DETECT_HIDDEN))
+        .map(|e| e.character);
+
+    let characters = client_characters
+        .chain(fighters)
+        .chain(skill_users)
+        .chain(attackers.iter())
+        .collect::<Vec<_>>();
+    for character in characters {
+        reveal_events.send(RevealEvent { character });
+    }
+}
+
+/// Spend a stealth step for each tile a hidden character moves, revealing them if they run or
+/// have no steps left.
+pub fn update_stealth_movement(
+    mut reveal_events: EventWriter<RevealEvent>,
+    mut move_events: EventReader<MoveEvent>,
+    clients: Query<&Possessing>,
+    mut characters: Query<(Entity, &Location, &mut Hidden, Option<&mut Stealthing>), Changed<Location>>,
+) {
+    for event in move_events.iter().filter(|e| e.request.run) {
+        if let Ok(possessing) = clients.get(event.client_entity) {
+            reveal_events.send(RevealEvent { character: possessing.entity });
+        }
+    }
+
+    for (entity, location, mut hidden, stealthing) in &mut characters {
+        if location.position == hidden.position {
+            continue;
+        }
+
+        match stealthing {
+            Some(mut stealthing) if stealthing.steps > 0 => {
+                stealthing.steps -= 1;
+                hidden.position = location.position;
+            }
+            _ => reveal_events.send(RevealEvent { character: entity }),
+        }
+    }
+}
+
+pub fn reveal_characters(
+    mut commands: Commands,
+    mut reveal_events: EventReader<RevealEvent>,
+    clients: Query<&NetClient>,
+    owners: Query<&NetOwner>,
+    hidden: Query<(), With<Hidden>>,
+) {
+    let mut revealed = HashSet::new();
+    for event in reveal_events.iter() {
+        if !hidden.contains(event.character) || !revealed.insert(event.character) {
+            continue;
+        }
+
+        commands.entity(event.character).remove::<(Hidden, Stealthing)>();
+        send_to_owner(&clients, &owners, event.character, "You have been revealed!");
+    }
+}
+
+/// Show hidden characters as hidden to their owners.
+pub fn update_hidden_flags(
+    mut characters: ParamSet<(
+        Query<Entity, Or<(Added<Hidden>, Added<Flags>)>>,
+        Query<(&mut Flags, Option<&Hidden>)>,
+    )>,
+    mut removed: RemovedComponents<Hidden>,
+) {
+    let changed = characters.p0().iter()
+        .chain(removed.iter())
+        .collect::<Vec<_>>();
+    let mut characters = characters.p1();
+    for entity in changed {
+        let (mut flags, hidden) = match characters.get_mut(entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        if flags.flags.contains(EntityFlags::HIDDEN) != hidden.is_some() {
+            flags.flags.set(EntityFlags::HIDDEN, hidden.is_some());
+        }
+    }
+}
+
+/// Marks a character which [`update_concealment`] has hidden from other observers.
+///
+/// Keeps the [`VisibleTo`] the character had before being concealed, so that it can be
+/// restored once the character is no longer hidden or staff invisible.
+#[derive(Debug, Clone, Component)]
+pub struct Concealed {
+    pub previous: Option<VisibleTo>,
+}
+
+/// Hide hidden and staff invisible characters from other observers.
+pub fn update_concealment(
+    mut commands: Commands,
+    characters: Query<(Option<&Hidden>, Option<&StaffInvisible>, Option<&VisibleTo>, Option<&Concealed>)>,
+    added: Query<Entity, Or<(Added<Hidden>, Added<StaffInvisible>)>>,
+    mut removed_hidden: RemovedComponents<Hidden>,
+    mut removed_invisible: RemovedComponents<StaffInvisible>,
+) {
+    for entity in added.iter().chain(removed_hidden.iter()).chain(removed_invisible.iter()) {
+        let (hidden, invisible, visible_to, concealed) = match characters.get(entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        let should_conceal = hidden.is_some() || invisible.is_some();
+        match (should_conceal, concealed) {
+            (true, None) => {
+                commands.entity(entity).insert((
+                    Concealed { previous: visible_to.cloned() },
+                    VisibleTo::nobody(),
+                ));
+            }
+            (false, Some(concealed)) => {
+                let mut entity = commands.entity(entity);
+                entity.remove::<Concealed>();
+                match concealed.previous.clone() {
+                    Some(previous) => entity.insert(previous),
+                    None => entity.remove::<VisibleTo>(),
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct StealthPlugin;
+
+impl Plugin for StealthPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<RevealEvent>()
+            .register_usable_skill(DETECT_HIDDEN)
+            .register_usable_skill(HIDING)
+            .register_usable_skill(STEALTH)
+            .add_systems((
+                use_hiding,
+                use_stealth.after(use_hiding),
+                use_detect_hidden,
+                reveal_on_actions,
+                update_stealth_movement,
+                reveal_characters
+                    .after(use_hiding)
+                    .after(use_stealth)
+                    .after(use_detect_hidden)
+                    .after(reveal_on_actions)
+                    .after(update_stealth_movement),
+                update_hidden_flags.after(reveal_characters),
+                update_concealment.after(reveal_characters),
+            ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::event::Events;
+    use bevy_ecs::schedule::Schedule;
+    use yewoh::Direction;
+
+    use super::*;
+
+    fn location(x: i32) -> Location {
+        Location { map_id: 0, position: IVec3::new(x, 0, 0), direction: Direction::North }
+    }
+
+    #[test]
+    fn stealth_spends_a_step_per_move() {
+        let mut world = World::new();
+        world.init_resource::<Events<RevealEvent>>();
+        world.init_resource::<Events<MoveEvent>>();
+        let stealthing = world.spawn((location(0), Hidden { position: IVec3::ZERO }, Stealthing { steps: 2 })).id();
+        let hiding = world.spawn((location(10), Hidden { position: IVec3::new(10, 0, 0) })).id();
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(update_stealth_movement);
+        let mut run = |world: &mut World| {
+            schedule.run(world);
+            world.resource_mut::<Events<RevealEvent>>().drain()
+                .map(|e| e.character)
+                .collect::<Vec<_>>()
+        };
+        assert!(run(&mut world).is_empty());
+
+        for x in 1..=2 {
+            *world.get_mut::<Location>(stealthing).unwrap() = location(x);
+            assert!(run(&mut world).is_empty());
+            assert_eq!(world.get::<Hidden>(stealthing).unwrap().position, IVec3::new(x, 0, 0));
+        }
+        assert_eq!(world.get::<Stealthing>(stealthing).unwrap().steps, 0);
+
+        *world.get_mut::<Location>(stealthing).unwrap() = location(3);
+        *world.get_mut::<Location>(hiding).unwrap() = location(11);
+        let mut revealed = run(&mut world);
+        revealed.sort();
+        assert_eq!(revealed, vec![stealthing, hiding]);
+    }
+
+    #[test]
+    fn concealment_restores_visibility() {
+        let mut world = World::new();
+        let observer = Entity::from_raw(100);
+        let restricted = world.spawn(VisibleTo::only(observer)).id();
+        let public = world.spawn_empty().id();
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(update_concealment);
+        schedule.run(&mut world);
+
+        world.entity_mut(restricted).insert(Hidden { position: IVec3::ZERO });
+        world.entity_mut(public).insert(StaffInvisible);
+        schedule.run(&mut world);
+        for entity in [restricted, public] {
+            assert_eq!(world.get::<VisibleTo>(entity), Some(&VisibleTo::nobody()));
+            assert!(world.get::<Concealed>(entity).is_some());
+        }
+
+        world.entity_mut(restricted).remove::<Hidden>();
+        world.entity_mut(public).remove::<StaffInvisible>();
+        schedule.run(&mut world);
+        assert_eq!(world.get::<VisibleTo>(restricted), Some(&VisibleTo::only(observer)));
+        assert_eq!(world.get::<VisibleTo>(public), None);
+        assert!(world.get::<Concealed>(restricted).is_none());
+        assert!(world.get::<Concealed>(public).is_none());
+    }
+}
@@ -2,7 +2,9 @@ use bevy_ecs::prelude::*;
 use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
 use yewoh::protocol::{GlobalLightLevel, Packet, SetTime};
 
-use yewoh_server::world::net::{NetClient, Synchronizing};
+use yewoh_server::world::net::{NetClient, NetOwner, Possessing, Synchronizing};
+
+use crate::regions::{CurrentRegion, Region};
 
 pub const MULTIPLIER: f64 = 12.;
 
@@ -49,9 +51,27 @@ impl<T: TimeZone> From<DateTime<T>> for WorldTime {
     }
 }
 
+/// The light level a client should see, taking the region its character is in into account.
+fn client_light_level(
+    possessing: Option<&Possessing>,
+    characters: &Query<&CurrentRegion>,
+    regions: &Query<&Region>,
+    global_light_level: u8,
+) -> u8 {
+    possessing
+        .and_then(|possessing| characters.get(possessing.entity).ok())
+        .and_then(|current| current.region)
+        .and_then(|region| regions.get(region).ok())
+        .and_then(|region| region.light_level)
+        .unwrap_or(global_light_level)
+}
+
 pub fn send_time(
-    new_clients: Query<&NetClient, With<Synchronizing>>,
-    all_clients: Query<&NetClient>,
+    new_clients: Query<(&NetClient, Option<&Possessing>), With<Synchronizing>>,
+    all_clients: Query<(&NetClient, Option<&Possessing>)>,
+    moved_characters: Query<&NetOwner, Changed<CurrentRegion>>,
+    characters: Query<&CurrentRegion>,
+    regions: Query<&Region>,
     mut last_light_level: Local<u8>,
 ) {
     let now = WorldTime::now();
@@ -60,20 +80,29 @@ pub fn send_time(
 
     if *last_light_level != light_level {
         let packet = SetTime { hour, minute, second }.into_arc();
-        let light_level_packet = GlobalLightLevel(light_level).into_arc();
         *last_light_level = light_level;
 
-        for client in all_clients.iter() {
+        for (client, possessing) in all_clients.iter() {
+            let client_light_level = client_light_level(possessing, &characters, &regions, light_level);
             client.send_packet_arc(packet.clone());
-            client.send_packet_arc(light_level_packet.clone());
+            client.send_packet(GlobalLightLevel(client_light_level).into());
         }
-    } else if !new_clients.is_empty() {
-        let packet = SetTime { hour, minute, second }.into_arc();
-        let light_level_packet = GlobalLightLevel(light_level).into_arc();
+    } else {
+        if !new_clients.is_empty() {
+            let packet = SetTime { hour, minute, second }.into_arc();
 
-        for client in new_clients.iter() {
-            client.send_packet_arc(packet.clone());
-            client.send_packet_arc(light_level_packet.clone());
+            for (client, possessing) in new_clients.iter() {
+                let client_light_level = client_light_level(possessing, &characters, &regions, light_level);
+                client.send_packet_arc(packet.clone());
+                client.send_packet(GlobalLightLevel(client_light_level).into());
+            }
+        }
+
+        for owner in moved_characters.iter() {
+            if let Ok((client, possessing)) = all_clients.get(owner.client_entity) {
+                let client_light_level = client_light_level(possessing, &characters, &regions, light_level);
+                client.send_packet(GlobalLightLevel(client_light_level).into());
+            }
         }
     }
 }
@@ -14,7 +14,7 @@ use bevy::prelude::*;
 use bevy::time::Time;
 use clap::Parser;
 use futures::future::join;
-use log::info;
+use log::{info, warn};
 use serde::Deserialize;
 use tokio::fs;
 use tokio::net::{lookup_host, TcpListener};
@@ -22,16 +22,20 @@ use tokio::sync::mpsc;
 use tokio::time::sleep;
 use tokio::runtime::Handle;
 
+use yewoh::assets::fonts::load_fonts;
 use yewoh::assets::multi::load_multi_data;
 use yewoh::assets::tiles::load_tile_data;
+use yewoh_default_game::commands::freeze::StaticsExport;
 use yewoh_default_game::data::prefab::{Prefab, PrefabCollection, PrefabCommandsExt, PrefabFactory};
 use yewoh_default_game::data::static_data;
 use yewoh_default_game::DefaultGamePlugins;
 use yewoh_default_game::persistence::{migrate, SerializationWorldExt, SerializedBuffers};
 use yewoh_server::async_runtime::AsyncRuntime;
 use yewoh_server::game_server::listen_for_game;
+use yewoh_server::gump_ui::{GUMP_FONT_INDEX, GumpFont};
 use yewoh_server::lobby::{listen_for_lobby, LocalServerRepository};
-use yewoh_server::world::map::{Chunk, create_map_entities, create_statics, MultiDataResource, Static, TileDataResource};
+use yewoh_server::world::map::{MultiDataResource, TileDataResource};
+use yewoh_server::world::map_storage::MapStorage;
 use yewoh_server::world::net::{NetCommandsExt, NetServer};
 use yewoh_server::world::ServerPlugin;
 
@@ -50,6 +54,10 @@ struct Args {
     #[clap(short, long, default_value = "data", env = "YEWOH_DATA")]
     data_path: PathBuf,
 
+    /// Path to write rebuilt Ultima Online Classic data to.
+    #[clap(long, default_value = "uoexport", env = "YEWOH_UO_EXPORT")]
+    uo_export_path: PathBuf,
+
     /// The display name of this game server.
     #[clap(short, long, default_value = "Yewoh Server", env = "YEWOH_SERVER_NAME")]
     server_display_name: String,
@@ -119,13 +127,13 @@ async fn main() -> anyhow::Result<()> {
     let static_data = static_data::load_from_directory(&args.data_path).await?;
     let map_infos = static_data.maps.map_infos();
     let tile_data = load_tile_data(&args.uo_data_path).await?;
-    let multi_data = load_multi_data(&args.uo_data_path).await?;
+    let multi_data = load_multi_data(&args.uo_data_path, &tile_data).await?;
+    let gump_font = load_fonts(&args.uo_data_path).await?
+        .unicode.into_iter().nth(GUMP_FONT_INDEX).flatten();
 
     // Load UO data
-    info!("Loading map data...");
-    create_map_entities(&mut app.world, &map_infos, &args.uo_data_path).await?;
-    info!("Loading statics...");
-    create_statics(&mut app.world, &map_infos, &tile_data, &args.uo_data_path).await?;
+    info!("Opening map data...");
+    let map_storage = MapStorage::open(&map_infos, &args.uo_data_path).await?;
 
     // Load server data
     let mut prefabs = PrefabCollection::default();
@@ -134,10 +142,6 @@ async fn main() -> anyhow::Result<()> {
     app.insert_resource(prefabs);
 
     // Spawn map data
-    let mut query = app.world.query_filtered::<(), With<Chunk>>();
-    info!("Spawned {} map chunks", query.iter(&app.world).count());
-    let mut query = app.world.query_filtered::<(), With<Static>>();
-    info!("Spawned {} statics", query.iter(&app.world).count());
     load_static_entities(&mut app.world, &args.data_path.join("entities")).await?;
 
     let (lobby_listener, game_listener) = join(
@@ -164,13 +168,21 @@ async fn main() -> anyhow::Result<()> {
         .insert_resource(AsyncRuntime::from(Handle::current()))
         .insert_resource(NetServer::new(args.encryption, new_session_requests, new_session_rx))
         .insert_resource(map_infos)
+        .insert_resource(map_storage)
         .insert_resource(static_data)
         .insert_resource(TileDataResource { tile_data })
         .insert_resource(MultiDataResource { multi_data })
+        .insert_resource(StaticsExport { output_path: args.uo_export_path.clone() })
         .insert_resource(world_repo.clone())
         .insert_resource(accounts_repo.clone())
         .add_system(scheduled_save.in_base_set(CoreSet::Last));
 
+    if let Some(font) = gump_font {
+        app.insert_resource(GumpFont(font));
+    } else {
+        warn!("unifont{GUMP_FONT_INDEX}.mul is missing, gump text sizes will be estimated");
+    }
+
     // Load previous state
     if let Some(contents) = world_repo.get_snapshot().await? {
         let mut d = serde_json::Deserializer::from_reader(Cursor::new(&contents));
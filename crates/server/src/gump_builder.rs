@@ -2,6 +2,7 @@ use std::fmt::Write;
 
 use glam::IVec2;
 
+use yewoh::assets::fonts::{FontMetrics, measure_text, strip_html, wrap_text};
 use yewoh::EntityId;
 use yewoh::protocol::GumpLayout;
 
@@ -22,6 +23,16 @@ impl GumpText {
     }
 }
 
+/// Size an HTML area `width` pixels wide to fit `content`.
+///
+/// If the contents are taller than `max_height`, the area is limited to that height and the
+/// returned flag is set to show a scrollbar.
+pub fn fit_html(font: &impl FontMetrics, content: &str, width: u32, max_height: u32) -> (IVec2, bool) {
+    let lines = wrap_text(font, &strip_html(content), width).len() as u32;
+    let height = lines * font.line_height();
+    (IVec2::new(width as i32, height.min(max_height) as i32), height > max_height)
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct GumpBuilder {
     layout: String,
@@ -39,6 +50,23 @@ impl GumpBuilder {
         }
     }
 
+    /// The current end of the layout, for inserting elements later with [`GumpBuilder::insert`].
+    pub fn mark(&self) -> usize {
+        self.layout.len()
+    }
+
+    /// Insert the elements from another builder at a position returned by [`GumpBuilder::mark`].
+    pub fn insert(&mut self, mark: usize, other: &GumpBuilder) -> &mut Self {
+        self.layout.insert_str(mark, &other.layout);
+        self
+    }
+
+    /// Append the elements from another builder.
+    pub fn append(&mut self, other: &GumpBuilder) -> &mut Self {
+        self.layout.push_str(&other.layout);
+        self
+    }
+
     pub fn mark_no_close(&mut self) -> &mut Self {
         write!(&mut self.layout, "{{ noclose }}").unwrap();
         self
@@ -168,6 +196,55 @@ impl GumpBuilder {
         self
     }
 
+    /// Add a single line of text, cropped to its measured size.
+    pub fn add_label(
+        &mut self,
+        font: &impl FontMetrics,
+        text: &mut GumpText,
+        content: String,
+        hue: u16,
+        position: IVec2,
+    ) -> &mut Self {
+        let size = measure_text(font, &content).as_ivec2();
+        let intern_id = text.intern(content);
+        self.add_text_cropped(intern_id, hue, position, size)
+    }
+
+    /// Add text wrapped to `max_width`, as one text element per line.
+    pub fn add_text_wrapped(
+        &mut self,
+        font: &impl FontMetrics,
+        text: &mut GumpText,
+        content: &str,
+        hue: u16,
+        position: IVec2,
+        max_width: u32,
+    ) -> &mut Self {
+        let line_height = font.line_height() as i32;
+        for (index, line) in wrap_text(font, content, max_width).into_iter().enumerate() {
+            let intern_id = text.intern(line);
+            self.add_text(intern_id, hue, position + IVec2::new(0, index as i32 * line_height));
+        }
+        self
+    }
+
+    /// Add an HTML area `width` pixels wide, sized to fit its contents with [`fit_html`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_html_fitted(
+        &mut self,
+        font: &impl FontMetrics,
+        text: &mut GumpText,
+        content: String,
+        background: bool,
+        position: IVec2,
+        width: u32,
+        max_height: u32,
+    ) -> &mut Self {
+        let (size, scrollbar) = fit_html(font, &content, width, max_height);
+        let intern_id = text.intern(content);
+        self.add_html(intern_id, background, scrollbar, position, size)
+    }
+
     pub fn add_text_entry(
         &mut self,
         id: u32,
@@ -363,10 +440,12 @@ impl GumpBuilder {
         self
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn add_html_localised_parametric(
         &mut self,
         text_id: u32,
         params: &str,
+        colour: u32,
         background: bool,
         scrollbar: bool,
         position: IVec2,
@@ -377,14 +456,15 @@ impl GumpBuilder {
 
         write!(
             &mut self.layout,
-            "{{ xmfhtmltok {} {} {} {} {} {} {} @{}@ }}",
+            "{{ xmfhtmltok {} {} {} {} {} {} {} {} @{}@ }}",
             position.x,
             position.y,
             size.x,
             size.y,
-            text_id,
             background,
             scrollbar,
+            colour,
+            text_id,
             params,
         ).unwrap();
         self
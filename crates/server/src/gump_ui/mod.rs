@@ -0,0 +1,373 @@
+//! A declarative toolkit for building gumps.
+//!
+//! Gumps are described by a view function which places widgets inside layout containers
+//! using a [`Ui`], rather than positioning elements and interning text by hand. Buttons take
+//! callbacks, and [`ReactiveGump`] re-runs the view and resends the gump whenever the state
+//! it's bound to changes.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bevy_ecs::prelude::*;
+use glam::IVec2;
+
+use yewoh::assets::fonts::{FontMetrics, GlyphMetrics, measure_text, UnicodeFont};
+use yewoh::protocol::GumpLayout;
+
+use crate::gump_builder::{GumpBuilder, GumpText};
+
+pub use reactive::{
+    GumpAction,
+    handle_reactive_gump_responses,
+    ReactiveGump,
+    ReactiveGumpRegistrationExt,
+    render_reactive_gumps,
+};
+
+mod reactive;
+
+mod widgets;
+
+/// A callback run when a button is pressed on a gump bound to state `S`.
+pub type GumpCallback<S> = Arc<dyn Fn(&mut GumpAction<S>) + Send + Sync>;
+
+/// The index of the unicode font used for gump text.
+pub const GUMP_FONT_INDEX: usize = 1;
+
+/// The font used to measure text in gumps.
+///
+/// Gump text uses unicode font [`GUMP_FONT_INDEX`]. If this resource isn't present, text
+/// sizes are estimated.
+#[derive(Debug, Clone, Resource)]
+pub struct GumpFont(pub UnicodeFont);
+
+/// Rough metrics for unicode font 1, for when the real font isn't loaded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApproximateFont;
+
+impl FontMetrics for ApproximateFont {
+    fn glyph(&self, c: char) -> Option<GlyphMetrics> {
+        let width = if c == ' ' { 4 } else { 7 };
+        Some(GlyphMetrics { x_offset: 0, y_offset: 0, width, height: 16 })
+    }
+
+    fn line_height(&self) -> u32 {
+        18
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum UiFont<'a> {
+    Loaded(&'a UnicodeFont),
+    Approximate(ApproximateFont),
+}
+
+impl FontMetrics for UiFont<'_> {
+    fn glyph(&self, c: char) -> Option<GlyphMetrics> {
+        match self {
+            UiFont::Loaded(font) => font.glyph(c),
+            UiFont::Approximate(font) => font.glyph(c),
+        }
+    }
+
+    fn line_height(&self) -> u32 {
+        match self {
+            UiFont::Loaded(font) => font.line_height(),
+            UiFont::Approximate(font) => font.line_height(),
+        }
+    }
+}
+
+/// Identifies a text entry in a gump response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextEntryId(pub u16);
+
+/// Identifies a checkbox or radio button in a gump response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SwitchId(pub u32);
+
+#[derive(Debug, Clone, Copy)]
+enum Flow {
+    Column,
+    Row,
+    Grid { columns: usize, cell: IVec2 },
+}
+
+struct Output<S> {
+    header: GumpBuilder,
+    pages: Vec<GumpBuilder>,
+    text: GumpText,
+    callbacks: HashMap<u32, GumpCallback<S>>,
+    on_close: Option<GumpCallback<S>>,
+    next_button_id: u32,
+    next_entry_id: u16,
+    next_switch_id: u32,
+}
+
+/// The result of running a view.
+pub struct RenderedGump<S> {
+    pub layout: GumpLayout,
+    callbacks: HashMap<u32, GumpCallback<S>>,
+    on_close: Option<GumpCallback<S>>,
+}
+
+/// Lays out the contents of a gump.
+///
+/// Each `Ui` places its children one after another: downwards in a column, rightwards in a
+/// row, or into fixed size cells in a grid. Containers size themselves to fit their
+/// contents.
+pub struct Ui<'a, S> {
+    output: &'a mut Output<S>,
+    font: UiFont<'a>,
+    page: usize,
+    flow: Flow,
+    origin: IVec2,
+    cursor: IVec2,
+    size: IVec2,
+    count: usize,
+    spacing: i32,
+}
+
+/// Lay out a gump by running `build` on an empty column.
+pub fn render_gump<S: Component>(font: Option<&UnicodeFont>, build: impl FnOnce(&mut Ui<S>)) -> RenderedGump<S> {
+    let mut output = Output {
+        header: GumpBuilder::new(),
+        pages: vec![GumpBuilder::new()],
+        text: GumpText::new(),
+        callbacks: HashMap::new(),
+        on_close: None,
+        next_button_id: 1,
+        next_entry_id: 1,
+        next_switch_id: 1,
+    };
+    let font = match font {
+        Some(font) => UiFont::Loaded(font),
+        None => UiFont::Approximate(ApproximateFont),
+    };
+
+    build(&mut Ui {
+        output: &mut output,
+        font,
+        page: 0,
+        flow: Flow::Column,
+        origin: IVec2::ZERO,
+        cursor: IVec2::ZERO,
+        size: IVec2::ZERO,
+        count: 0,
+        spacing: 4,
+    });
+
+    let mut layout = output.header;
+    for (index, page) in output.pages.iter().enumerate() {
+        layout.add_page(index).append(page);
+    }
+
+    RenderedGump {
+        layout: layout.into_layout(output.text),
+        callbacks: output.callbacks,
+        on_close: output.on_close,
+    }
+}
+
+impl<'a, S: Component> Ui<'a, S> {
+    /// The size taken up by the contents so far.
+    pub fn size(&self) -> IVec2 {
+        self.size
+    }
+
+    /// Measure the size of some text in the gump font.
+    pub fn measure(&self, text: &str) -> IVec2 {
+        measure_text(&self.font, text).as_ivec2()
+    }
+
+    /// Set the gap left between children added after this.
+    pub fn set_spacing(&mut self, spacing: i32) -> &mut Self {
+        self.spacing = spacing;
+        self
+    }
+
+    pub fn no_close(&mut self) -> &mut Self {
+        self.output.header.mark_no_close();
+        self
+    }
+
+    pub fn no_move(&mut self) -> &mut Self {
+        self.output.header.mark_no_move();
+        self
+    }
+
+    pub fn no_dispose(&mut self) -> &mut Self {
+        self.output.header.mark_no_dispose();
+        self
+    }
+
+    fn next_position(&self) -> IVec2 {
+        match self.flow {
+            Flow::Grid { columns, cell } => {
+                let columns = columns.max(1);
+                let index = IVec2::new((self.count % columns) as i32, (self.count / columns) as i32);
+                self.origin + index * (cell + IVec2::splat(self.spacing))
+            }
+            _ => self.origin + self.cursor,
+        }
+    }
+
+    fn advance(&mut self, size: IVec2) {
+        let end = match self.flow {
+            Flow::Column => {
+                let end = self.cursor + size;
+                self.cursor.y = end.y + self.spacing;
+                end
+            }
+            Flow::Row => {
+                let end = self.cursor + size;
+                self.cursor.x = end.x + self.spacing;
+                end
+            }
+            Flow::Grid { cell, .. } => self.next_position() - self.origin + cell,
+        };
+        self.size = self.size.max(end);
+        self.count += 1;
+    }
+
+    /// Reserve space for an element, returning its position.
+    ///
+    /// In a grid, each element takes up one cell regardless of `size`.
+    pub fn allocate(&mut self, size: IVec2) -> IVec2 {
+        let position = self.next_position();
+        self.advance(size);
+        position
+    }
+
+    /// Leave an empty gap along the direction of this container.
+    pub fn add_space(&mut self, amount: i32) -> &mut Self {
+        let size = match self.flow {
+            Flow::Row => IVec2::new(amount, 0),
+            _ => IVec2::new(0, amount),
+        };
+        self.allocate(size);
+        self
+    }
+
+    /// Add elements directly with a [`GumpBuilder`], in an area of a known size.
+    pub fn add_raw(&mut self, size: IVec2, add: impl FnOnce(&mut GumpBuilder, &mut GumpText, IVec2)) -> &mut Self {
+        let position = self.allocate(size);
+        add(&mut self.output.pages[self.page], &mut self.output.text, position);
+        self
+    }
+
+    fn child<R>(&mut self, flow: Flow, offset: IVec2, page: usize, build: impl FnOnce(&mut Ui<S>) -> R) -> (R, IVec2) {
+        let origin = self.next_position() + offset;
+        let mut ui = Ui {
+            output: &mut *self.output,
+            font: self.font,
+            page,
+            flow,
+            origin,
+            cursor: IVec2::ZERO,
+            size: IVec2::ZERO,
+            count: 0,
+            spacing: self.spacing,
+        };
+        let result = build(&mut ui);
+        (result, ui.size)
+    }
+
+    /// Lay out children from top to bottom.
+    pub fn column<R>(&mut self, build: impl FnOnce(&mut Ui<S>) -> R) -> R {
+        let (result, size) = self.child(Flow::Column, IVec2::ZERO, self.page, build);
+        self.advance(size);
+        result
+    }
+
+    /// Lay out children from left to right.
+    pub fn row<R>(&mut self, build: impl FnOnce(&mut Ui<S>) -> R) -> R {
+        let (result, size) = self.child(Flow::Row, IVec2::ZERO, self.page, build);
+        self.advance(size);
+        result
+    }
+
+    /// Lay out children in `cell` sized cells, filling each row of `columns` cells in turn.
+    pub fn grid<R>(&mut self, columns: usize, cell: IVec2, build: impl FnOnce(&mut Ui<S>) -> R) -> R {
+        let (result, size) = self.child(Flow::Grid { columns, cell }, IVec2::ZERO, self.page, build);
+        self.advance(size);
+        result
+    }
+
+    /// Lay out children in a column on a sliced background image, sized to fit.
+    pub fn panel<R>(&mut self, image_id: u32, padding: i32, build: impl FnOnce(&mut Ui<S>) -> R) -> R {
+        let position = self.next_position();
+        let mark = self.output.pages[self.page].mark();
+        let (result, size) = self.child(Flow::Column, IVec2::splat(padding), self.page, build);
+        let size = size + IVec2::splat(padding * 2);
+
+        let mut background = GumpBuilder::new();
+        background.add_image_sliced(image_id, position, size);
+        self.output.pages[self.page].insert(mark, &background);
+        self.advance(size);
+        result
+    }
+
+    /// Allocate a new client-side page.
+    ///
+    /// Pages can't be nested, so this returns `None` if called from inside a page.
+    fn new_page(&mut self) -> Option<usize> {
+        if self.page != 0 {
+            log::warn!("gump pages can't be nested, only showing the first page");
+            return None;
+        }
+
+        self.output.pages.push(GumpBuilder::new());
+        Some(self.output.pages.len() - 1)
+    }
+
+    /// Lay out each of `pages` in a column on its own client-side page, all at the current
+    /// position. The size taken up is that of the largest page.
+    fn add_pages(&mut self, pages: usize, mut build: impl FnMut(&mut Ui<S>, usize, usize)) -> Vec<usize> {
+        let mut page_ids = Vec::with_capacity(pages);
+        let mut size = IVec2::ZERO;
+        for index in 0..pages {
+            let page = match self.new_page() {
+                Some(x) => x,
+                None if index == 0 => self.page,
+                None => break,
+            };
+            let (_, page_size) = self.child(Flow::Column, IVec2::ZERO, page, |ui| build(ui, index, page));
+            size = size.max(page_size);
+            page_ids.push(page);
+        }
+        self.advance(size);
+        page_ids
+    }
+
+    fn next_button_id(&mut self) -> u32 {
+        let id = self.output.next_button_id;
+        self.output.next_button_id += 1;
+        id
+    }
+
+    /// Register a callback for a new reply button, returning the button ID.
+    pub fn register_button(&mut self, callback: impl Fn(&mut GumpAction<S>) + Send + Sync + 'static) -> u32 {
+        let id = self.next_button_id();
+        self.output.callbacks.insert(id, Arc::new(callback));
+        id
+    }
+
+    /// Run a callback when the gump is closed without pressing a button.
+    pub fn on_close(&mut self, callback: impl Fn(&mut GumpAction<S>) + Send + Sync + 'static) -> &mut Self {
+        self.output.on_close = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn allocate_text_entry(&mut self) -> TextEntryId {
+        let id = self.output.next_entry_id;
+        self.output.next_entry_id += 1;
+        TextEntryId(id)
+    }
+
+    pub fn allocate_switch(&mut self) -> SwitchId {
+        let id = self.output.next_switch_id;
+        self.output.next_switch_id += 1;
+        SwitchId(id)
+    }
+}
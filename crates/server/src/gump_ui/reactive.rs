@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bevy_app::{App, CoreSet};
+use bevy_ecs::prelude::*;
+use glam::IVec2;
+
+use yewoh::protocol::GumpLayout;
+
+use crate::gump_ui::{GumpCallback, GumpFont, render_gump, SwitchId, TextEntryId, Ui};
+use crate::world::gump::{Gump, GumpResponse};
+
+type GumpView<S> = Arc<dyn Fn(&S, &mut Ui<S>) + Send + Sync>;
+
+/// The context passed to button callbacks.
+pub struct GumpAction<'a, 'w, 's, S> {
+    pub commands: &'a mut Commands<'w, 's>,
+    /// The state the gump is bound to.
+    pub state: &'a mut S,
+    pub response: &'a GumpResponse,
+    pub client_entity: Entity,
+    pub gump: Entity,
+    close: bool,
+}
+
+impl<'a, 'w, 's, S> GumpAction<'a, 'w, 's, S> {
+    /// Close the gump after this callback, instead of showing it again.
+    pub fn close(&mut self) {
+        self.close = true;
+    }
+
+    /// Show the gump again after this callback, even if it was closed by the client.
+    pub fn keep_open(&mut self) {
+        self.close = false;
+    }
+
+    pub fn text(&self, entry: TextEntryId) -> Option<&str> {
+        self.response.text(entry.0)
+    }
+
+    pub fn is_checked(&self, switch: SwitchId) -> bool {
+        self.response.is_switch_on(switch.0)
+    }
+}
+
+/// A gump built by a view function, bound to a component on another entity.
+///
+/// The view is run again whenever the bound component changes, and after every button
+/// press, and the gump is resent if the layout has changed. Pressing a button runs its
+/// callback with mutable access to the bound component, and the gump stays open unless
+/// the callback closes it. Closing the gump from the client closes it for good, unless an
+/// [`Ui::on_close`] callback keeps it open.
+///
+/// The gump is closed if the bound entity is despawned.
+///
+/// Each state type must be registered with
+/// [`ReactiveGumpRegistrationExt::add_reactive_gump`].
+#[derive(Component)]
+pub struct ReactiveGump<S: Component> {
+    pub client_entity: Entity,
+    pub state_entity: Entity,
+    pub type_id: u32,
+    pub position: IVec2,
+    view: GumpView<S>,
+    callbacks: HashMap<u32, GumpCallback<S>>,
+    on_close: Option<GumpCallback<S>>,
+    needs_render: bool,
+}
+
+impl<S: Component> ReactiveGump<S> {
+    pub fn new(
+        client_entity: Entity,
+        state_entity: Entity,
+        type_id: u32,
+        view: impl Fn(&S, &mut Ui<S>) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            client_entity,
+            state_entity,
+            type_id,
+            position: IVec2::new(50, 50),
+            view: Arc::new(view),
+            callbacks: HashMap::new(),
+            on_close: None,
+            needs_render: true,
+        }
+    }
+
+    pub fn with_position(mut self, position: IVec2) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Run the view again, even if the bound state hasn't changed.
+    pub fn refresh(&mut self) {
+        self.needs_render = true;
+    }
+}
+
+fn same_layout(a: &GumpLayout, b: &GumpLayout) -> bool {
+    a.layout == b.layout && a.text == b.text
+}
+
+pub fn render_reactive_gumps<S: Component>(
+    mut commands: Commands,
+    font: Option<Res<GumpFont>>,
+    states: Query<Ref<S>>,
+    mut gumps: Query<(Entity, &mut ReactiveGump<S>, Option<&mut Gump>), Without<GumpResponse>>,
+) {
+    for (entity, mut reactive, gump) in &mut gumps {
+        let state = match states.get(reactive.state_entity) {
+            Ok(x) => x,
+            _ => {
+                commands.entity(entity).despawn();
+                continue;
+            }
+        };
+
+        if !reactive.needs_render && !state.is_changed() {
+            continue;
+        }
+
+        let view = reactive.view.clone();
+        let rendered = render_gump(font.as_ref().map(|f| &f.0), |ui| view(&state, ui));
+        let force = reactive.needs_render;
+        reactive.callbacks = rendered.callbacks;
+        reactive.on_close = rendered.on_close;
+        reactive.needs_render = false;
+
+        match gump {
+            Some(mut gump) => {
+                // A gump which was answered has been closed on the client and always needs resending.
+                if force || !same_layout(&gump.layout, &rendered.layout) {
+                    gump.layout = rendered.layout;
+                }
+            }
+            None => {
+                commands.entity(entity).insert(Gump {
+                    client_entity: reactive.client_entity,
+                    type_id: reactive.type_id,
+                    position: reactive.position,
+                    layout: rendered.layout,
+                });
+            }
+        }
+    }
+}
+
+pub fn handle_reactive_gump_responses<S: Component>(
+    mut commands: Commands,
+    mut states: Query<&mut S>,
+    mut gumps: Query<(Entity, &Gump, &GumpResponse, &mut ReactiveGump<S>), Added<GumpResponse>>,
+) {
+    for (entity, gump, response, mut reactive) in &mut gumps {
+        let callback = if response.is_cancelled() {
+            reactive.on_close.clone()
+        } else {
+            reactive.callbacks.get(&response.button_id).cloned()
+        };
+
+        let mut close = response.is_cancelled();
+        if let (Some(callback), Ok(mut state)) = (callback, states.get_mut(reactive.state_entity)) {
+            let mut action = GumpAction {
+                commands: &mut commands,
+                state: &mut *state,
+                response,
+                client_entity: gump.client_entity,
+                gump: entity,
+                close,
+            };
+            callback(&mut action);
+            close = action.close;
+        }
+
+        if !close {
+            commands.entity(entity).remove::<GumpResponse>();
+            reactive.refresh();
+        }
+    }
+}
+
+pub trait ReactiveGumpRegistrationExt {
+    fn add_reactive_gump<S: Component>(&mut self) -> &mut Self;
+}
+
+impl ReactiveGumpRegistrationExt for App {
+    fn add_reactive_gump<S: Component>(&mut self) -> &mut Self {
+        // Responses are handled during the update so that the removed responses are
+        // applied before rendering.
+        self
+            .add_system(handle_reactive_gump_responses::<S>.in_base_set(CoreSet::Update))
+            .add_system(render_reactive_gumps::<S>.in_base_set(CoreSet::PostUpdate))
+    }
+}
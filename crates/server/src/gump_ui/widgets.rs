@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use bevy_ecs::prelude::*;
+use glam::IVec2;
+
+use yewoh::assets::fonts::FontMetrics;
+
+use crate::gump_builder::fit_html;
+use crate::gump_ui::{GumpAction, SwitchId, TextEntryId, Ui};
+
+const BUTTON_UP: u32 = 0xfa5;
+const BUTTON_DOWN: u32 = 0xfa7;
+const PREVIOUS_UP: u32 = 0xfae;
+const PREVIOUS_DOWN: u32 = 0xfb0;
+const BUTTON_SIZE: IVec2 = IVec2::new(30, 22);
+const CHECKBOX_OFF: u32 = 0xd2;
+const CHECKBOX_ON: u32 = 0xd3;
+const CHECKBOX_SIZE: IVec2 = IVec2::new(20, 20);
+const TEXT_ENTRY_BACKGROUND: u32 = 0xbbc;
+const DIALOG_BACKGROUND: u32 = 0x13be;
+const DIALOG_PADDING: i32 = 16;
+
+impl<'a, S: Component> Ui<'a, S> {
+    /// Add a single line of text.
+    pub fn label(&mut self, content: impl Into<String>) -> &mut Self {
+        self.label_hue(content, 0)
+    }
+
+    pub fn label_hue(&mut self, content: impl Into<String>, hue: u16) -> &mut Self {
+        let content = content.into();
+        let size = self.measure(&content);
+        self.add_raw(size, |builder, text, position| {
+            builder.add_text(text.intern(content), hue, position);
+        })
+    }
+
+    /// Add an HTML area `width` pixels wide, sized to fit its contents with [`fit_html`].
+    pub fn html(&mut self, content: impl Into<String>, width: u32, max_height: u32) -> &mut Self {
+        let content = content.into();
+        let (size, scrollbar) = fit_html(&self.font, &content, width, max_height);
+        self.add_raw(size, |builder, text, position| {
+            builder.add_html(text.intern(content), false, scrollbar, position, size);
+        })
+    }
+
+    pub fn image(&mut self, image_id: u32, size: IVec2) -> &mut Self {
+        self.add_raw(size, |builder, _, position| {
+            builder.add_image(image_id, position);
+        })
+    }
+
+    /// Add a reply button which runs `callback` when pressed.
+    pub fn image_button(
+        &mut self,
+        up_texture_id: u32,
+        down_texture_id: u32,
+        size: IVec2,
+        callback: impl Fn(&mut GumpAction<S>) + Send + Sync + 'static,
+    ) -> &mut Self {
+        let button_id = self.register_button(callback);
+        self.add_raw(size, |builder, _, position| {
+            builder.add_button(up_texture_id, down_texture_id, button_id, 0, true, position);
+        })
+    }
+
+    /// Add a labelled reply button which runs `callback` when pressed.
+    pub fn button(
+        &mut self,
+        content: impl Into<String>,
+        callback: impl Fn(&mut GumpAction<S>) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.row(|ui| {
+            ui.image_button(BUTTON_UP, BUTTON_DOWN, BUTTON_SIZE, callback);
+            ui.label(content);
+        });
+        self
+    }
+
+    fn page_button(&mut self, up_texture_id: u32, down_texture_id: u32, page: usize) {
+        self.add_raw(BUTTON_SIZE, |builder, _, position| {
+            builder.add_button(up_texture_id, down_texture_id, 0, page as u16, false, position);
+        });
+    }
+
+    /// Add a labelled checkbox, returning the switch to look up in the response.
+    pub fn checkbox(&mut self, content: impl Into<String>, checked: bool) -> SwitchId {
+        let switch = self.allocate_switch();
+        self.row(|ui| {
+            ui.add_raw(CHECKBOX_SIZE, |builder, _, position| {
+                builder.add_checkbox(CHECKBOX_OFF, CHECKBOX_ON, checked, switch.0, position);
+            });
+            ui.label(content);
+        });
+        switch
+    }
+
+    /// Add a text entry, returning the ID to look up its contents in the response.
+    pub fn text_entry(&mut self, width: u32, default: impl Into<String>) -> TextEntryId {
+        let entry = self.allocate_text_entry();
+        let size = IVec2::new(width as i32, self.font.line_height() as i32 + 4);
+        let default = default.into();
+        self.add_raw(size, |builder, text, position| {
+            builder
+                .add_image_tiled(TEXT_ENTRY_BACKGROUND, position, size)
+                .add_text_entry(entry.0 as u32, text.intern(default), 0, position + IVec2::new(2, 2), size - IVec2::new(4, 4));
+        });
+        entry
+    }
+
+    /// Add a text entry with a label in front of it.
+    ///
+    /// The label is given a fixed width so that entries in a column line up.
+    pub fn labelled_text_entry(
+        &mut self,
+        content: impl Into<String>,
+        label_width: u32,
+        entry_width: u32,
+        default: impl Into<String>,
+    ) -> TextEntryId {
+        self.row(|ui| {
+            let content = content.into();
+            let size = IVec2::new(label_width as i32, ui.font.line_height() as i32);
+            ui.add_raw(size, |builder, text, position| {
+                builder.add_text_cropped(text.intern(content), 0, position, size);
+            });
+            ui.text_entry(entry_width, default)
+        })
+    }
+
+    fn page_footer(&mut self, index: usize, pages: usize, previous: impl FnOnce(&mut Ui<S>), next: impl FnOnce(&mut Ui<S>)) {
+        self.row(|ui| {
+            if index > 0 {
+                previous(ui);
+            }
+            ui.label(format!("Page {} of {}", index + 1, pages));
+            if index + 1 < pages {
+                next(ui);
+            }
+        });
+    }
+
+    /// Show a page of `per_page` items from a list of `len` items, with buttons to move
+    /// between pages.
+    ///
+    /// Each page is fetched from the server, so this can be used anywhere and with lists of
+    /// any length. The current page should be kept in the bound state: it's passed in as
+    /// `page`, and `set_page` is called to change it.
+    pub fn paginated_list(
+        &mut self,
+        len: usize,
+        per_page: usize,
+        page: usize,
+        set_page: impl Fn(&mut S, usize) + Send + Sync + 'static,
+        mut item: impl FnMut(&mut Ui<S>, usize),
+    ) -> &mut Self {
+        let per_page = per_page.max(1);
+        let pages = len.div_ceil(per_page).max(1);
+        let page = page.min(pages - 1);
+        let set_page = Arc::new(set_page);
+
+        self.column(|ui| {
+            for index in (page * per_page)..((page + 1) * per_page).min(len) {
+                ui.row(|ui| item(ui, index));
+            }
+
+            let set_previous = set_page.clone();
+            ui.page_footer(
+                page,
+                pages,
+                |ui| {
+                    ui.image_button(PREVIOUS_UP, PREVIOUS_DOWN, BUTTON_SIZE,
+                        move |action| set_previous(action.state, page - 1));
+                },
+                |ui| {
+                    ui.image_button(BUTTON_UP, BUTTON_DOWN, BUTTON_SIZE,
+                        move |action| set_page(action.state, page + 1));
+                },
+            );
+        });
+        self
+    }
+
+    /// Show a list of `len` items split over client-side pages of `per_page` items.
+    ///
+    /// Moving between pages doesn't involve the server, but client-side pages can't be
+    /// nested, and only the first set of pages in a gump is shown when it opens.
+    pub fn scroll_pages(&mut self, len: usize, per_page: usize, mut item: impl FnMut(&mut Ui<S>, usize)) -> &mut Self {
+        let per_page = per_page.max(1);
+        let pages = len.div_ceil(per_page).max(1);
+        let nested = self.page != 0;
+
+        self.add_pages(pages, |ui, index, page| {
+            for item_index in (index * per_page)..((index + 1) * per_page).min(len) {
+                ui.row(|ui| item(ui, item_index));
+            }
+
+            if !nested {
+                ui.page_footer(
+                    index,
+                    pages,
+                    |ui| ui.page_button(PREVIOUS_UP, PREVIOUS_DOWN, page - 1),
+                    |ui| ui.page_button(BUTTON_UP, BUTTON_DOWN, page + 1),
+                );
+            }
+        });
+        self
+    }
+
+    /// Show a row of tabs, with the contents of each tab on its own client-side page.
+    ///
+    /// Tabs are subject to the same restrictions as [`Ui::scroll_pages`].
+    pub fn tabs(&mut self, labels: &[&str], mut build: impl FnMut(&mut Ui<S>, usize)) -> &mut Self {
+        let first_page = self.output.pages.len();
+        let nested = self.page != 0;
+
+        self.column(|ui| {
+            ui.row(|ui| {
+                for (index, label) in labels.iter().enumerate() {
+                    if !nested {
+                        ui.page_button(BUTTON_UP, BUTTON_DOWN, first_page + index);
+                    }
+                    ui.label(*label);
+                }
+            });
+            ui.add_pages(labels.len(), |ui, index, _| build(ui, index));
+        });
+        self
+    }
+
+    /// Show a message on a background with "Yes" and "No" buttons.
+    ///
+    /// Closing the gump counts as pressing "No".
+    pub fn confirm_dialog(
+        &mut self,
+        message: impl Into<String>,
+        width: u32,
+        on_confirm: impl Fn(&mut GumpAction<S>) + Send + Sync + 'static,
+        on_cancel: impl Fn(&mut GumpAction<S>) + Send + Sync + 'static,
+    ) -> &mut Self {
+        let on_cancel = Arc::new(on_cancel);
+        let on_no = on_cancel.clone();
+
+        self.panel(DIALOG_BACKGROUND, DIALOG_PADDING, |ui| {
+            ui.html(message, width, 200);
+            ui.row(|ui| {
+                ui.button("Yes", on_confirm);
+                ui.button("No", move |action| on_no(action));
+            });
+        });
+        self.on_close(move |action| on_cancel(action))
+    }
+}
@@ -2,5 +2,6 @@ pub mod lobby;
 pub mod game_server;
 pub mod world;
 pub mod gump_builder;
+pub mod gump_ui;
 pub mod async_runtime;
 pub mod math;
@@ -1,5 +1,5 @@
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, DerefMut};
 
 use bevy_ecs::entity::{EntityMap, MapEntities, MapEntitiesError};
@@ -50,6 +50,51 @@ impl DerefMut for Notorious {
     fn deref_mut(&mut self) -> &mut Self::Target { &mut self.0 }
 }
 
+/// Notoriety shown to particular observers in place of [`Notorious`], keyed by the
+/// observing character.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Component)]
+pub struct NotorietyOverrides {
+    pub overrides: HashMap<Entity, Notoriety>,
+}
+
+impl NotorietyOverrides {
+    /// The notoriety `observer` should see, given the base notoriety.
+    pub fn towards(&self, observer: Entity, notoriety: Notoriety) -> Notoriety {
+        self.overrides.get(&observer).copied().unwrap_or(notoriety)
+    }
+}
+
+/// Restricts which observers can see an entity.
+///
+/// Entities without this component are visible to everyone. Entities with it are only
+/// visible to the characters listed, to themselves, and to observers with
+/// [`SeesEverything`]. Items inside containers or equipped by a character are only visible
+/// if both they and their parent are.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Component)]
+pub struct VisibleTo {
+    pub observers: HashSet<Entity>,
+}
+
+impl VisibleTo {
+    /// Visible to no-one except observers with [`SeesEverything`].
+    pub fn nobody() -> VisibleTo {
+        Default::default()
+    }
+
+    pub fn only(observer: Entity) -> VisibleTo {
+        VisibleTo { observers: [observer].into_iter().collect() }
+    }
+
+    pub fn can_see(&self, observer: Entity) -> bool {
+        self.observers.contains(&observer)
+    }
+}
+
+/// Marks a character which can see every entity, regardless of [`VisibleTo`].
+#[derive(Debug, Clone, Copy, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct SeesEverything;
+
 #[derive(Debug, Clone, Eq, PartialEq, Reflect, FromReflect)]
 pub struct CharacterEquipped {
     pub entity: Entity,
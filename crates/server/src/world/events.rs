@@ -3,7 +3,7 @@ use std::sync::Arc;
 use bevy_ecs::prelude::*;
 use glam::IVec3;
 
-use yewoh::protocol::{AnyPacket, CreateCharacter, DeleteCharacter, EquipmentSlot, Move, SelectCharacter, UnicodeTextMessageRequest};
+use yewoh::protocol::{AnyPacket, CreateCharacter, DeleteCharacter, EquipmentSlot, Move, SelectCharacter, SharedPacket, UnicodeTextMessageRequest};
 
 #[derive(Debug)]
 pub struct ReceivedPacketEvent {
@@ -14,7 +14,7 @@ pub struct ReceivedPacketEvent {
 #[derive(Debug)]
 pub struct SentPacketEvent {
     pub client_entity: Option<Entity>,
-    pub packet: Arc<AnyPacket>,
+    pub packet: Arc<SharedPacket>,
 }
 
 #[derive(Debug, Clone)]
@@ -107,6 +107,12 @@ pub struct RequestSkillsEvent {
     pub target: Entity,
 }
 
+#[derive(Debug, Clone)]
+pub struct UseSkillEvent {
+    pub client_entity: Entity,
+    pub skill_id: u16,
+}
+
 #[derive(Debug, Clone)]
 pub struct AttackRequestedEvent {
     pub client_entity: Entity,
@@ -0,0 +1,274 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy_ecs::prelude::*;
+use glam::IVec2;
+
+use yewoh::protocol::{CloseGump, ExtendedCommand, GumpLayout, GumpResult, GumpTextField, OpenGump};
+
+use crate::world::events::ReceivedPacketEvent;
+use crate::world::net::NetClient;
+
+/// A gump shown to a client.
+///
+/// Spawn an entity with this component to open a gump, and despawn it to close it again.
+/// Changing the gump sends the new layout to the client.
+///
+/// If the client already has a gump open with the same `type_id`, this one is sent with a
+/// unique type ID instead so that each can be closed separately.
+#[derive(Debug, Clone, Component)]
+pub struct Gump {
+    pub client_entity: Entity,
+    pub type_id: u32,
+    pub position: IVec2,
+    pub layout: GumpLayout,
+}
+
+/// The serial of the last copy of a gump sent to the client.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct SentGump {
+    pub serial: u32,
+}
+
+/// Type IDs at or above this are allocated to gumps whose own type ID is already open.
+const UNIQUE_TYPE_ID_BASE: u32 = 0x8000_0000;
+
+#[derive(Debug, Clone, Copy)]
+struct OpenGumpEntry {
+    entity: Entity,
+    /// The type ID the gump was sent with, which is unique amongst the client's open gumps.
+    type_id: u32,
+}
+
+/// The gumps which are open on a client.
+#[derive(Debug, Clone, Default, Component)]
+pub struct OpenGumps {
+    next_serial: u32,
+    gumps: HashMap<u32, OpenGumpEntry>,
+    /// Gumps closed by the server, which the client will still respond to.
+    closing: HashSet<u32>,
+}
+
+impl OpenGumps {
+    fn allocate_serial(&mut self) -> u32 {
+        self.next_serial = self.next_serial.wrapping_add(1).max(1);
+        self.next_serial
+    }
+
+    /// Pick the type ID to send a gump with.
+    ///
+    /// The client closes gumps by type ID, so two open gumps can't share one.
+    fn allocate_type_id(&self, type_id: u32, serial: u32) -> u32 {
+        let in_use = |type_id| self.gumps.values().any(|e| e.type_id == type_id);
+        if !in_use(type_id) {
+            return type_id;
+        }
+
+        let mut candidate = UNIQUE_TYPE_ID_BASE | serial;
+        while in_use(candidate) {
+            candidate = UNIQUE_TYPE_ID_BASE | candidate.wrapping_add(1);
+        }
+        candidate
+    }
+
+    fn close(&mut self, client: &NetClient, serial: u32) {
+        if let Some(entry) = self.gumps.remove(&serial) {
+            self.closing.insert(serial);
+            client.send_packet(ExtendedCommand::CloseGump(CloseGump {
+                type_id: entry.type_id,
+                button_id: 0,
+            }).into());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.gumps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gumps.is_empty()
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.gumps.values().map(|e| e.entity)
+    }
+}
+
+/// The client and serial each sent gump entity is open under.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct GumpIndex {
+    gumps: HashMap<Entity, (Entity, u32)>,
+}
+
+/// A client's response to a gump.
+///
+/// This is added to the gump entity when the response arrives, and the gump is despawned
+/// at the end of the frame.
+#[derive(Debug, Clone, Component)]
+pub struct GumpResponse {
+    pub button_id: u32,
+    pub switches: Vec<u32>,
+    pub text_fields: Vec<GumpTextField>,
+}
+
+impl GumpResponse {
+    /// Whether the gump was closed without pressing a button.
+    pub fn is_cancelled(&self) -> bool {
+        self.button_id == 0
+    }
+
+    pub fn is_switch_on(&self, switch_id: u32) -> bool {
+        self.switches.contains(&switch_id)
+    }
+
+    pub fn text(&self, field_id: u16) -> Option<&str> {
+        self.text_fields.iter()
+            .find(|f| f.id == field_id)
+            .map(|f| f.text.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GumpResponseEvent {
+    pub client_entity: Entity,
+    pub gump: Entity,
+    pub response: GumpResponse,
+}
+
+pub fn handle_gump_responses(
+    mut commands: Commands,
+    mut index: ResMut<GumpIndex>,
+    mut events: EventReader<ReceivedPacketEvent>,
+    mut response_events: EventWriter<GumpResponseEvent>,
+    mut clients: Query<&mut OpenGumps>,
+) {
+    for ReceivedPacketEvent { client_entity, packet } in events.iter() {
+        let client_entity = *client_entity;
+        let result = match packet.downcast::<GumpResult>() {
+            Some(x) => x,
+            None => continue,
+        };
+
+        let mut open_gumps = match clients.get_mut(client_entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        // Closing a gump from the server makes the client respond to it.
+        if open_gumps.closing.remove(&result.id) {
+            if result.button_id != 0 {
+                log::debug!("gump {} from {client_entity:?} was closed but answered with button {}",
+                    result.id, result.button_id);
+            }
+            continue;
+        }
+
+        let entry = match open_gumps.gumps.get(&result.id) {
+            Some(x) => *x,
+            None => {
+                log::debug!("ignoring response to unsent gump {} from {client_entity:?}", result.id);
+                continue;
+            }
+        };
+
+        if entry.type_id != result.type_id {
+            log::warn!("gump response from {client_entity:?} has type {} but gump {} was sent with type {}",
+                result.type_id, result.id, entry.type_id);
+            continue;
+        }
+
+        open_gumps.gumps.remove(&result.id);
+        index.gumps.remove(&entry.entity);
+
+        let response = GumpResponse {
+            button_id: result.button_id,
+            switches: result.on_switches.clone(),
+            text_fields: result.text_fields.clone(),
+        };
+        commands.entity(entry.entity).insert(response.clone());
+        response_events.send(GumpResponseEvent {
+            client_entity,
+            gump: entry.entity,
+            response,
+        });
+    }
+}
+
+pub fn send_gumps(
+    mut commands: Commands,
+    mut index: ResMut<GumpIndex>,
+    mut clients: Query<(&NetClient, &mut OpenGumps)>,
+    gumps: Query<(Entity, &Gump, Option<&SentGump>), (Changed<Gump>, Without<GumpResponse>)>,
+) {
+    for (entity, gump, sent) in &gumps {
+        let (client, mut open_gumps) = match clients.get_mut(gump.client_entity) {
+            Ok(x) => x,
+            _ => {
+                commands.entity(entity).despawn();
+                continue;
+            }
+        };
+
+        if let Some(sent) = sent {
+            open_gumps.close(client, sent.serial);
+        }
+
+        let serial = open_gumps.allocate_serial();
+        let type_id = open_gumps.allocate_type_id(gump.type_id, serial);
+        open_gumps.gumps.insert(serial, OpenGumpEntry { entity, type_id });
+        index.gumps.insert(entity, (gump.client_entity, serial));
+        commands.entity(entity).insert(SentGump { serial });
+        client.send_packet(OpenGump {
+            id: serial,
+            type_id,
+            position: gump.position,
+            layout: gump.layout.clone(),
+        }.into());
+    }
+}
+
+pub fn close_removed_gumps(
+    mut index: ResMut<GumpIndex>,
+    mut removed: RemovedComponents<Gump>,
+    mut clients: Query<(&NetClient, &mut OpenGumps)>,
+) {
+    for entity in removed.iter() {
+        let (client_entity, serial) = match index.gumps.remove(&entity) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        let (client, mut open_gumps) = match clients.get_mut(client_entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        open_gumps.close(client, serial);
+    }
+}
+
+/// Despawn the gumps of clients which have disconnected.
+pub fn despawn_orphaned_gumps(
+    mut commands: Commands,
+    mut removed: RemovedComponents<NetClient>,
+    gumps: Query<(Entity, &Gump)>,
+) {
+    let clients = removed.iter().collect::<HashSet<_>>();
+    if clients.is_empty() {
+        return;
+    }
+
+    for (entity, gump) in &gumps {
+        if clients.contains(&gump.client_entity) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn despawn_answered_gumps(
+    mut commands: Commands,
+    gumps: Query<Entity, (With<Gump>, With<GumpResponse>)>,
+) {
+    for entity in &gumps {
+        commands.entity(entity).despawn();
+    }
+}
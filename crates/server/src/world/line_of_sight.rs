@@ -0,0 +1,223 @@
+use bevy_ecs::entity::Entity;
+use glam::{IVec3, Vec3};
+
+use yewoh::assets::tiles::{TileData, TileFlags};
+
+use crate::world::entity::Location;
+use crate::world::map_storage::MapStorage;
+use crate::world::navigation::{land_heights, MovementTerrain, surface_tiles, WorldTerrain};
+use crate::world::spatial::EntitySurfaces;
+
+/// The height above a character's feet that line of sight is measured from.
+pub const EYE_HEIGHT: i32 = 15;
+
+/// What stopped a line of sight check from succeeding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineOfSightBlocker {
+    /// The two locations are on different maps.
+    DifferentMap,
+    /// The line passes underneath the land at this position.
+    Land(IVec3),
+    /// The line passes through a static at this position.
+    Static(IVec3),
+    /// The line passes through a dynamic item or a multi.
+    Item(Entity),
+}
+
+/// Whether an item with these flags blocks line of sight.
+///
+/// Windows block line of sight like walls do: they can be seen through on the client but
+/// can't be shot or cast through.
+pub fn blocks_line_of_sight(flags: TileFlags) -> bool {
+    flags.contains(TileFlags::BLOCK_LOS)
+        || flags.contains(TileFlags::WINDOW)
+        || flags.contains(TileFlags::WALL | TileFlags::IMPASSABLE)
+}
+
+fn item_blocks(flags: TileFlags, min_z: i32, max_z: i32, z: i32) -> bool {
+    blocks_line_of_sight(flags) && z >= min_z && z <= max_z.max(min_z + 1)
+}
+
+/// The tiles crossed by the line from `start` to `end`, not including either end.
+fn line_points(start: IVec3, end: IVec3) -> Vec<IVec3> {
+    let delta = end - start;
+    let steps = delta.x.abs().max(delta.y.abs());
+    let step = delta.as_vec3() / steps.max(1) as f32;
+
+    let mut points = Vec::with_capacity(steps.max(1) as usize);
+    for i in 1..steps {
+        let point = (start.as_vec3() + step * i as f32 + Vec3::splat(0.5)).floor().as_ivec3();
+        if points.last() != Some(&point) {
+            points.push(point);
+        }
+    }
+    points
+}
+
+/// Check for an unobstructed line between two locations.
+///
+/// The line is traced tile by tile between the two positions, interpolating the height.
+/// It's blocked if it passes below the land, or through a static or dynamic item which
+/// blocks line of sight. Anything on the start and end tiles is ignored, so that targets
+/// standing inside or on top of an item can still be seen.
+///
+/// Positions are used as-is, so callers checking between characters will usually want to
+/// raise both by [`EYE_HEIGHT`].
+pub fn line_of_sight(
+    map: &MapStorage,
+    surfaces: &EntitySurfaces,
+    tile_data: &TileData,
+    from: &Location,
+    to: &Location,
+) -> Result<(), LineOfSightBlocker> {
+    let terrain = WorldTerrain { map, surfaces, tile_data, ignore: None };
+    check_line_of_sight(&terrain, from, to)
+}
+
+/// Check for an unobstructed line between two locations over any terrain.
+///
+/// See [`line_of_sight`].
+pub fn check_line_of_sight(
+    terrain: &impl MovementTerrain,
+    from: &Location,
+    to: &Location,
+) -> Result<(), LineOfSightBlocker> {
+    if from.map_id != to.map_id {
+        return Err(LineOfSightBlocker::DifferentMap);
+    }
+
+    let map_id = from.map_id;
+    // Dynamic items and multis are looked up once for the whole line, rather than per tile.
+    let crossed = terrain.surfaces().tree
+        .iter_line(map_id, from.position.truncate(), to.position.truncate())
+        .collect::<Vec<_>>();
+    let mut items = Vec::new();
+    for point in line_points(from.position, to.position) {
+        let position = point.truncate();
+
+        if let Some(land) = land_heights(terrain, map_id, position) {
+            if !land.tile.is_ignored() && point.z < land.center {
+                return Err(LineOfSightBlocker::Land(point));
+            }
+        }
+
+        items.clear();
+        terrain.statics(map_id, position, &mut items);
+        for (entity, kind) in &crossed {
+            surface_tiles(terrain.tile_data(), *entity, kind, position, &mut items);
+        }
+
+        for item in &items {
+            if item_blocks(item.flags, item.z, item.z + item.height, point.z) {
+                return Err(match item.entity {
+                    Some(entity) => LineOfSightBlocker::Item(entity),
+                    None => LineOfSightBlocker::Static(position.extend(item.z)),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check line of sight between the eyes of two characters.
+pub fn can_see(
+    map: &MapStorage,
+    surfaces: &EntitySurfaces,
+    tile_data: &TileData,
+    from: &Location,
+    to: &Location,
+) -> bool {
+    let eye = IVec3::new(0, 0, EYE_HEIGHT);
+    let from = Location { position: from.position + eye, ..*from };
+    let to = Location { position: to.position + eye, ..*to };
+    line_of_sight(map, surfaces, tile_data, &from, &to).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::IVec3;
+
+    use yewoh::Direction;
+
+    use crate::world::navigation::tests::{GRASS, TestTerrain, WALL, WINDOW};
+
+    use super::*;
+
+    fn check(terrain: &TestTerrain, from: IVec3, to: IVec3) -> Result<(), LineOfSightBlocker> {
+        let from = Location { map_id: 0, position: from, direction: Direction::North };
+        let to = Location { map_id: 0, position: to, direction: Direction::North };
+        check_line_of_sight(terrain, &from, &to)
+    }
+
+    #[test]
+    fn clear_over_flat_ground() {
+        let terrain = TestTerrain::flat();
+        assert_eq!(check(&terrain, IVec3::new(1, 3, 10), IVec3::new(7, 3, 10)), Ok(()));
+    }
+
+    #[test]
+    fn walls_block() {
+        let mut terrain = TestTerrain::flat();
+        terrain.add_static(4, 3, 0, WALL);
+        assert_eq!(check(&terrain, IVec3::new(1, 3, 10), IVec3::new(7, 3, 10)),
+            Err(LineOfSightBlocker::Static(IVec3::new(4, 3, 0))));
+
+        // Looking over the top of the wall is fine.
+        assert_eq!(check(&terrain, IVec3::new(1, 3, 30), IVec3::new(7, 3, 30)), Ok(()));
+    }
+
+    #[test]
+    fn windows_block() {
+        let mut terrain = TestTerrain::flat();
+        terrain.add_static(4, 3, 0, WINDOW);
+        assert_eq!(check(&terrain, IVec3::new(1, 3, 10), IVec3::new(7, 3, 10)),
+            Err(LineOfSightBlocker::Static(IVec3::new(4, 3, 0))));
+    }
+
+    #[test]
+    fn multis_block() {
+        let mut terrain = TestTerrain::flat();
+        let multi = Entity::from_raw(1);
+        terrain.add_multi(multi, IVec3::new(4, 2, 0), &[(IVec3::new(0, 1, 0), WALL)]);
+        assert_eq!(check(&terrain, IVec3::new(1, 3, 10), IVec3::new(7, 3, 10)),
+            Err(LineOfSightBlocker::Item(multi)));
+        assert_eq!(check(&terrain, IVec3::new(1, 2, 10), IVec3::new(7, 2, 10)), Ok(()));
+    }
+
+    #[test]
+    fn dynamic_items_block() {
+        let mut terrain = TestTerrain::flat();
+        let item = Entity::from_raw(1);
+        terrain.add_item(item, 4, 3, 0, WALL);
+        assert_eq!(check(&terrain, IVec3::new(1, 3, 10), IVec3::new(7, 3, 10)),
+            Err(LineOfSightBlocker::Item(item)));
+        assert_eq!(check(&terrain, IVec3::new(1, 1, 10), IVec3::new(7, 7, 10)), Ok(()));
+        assert_eq!(check(&terrain, IVec3::new(1, 4, 10), IVec3::new(7, 4, 10)), Ok(()));
+    }
+
+    #[test]
+    fn items_at_the_ends_are_ignored() {
+        let mut terrain = TestTerrain::flat();
+        terrain.add_static(1, 3, 0, WALL);
+        terrain.add_static(7, 3, 0, WALL);
+        assert_eq!(check(&terrain, IVec3::new(1, 3, 10), IVec3::new(7, 3, 10)), Ok(()));
+    }
+
+    #[test]
+    fn slopes_use_average_height() {
+        let mut terrain = TestTerrain::flat();
+        // A single raised corner only lifts the middle of the surrounding tiles a little.
+        terrain.set_land(4, 3, GRASS, 20);
+        assert_eq!(check(&terrain, IVec3::new(1, 3, 10), IVec3::new(7, 3, 10)), Ok(()));
+
+        // A raised plateau blocks the line.
+        for x in 3..7 {
+            for y in 2..6 {
+                terrain.set_land(x, y, GRASS, 20);
+            }
+        }
+        assert!(matches!(check(&terrain, IVec3::new(1, 3, 10), IVec3::new(7, 3, 10)),
+            Err(LineOfSightBlocker::Land(_))));
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use bevy_ecs::prelude::*;
+use byteorder::{ByteOrder, LittleEndian as Endian};
+use glam::{IVec2, UVec2};
+
+use yewoh::assets::map::{CHUNK_SIZE, load_map_patches, load_statics_patches, MAP_BLOCK_LENGTH, MapChunk, read_map_chunk, read_static_block, Static, STATIC_INDEX_ENTRY_LENGTH};
+use yewoh::assets::mul::MulFile;
+
+use crate::world::net::MapInfos;
+
+const DEFAULT_CACHE_CAPACITY: usize = 16384;
+
+/// The land and statics for a single 8x8 block of a map.
+#[derive(Debug, Clone, Default)]
+pub struct MapBlock {
+    pub chunk: MapChunk,
+    pub statics: Vec<Static>,
+}
+
+impl MapBlock {
+    pub fn land(&self, position: IVec2) -> (u16, i8) {
+        let size = CHUNK_SIZE as i32;
+        self.chunk.get(position.x.rem_euclid(size) as usize, position.y.rem_euclid(size) as usize)
+    }
+
+    pub fn statics_at(&self, position: IVec2) -> impl Iterator<Item = &Static> + '_ {
+        self.statics.iter().filter(move |s| s.position.truncate() == position)
+    }
+}
+
+#[derive(Debug)]
+struct MapFiles {
+    width_blocks: usize,
+    height_blocks: usize,
+    map: MulFile,
+    statics_index: MulFile,
+    statics: MulFile,
+    map_patches: HashMap<u32, MapChunk>,
+    statics_patches: HashMap<u32, Vec<Static>>,
+}
+
+impl MapFiles {
+    fn read_block(&self, block_x: usize, block_y: usize) -> anyhow::Result<MapBlock> {
+        let block = (block_x * self.height_blocks + block_y) as u32;
+
+        let chunk = if let Some(chunk) = self.map_patches.get(&block) {
+            chunk.clone()
+        } else {
+            let mut bytes = [0u8; MAP_BLOCK_LENGTH];
+            self.map.read_at(block as u64 * MAP_BLOCK_LENGTH as u64, &mut bytes)?;
+            read_map_chunk(&mut &bytes[..])?
+        };
+
+        let statics = if let Some(statics) = self.statics_patches.get(&block) {
+            statics.clone()
+        } else {
+            let mut entry = [0u8; STATIC_INDEX_ENTRY_LENGTH];
+            self.statics_index.read_at(block as u64 * STATIC_INDEX_ENTRY_LENGTH as u64, &mut entry)?;
+            let offset = Endian::read_u32(&entry[0..]);
+            let length = Endian::read_u32(&entry[4..]);
+
+            if offset == 0xffffffff || length == 0xffffffff {
+                Vec::new()
+            } else {
+                let mut bytes = vec![0u8; length as usize];
+                self.statics.read_at(offset as u64, &mut bytes)?;
+                read_static_block(&bytes, block_x, block_y)?
+            }
+        };
+
+        Ok(MapBlock { chunk, statics })
+    }
+}
+
+#[derive(Debug, Default)]
+struct BlockCache {
+    generation: u64,
+    blocks: HashMap<(u8, usize, usize), (Arc<MapBlock>, u64)>,
+}
+
+/// Map land and static data, paged in from the client files on demand.
+///
+/// Blocks are kept in a bounded cache, so that the server doesn't need to keep the whole
+/// world in memory or spawn an entity per chunk and static.
+///
+/// The files are read with positioned reads rather than memory-mapped: the freeze command can
+/// write statics files while the server is running, and if they replace the ones being read,
+/// a mapped file being truncated underneath the server would crash it instead of failing a read.
+#[derive(Debug, Resource)]
+pub struct MapStorage {
+    maps: HashMap<u8, Arc<MapFiles>>,
+    cache_capacity: usize,
+    cache: Mutex<BlockCache>,
+}
+
+impl Default for MapStorage {
+    fn default() -> Self {
+        Self {
+            maps: HashMap::new(),
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            cache: Default::default(),
+        }
+    }
+}
+
+impl MapStorage {
+    pub async fn open(map_infos: &MapInfos, uo_data_path: &Path) -> anyhow::Result<MapStorage> {
+        let mut maps = HashMap::new();
+
+        for (map_id, info) in map_infos.maps.iter().filter(|(_, m)| !m.is_virtual) {
+            let index = *map_id as usize;
+            let width = info.size.x as usize;
+            let height = info.size.y as usize;
+            let files = MapFiles {
+                width_blocks: width.div_ceil(CHUNK_SIZE),
+                height_blocks: height.div_ceil(CHUNK_SIZE),
+                map: MulFile::open(uo_data_path, &format!("map{}", index))?,
+                statics_index: MulFile::open(uo_data_path, &format!("staidx{}", index))?,
+                statics: MulFile::open(uo_data_path, &format!("statics{}", index))?,
+                map_patches: load_map_patches(uo_data_path, index).await?,
+                statics_patches: load_statics_patches(uo_data_path, index, height).await?,
+            };
+            maps.insert(*map_id, Arc::new(files));
+        }
+
+        Ok(MapStorage {
+            maps,
+            ..Default::default()
+        })
+    }
+
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = capacity.max(1);
+        self
+    }
+
+    /// The size of a map in blocks.
+    pub fn size_in_blocks(&self, map_id: u8) -> Option<UVec2> {
+        self.maps.get(&map_id)
+            .map(|m| UVec2::new(m.width_blocks as u32, m.height_blocks as u32))
+    }
+
+    pub fn block(&self, map_id: u8, block_x: usize, block_y: usize) -> Option<Arc<MapBlock>> {
+        let files = self.maps.get(&map_id)?;
+        if block_x >= files.width_blocks || block_y >= files.height_blocks {
+            return None;
+        }
+
+        let key = (map_id, block_x, block_y);
+        {
+            let mut cache = self.cache.lock().unwrap();
+            cache.generation += 1;
+            let generation = cache.generation;
+
+            if let Some((block, last_used)) = cache.blocks.get_mut(&key) {
+                *last_used = generation;
+                return Some(block.clone());
+            }
+        }
+
+        // Read without holding the cache lock, so that cache hits aren't held up by disk reads.
+        let block = match files.read_block(block_x, block_y) {
+            Ok(x) => Arc::new(x),
+            Err(err) => {
+                log::warn!("failed to read map {map_id} block {block_x},{block_y}: {err}");
+                return None;
+            }
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.generation += 1;
+        let generation = cache.generation;
+
+        // Another thread may have read the same block in the meantime.
+        if let Some((existing, last_used)) = cache.blocks.get_mut(&key) {
+            *last_used = generation;
+            return Some(existing.clone());
+        }
+
+        if cache.blocks.len() >= self.cache_capacity {
+            // Evict the least recently used half of the cache.
+            let mut ages = cache.blocks.values().map(|(_, g)| *g).collect::<Vec<_>>();
+            let middle = ages.len() / 2;
+            let threshold = *ages.select_nth_unstable(middle).1;
+            cache.blocks.retain(|_, (_, g)| *g > threshold);
+        }
+
+        cache.blocks.insert(key, (block.clone(), generation));
+        Some(block)
+    }
+
+    pub fn block_at(&self, map_id: u8, position: IVec2) -> Option<Arc<MapBlock>> {
+        if position.x < 0 || position.y < 0 {
+            return None;
+        }
+
+        let block_x = position.x as usize / CHUNK_SIZE;
+        let block_y = position.y as usize / CHUNK_SIZE;
+        self.block(map_id, block_x, block_y)
+    }
+
+    /// The statics of a map, for reading the whole map outside of the world update.
+    pub fn statics(&self, map_id: u8) -> Option<MapStatics> {
+        self.maps.get(&map_id).map(|files| MapStatics { files: files.clone() })
+    }
+}
+
+/// A handle to the statics of a single map.
+#[derive(Debug, Clone)]
+pub struct MapStatics {
+    files: Arc<MapFiles>,
+}
+
+impl MapStatics {
+    /// Visit every static on the map, without populating the cache.
+    ///
+    /// This reads every block of the map, so should be run on a blocking thread.
+    pub fn for_each(&self, mut callback: impl FnMut(&Static)) -> anyhow::Result<()> {
+        for block_x in 0..self.files.width_blocks {
+            for block_y in 0..self.files.height_blocks {
+                for item in &self.files.read_block(block_x, block_y)?.statics {
+                    callback(item);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,11 +1,14 @@
 use bevy_app::prelude::*;
 use bevy_ecs::prelude::*;
-use crate::world::entity::{AttackTarget, Character, Container, EquippedBy, Flags, Graphic, Location, Multi, Notorious, ParentContainer, Quantity, Stats, Tooltip};
+use crate::world::entity::{AttackTarget, Character, Container, EquippedBy, Flags, Graphic, Location, Multi, Notorious, ParentContainer, Quantity, SeesEverything, Stats, Tooltip};
 
-use crate::world::events::{AttackRequestedEvent, CharacterListEvent, ChatRequestEvent, ContextMenuEvent, CreateCharacterEvent, DeleteCharacterEvent, DoubleClickEvent, DropEvent, EquipEvent, MoveEvent, PickUpEvent, ProfileEvent, ReceivedPacketEvent, RequestSkillsEvent, SelectCharacterEvent, SentPacketEvent, SingleClickEvent};
+use crate::world::events::{AttackRequestedEvent, CharacterListEvent, ChatRequestEvent, ContextMenuEvent, CreateCharacterEvent, DeleteCharacterEvent, DoubleClickEvent, DropEvent, EquipEvent, MoveEvent, PickUpEvent, ProfileEvent, ReceivedPacketEvent, RequestSkillsEvent, SelectCharacterEvent, SentPacketEvent, SingleClickEvent, UseSkillEvent};
 use crate::world::input::{handle_attack_packets, handle_context_menu_packets, send_context_menu, update_targets};
-use crate::world::net::{accept_new_clients, add_new_entities_to_lookup, ContainerOpenedEvent, finish_synchronizing, handle_input_packets, handle_login_packets, handle_new_packets, MapInfos, NetEntityAllocator, NetEntityLookup, observe_ghosts, remove_old_entities_from_lookup, send_change_map, send_ghost_updates, send_opened_containers, send_tooltips, send_updated_attack_target, start_synchronizing};
-use crate::world::spatial::{EntityPositions, EntitySurfaces, NetClientPositions, update_client_positions, update_entity_positions, update_entity_surfaces};
+use crate::world::net::{accept_new_clients, add_new_entities_to_lookup, apply_view_settings, ContainerOpenedEvent, finish_synchronizing, handle_input_packets, handle_login_packets, handle_move_packets, handle_new_packets, handle_view_range_packets, MapInfos, MovementSettings, MovementViolationEvent, NetEntityAllocator, NetEntityLookup, observe_ghosts, remove_old_entities_from_lookup, send_change_map, send_fast_walk_keys, send_ghost_updates, send_opened_containers, send_tooltips, send_updated_attack_target, start_synchronizing, ViewSettings};
+use crate::world::gump::{close_removed_gumps, despawn_answered_gumps, despawn_orphaned_gumps, GumpIndex, GumpResponseEvent, handle_gump_responses, send_gumps};
+use crate::world::map_storage::MapStorage;
+use crate::world::navigation::{PathCache, PathfindingSettings, update_pathfinding};
+use crate::world::spatial::{EntityPositions, EntitySurfaces, NetClientPositions, update_client_positions, update_entity_positions, update_entity_surfaces, update_multi_surfaces};
 
 pub mod net;
 
@@ -17,10 +20,16 @@ pub mod spatial;
 
 pub mod navigation;
 
+pub mod line_of_sight;
+
 pub mod map;
 
+pub mod map_storage;
+
 pub mod input;
 
+pub mod gump;
+
 pub mod hierarchy;
 
 #[derive(SystemSet, Hash, Debug, Clone, Copy, PartialEq, Eq)]
@@ -44,6 +53,12 @@ impl Plugin for ServerPlugin {
             .init_resource::<NetEntityAllocator>()
             .init_resource::<NetEntityLookup>()
             .init_resource::<EntitySurfaces>()
+            .init_resource::<MapStorage>()
+            .init_resource::<MovementSettings>()
+            .init_resource::<ViewSettings>()
+            .init_resource::<PathfindingSettings>()
+            .init_resource::<PathCache>()
+            .init_resource::<GumpIndex>()
             .init_resource::<EntityPositions>()
             .init_resource::<NetClientPositions>()
             .register_type::<Flags>()
@@ -59,6 +74,7 @@ impl Plugin for ServerPlugin {
             .register_type::<Stats>()
             .register_type::<Tooltip>()
             .register_type::<AttackTarget>()
+            .register_type::<SeesEverything>()
             .add_event::<ReceivedPacketEvent>()
             .add_event::<SentPacketEvent>()
             .add_event::<CharacterListEvent>()
@@ -66,6 +82,8 @@ impl Plugin for ServerPlugin {
             .add_event::<SelectCharacterEvent>()
             .add_event::<DeleteCharacterEvent>()
             .add_event::<MoveEvent>()
+            .add_event::<MovementViolationEvent>()
+            .add_event::<GumpResponseEvent>()
             .add_event::<SingleClickEvent>()
             .add_event::<DoubleClickEvent>()
             .add_event::<PickUpEvent>()
@@ -74,6 +92,7 @@ impl Plugin for ServerPlugin {
             .add_event::<ContextMenuEvent>()
             .add_event::<ProfileEvent>()
             .add_event::<RequestSkillsEvent>()
+            .add_event::<UseSkillEvent>()
             .add_event::<ChatRequestEvent>()
             .add_event::<AttackRequestedEvent>()
             .add_event::<ContainerOpenedEvent>()
@@ -96,6 +115,10 @@ impl Plugin for ServerPlugin {
                 start_synchronizing,
                 handle_login_packets,
                 handle_input_packets,
+                handle_move_packets,
+                handle_view_range_packets,
+                apply_view_settings,
+                handle_gump_responses,
                 handle_context_menu_packets,
                 handle_attack_packets,
             ).in_set(ServerSet::HandlePackets))
@@ -107,6 +130,7 @@ impl Plugin for ServerPlugin {
                 observe_ghosts,
             ).in_set(ServerSet::SendGhosts))
             .add_systems((
+                send_fast_walk_keys,
                 send_context_menu,
                 send_tooltips,
                 send_ghost_updates.before(finish_synchronizing),
@@ -114,12 +138,18 @@ impl Plugin for ServerPlugin {
                 send_opened_containers.after(send_ghost_updates),
                 finish_synchronizing,
                 update_targets,
+                send_gumps,
+                close_removed_gumps,
             ).in_set(ServerSet::Send))
             .add_systems((
                 remove_old_entities_from_lookup,
+                despawn_answered_gumps,
+                despawn_orphaned_gumps,
             ).in_set(ServerSet::SendLast))
+            .add_system(update_pathfinding)
             .add_systems((
                 update_entity_surfaces,
+                update_multi_surfaces,
                 update_entity_positions,
                 update_client_positions,
             ).in_set(ServerSet::UpdateVisibility));
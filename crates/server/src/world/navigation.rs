@@ -1,55 +1,1156 @@
-use bevy_ecs::entity::Entity;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::sync::Arc;
+
+use bevy_ecs::prelude::*;
+use glam::{IVec2, IVec3};
+use yewoh::assets::map::Static;
 use yewoh::assets::tiles::{TileData, TileFlags};
 
 use yewoh::Direction;
 
 use crate::world::entity::Location;
+use crate::world::map::TileDataResource;
+use crate::world::map_storage::MapStorage;
 use crate::world::spatial::{EntitySurfaces, SurfaceKind};
 
+/// The height of a character, used to check for ceilings.
+pub const PERSON_HEIGHT: i32 = 16;
+
+/// How far a character can step up without a surface in between.
+pub const STEP_HEIGHT: i32 = 2;
+
 #[derive(Debug, Clone)]
 pub enum MoveError {
     Impassable,
     Obstructed(Entity),
 }
 
-pub fn try_move_in_direction(surfaces: &EntitySurfaces, tile_data: &TileData, position: Location, direction: Direction, ignore: Option<Entity>) -> Result<Location, MoveError> {
-    // Step forward and up 10 units, then drop the character down onto their destination.
-    let mut test_position = position.position + direction.as_vec2().extend(10);
-    let mut new_z = -1;
+/// A land tile, as far as movement is concerned.
+#[derive(Debug, Clone, Copy)]
+pub struct LandTile {
+    pub tile_id: u16,
+    pub z: i32,
+    pub flags: TileFlags,
+}
+
+impl LandTile {
+    /// Whether this land tile is one of the "no draw" tiles which the client ignores.
+    pub fn is_ignored(&self) -> bool {
+        self.tile_id == 2 || self.tile_id == 0x1db || (0x1ae..=0x1b5).contains(&self.tile_id)
+    }
+}
+
+/// A static or dynamic item, as far as movement is concerned.
+#[derive(Debug, Clone, Copy)]
+pub struct MovementTile {
+    pub z: i32,
+    pub height: i32,
+    pub flags: TileFlags,
+    pub entity: Option<Entity>,
+}
+
+impl MovementTile {
+    pub fn from_static(tile_data: &TileData, item: &Static) -> Option<MovementTile> {
+        let info = tile_data.items.get(item.graphic_id as usize)?;
+        Some(MovementTile {
+            z: item.position.z,
+            height: info.height as i32,
+            flags: info.flags,
+            entity: None,
+        })
+    }
+
+    /// Bridges and stairs are stood on half way up, so that they can be climbed.
+    pub fn is_bridge(&self) -> bool {
+        self.flags.intersects(TileFlags::BRIDGE | TileFlags::STAIR_BACK | TileFlags::STAIR_RIGHT)
+    }
+
+    /// The height above the base of this tile that a character stands at.
+    pub fn stand_height(&self) -> i32 {
+        if self.is_bridge() {
+            self.height / 2
+        } else {
+            self.height
+        }
+    }
+
+    fn is_walkable(&self) -> bool {
+        self.flags.contains(TileFlags::SURFACE) && !self.flags.contains(TileFlags::IMPASSABLE)
+    }
+
+    fn is_solid(&self) -> bool {
+        self.flags.intersects(TileFlags::SURFACE | TileFlags::IMPASSABLE)
+    }
+}
+
+/// The source of land and items for movement checks.
+pub trait MovementTerrain {
+    fn land(&self, map_id: u8, position: IVec2) -> Option<LandTile>;
+
+    /// Append all of the items at a position to `items`.
+    fn items(&self, map_id: u8, position: IVec2, items: &mut Vec<MovementTile>);
+
+    /// Append only the static items at a position to `items`.
+    fn statics(&self, map_id: u8, position: IVec2, items: &mut Vec<MovementTile>);
+
+    /// The dynamic items and multis, for queries which cover more than one position.
+    fn surfaces(&self) -> &EntitySurfaces;
+
+    fn tile_data(&self) -> &TileData;
+}
+
+/// Movement terrain made up of map data and dynamic surfaces.
+pub struct WorldTerrain<'a> {
+    pub map: &'a MapStorage,
+    pub surfaces: &'a EntitySurfaces,
+    pub tile_data: &'a TileData,
+    /// An entity to leave out, usually the one which is moving.
+    pub ignore: Option<Entity>,
+}
+
+impl<'a> MovementTerrain for WorldTerrain<'a> {
+    fn land(&self, map_id: u8, position: IVec2) -> Option<LandTile> {
+        let (tile_id, z) = self.map.block_at(map_id, position)?.land(position);
+
+        let flags = self.tile_data.land.get(tile_id as usize)
+            .map_or(TileFlags::empty(), |l| l.flags);
+        Some(LandTile { tile_id, z: z as i32, flags })
+    }
+
+    fn items(&self, map_id: u8, position: IVec2, items: &mut Vec<MovementTile>) {
+        self.statics(map_id, position, items);
+        surface_items(self.surfaces, self.tile_data, map_id, position, self.ignore, items);
+    }
+
+    fn statics(&self, map_id: u8, position: IVec2, items: &mut Vec<MovementTile>) {
+        if let Some(block) = self.map.block_at(map_id, position) {
+            items.extend(block.statics_at(position)
+                .filter_map(|s| MovementTile::from_static(self.tile_data, s)));
+        }
+    }
+
+    fn surfaces(&self) -> &EntitySurfaces {
+        self.surfaces
+    }
+
+    fn tile_data(&self) -> &TileData {
+        self.tile_data
+    }
+}
 
-    for (entity, kind) in surfaces.tree.iter_at_point(position.map_id, test_position.truncate()) {
+/// Append the movement tiles of dynamic items and multis at a position to `items`.
+fn surface_items(
+    surfaces: &EntitySurfaces, tile_data: &TileData, map_id: u8, position: IVec2, ignore: Option<Entity>,
+    items: &mut Vec<MovementTile>,
+) {
+    for (entity, kind) in surfaces.tree.iter_at_point(map_id, position) {
         if Some(entity) == ignore {
             continue;
         }
 
-        match kind {
-            SurfaceKind::Chunk { position, chunk } => {
-                let chunk_pos = test_position.truncate() - *position;
-                let (tile_id, z) = chunk.get(chunk_pos.x as usize, chunk_pos.y as usize);
+        surface_tiles(tile_data, entity, kind, position, items);
+    }
+}
+
+/// Append the movement tiles of a single dynamic item or multi at a position to `items`.
+pub(crate) fn surface_tiles(
+    tile_data: &TileData, entity: Entity, kind: &SurfaceKind, position: IVec2, items: &mut Vec<MovementTile>,
+) {
+    match kind {
+        SurfaceKind::Item { position: item_position, .. } if *item_position != position => {}
+        SurfaceKind::Item { tile_id, impassable, min_z, max_z, .. } => {
+            let mut flags = tile_data.items.get(*tile_id as usize)
+                .map_or(TileFlags::empty(), |i| i.flags);
+            flags.set(TileFlags::IMPASSABLE, *impassable);
+            flags.set(TileFlags::SURFACE, !*impassable);
+            items.push(MovementTile {
+                z: *min_z,
+                height: *max_z - *min_z,
+                flags,
+                entity: Some(entity),
+            });
+        }
+        SurfaceKind::Multi { .. } => {
+            items.extend(kind.multi_tiles_at(position).filter_map(|tile| {
+                let info = tile_data.items.get(tile.tile_id as usize)?;
+                Some(MovementTile {
+                    z: tile.z,
+                    height: info.height as i32,
+                    flags: info.flags,
+                    entity: Some(entity),
+                })
+            }));
+        }
+    }
+}
+
+pub(crate) struct LandHeights {
+    pub tile: LandTile,
+    pub low: i32,
+    pub center: i32,
+    pub top: i32,
+}
+
+impl LandHeights {
+    fn is_walkable(&self) -> bool {
+        !self.tile.is_ignored() && !self.tile.flags.contains(TileFlags::IMPASSABLE)
+    }
+}
+
+/// Land is drawn stretched between the heights of its four corners, so the height of a
+/// tile depends on its neighbours to the south and east.
+pub(crate) fn land_heights(terrain: &impl MovementTerrain, map_id: u8, position: IVec2) -> Option<LandHeights> {
+    let tile = terrain.land(map_id, position)?;
+    let corner = |x, y| terrain.land(map_id, position + IVec2::new(x, y)).map_or(tile.z, |l| l.z);
+    let top = tile.z;
+    let left = corner(0, 1);
+    let right = corner(1, 0);
+    let bottom = corner(1, 1);
+
+    let center = if (top - bottom).abs() > (left - right).abs() {
+        (left + right).div_euclid(2)
+    } else {
+        (top + bottom).div_euclid(2)
+    };
+
+    Some(LandHeights {
+        tile,
+        low: top.min(left).min(right).min(bottom),
+        center,
+        top: top.max(left).max(right).max(bottom),
+    })
+}
 
-                if !tile_data.land[tile_id as usize].flags.contains(TileFlags::IMPASSABLE) {
-                    let z = z as i32;
-                    if z <= test_position.z {
-                        new_z = new_z.max(z);
-                    }
+/// Find the bottom and top of whatever a character at `position` is standing on.
+fn standing_heights(terrain: &impl MovementTerrain, map_id: u8, position: IVec3, items: &[MovementTile]) -> (i32, i32) {
+    let mut z_low = 0;
+    let mut z_center = 0;
+    let mut z_top = 0;
+    let mut is_set = false;
+
+    if let Some(land) = land_heights(terrain, map_id, position.truncate()) {
+        if land.is_walkable() && position.z >= land.center {
+            z_low = land.low;
+            z_center = land.center;
+            z_top = land.top;
+            is_set = true;
+        }
+    }
+
+    for item in items.iter().filter(|i| i.flags.contains(TileFlags::SURFACE)) {
+        let stand_z = item.z + item.stand_height();
+        if (!is_set || stand_z >= z_center) && position.z >= stand_z {
+            z_low = item.z;
+            z_center = stand_z;
+            let top = item.z + item.height;
+            if !is_set || top > z_top {
+                z_top = top;
+            }
+            is_set = true;
+        }
+    }
+
+    if is_set {
+        (z_low, z_top.max(position.z))
+    } else {
+        (position.z, position.z)
+    }
+}
+
+/// Check that nothing occupies the space between `z` and `top`.
+fn check_clearance(z: i32, top: i32, items: &[MovementTile]) -> Result<(), Option<Entity>> {
+    for item in items.iter().filter(|i| i.is_solid()) {
+        let item_top = item.z + item.stand_height();
+        if item_top > z && top > item.z {
+            return Err(item.entity);
+        }
+    }
+    Ok(())
+}
+
+/// Find the height a character would end up at when stepping onto `position`.
+///
+/// On failure, returns the dynamic item which blocked the move, if there was one.
+fn check_step(
+    terrain: &impl MovementTerrain,
+    map_id: u8,
+    position: IVec2,
+    current_z: i32,
+    start_z: i32,
+    start_top: i32,
+    items: &mut Vec<MovementTile>,
+) -> Result<i32, Option<Entity>> {
+    items.clear();
+    terrain.items(map_id, position, items);
+    let land = land_heights(terrain, map_id, position);
+
+    let step_top = start_top + STEP_HEIGHT;
+    let check_top = start_z + PERSON_HEIGHT;
+    let mut new_z = None;
+    let mut blocker = None;
+
+    // Prefer whichever surface is closest to the current height, then the lowest.
+    let is_better = |z: i32, best: Option<i32>| match best {
+        Some(best) => {
+            let cmp = (z - current_z).abs() - (best - current_z).abs();
+            cmp < 0 || (cmp == 0 && z < best)
+        }
+        None => true,
+    };
+
+    for item in items.iter().filter(|i| i.is_walkable()) {
+        let our_z = item.z + item.stand_height();
+        if !is_better(our_z, new_z) {
+            continue;
+        }
+
+        let test_top = check_top.max(our_z + PERSON_HEIGHT);
+        let item_top = if item.is_bridge() { item.z } else { item.z + item.height };
+        if step_top < item_top {
+            continue;
+        }
+
+        // Don't step onto items which are buried in a slope above us.
+        let land_check = item.z + item.height.min(STEP_HEIGHT);
+        if let Some(land) = land.as_ref().filter(|l| !l.tile.is_ignored()) {
+            if land_check < land.center && land.center > our_z && test_top > land.low {
+                continue;
+            }
+        }
+
+        match check_clearance(our_z, test_top, items) {
+            Ok(()) => new_z = Some(our_z),
+            Err(entity) => blocker = blocker.or(entity),
+        }
+    }
+
+    if let Some(land) = land.as_ref().filter(|l| l.is_walkable() && step_top >= l.low) {
+        let our_z = land.center;
+        let test_top = check_top.max(our_z + PERSON_HEIGHT);
+        if is_better(our_z, new_z) {
+            match check_clearance(our_z, test_top, items) {
+                Ok(()) => new_z = Some(our_z),
+                Err(entity) => blocker = blocker.or(entity),
+            }
+        }
+    }
+
+    new_z.ok_or(blocker)
+}
+
+/// Check a single step in `direction`, using the same rules as the client.
+///
+/// Characters can step onto land or surfaces up to [`STEP_HEIGHT`] above the top of what
+/// they're standing on, as long as there's [`PERSON_HEIGHT`] of headroom. Diagonal steps
+/// are only blocked if both of the neighbouring orthogonal steps are blocked.
+pub fn check_movement(
+    terrain: &impl MovementTerrain,
+    position: Location,
+    direction: Direction,
+) -> Result<Location, MoveError> {
+    let map_id = position.map_id;
+    let start = position.position;
+    let mut items = Vec::new();
+    terrain.items(map_id, start.truncate(), &mut items);
+    let (start_z, start_top) = standing_heights(terrain, map_id, start, &items);
+
+    let to_error = |entity: Option<Entity>| entity.map_or(MoveError::Impassable, MoveError::Obstructed);
+    let offset = direction.as_vec2();
+    if offset.x != 0 && offset.y != 0 {
+        let sides = [IVec2::new(offset.x, 0), IVec2::new(0, offset.y)];
+        let mut side_blocker = None;
+        let mut blocked = true;
+        for side in sides {
+            match check_step(terrain, map_id, start.truncate() + side, start.z, start_z, start_top, &mut items) {
+                Ok(_) => blocked = false,
+                Err(entity) => side_blocker = side_blocker.or(entity),
+            }
+        }
+
+        if blocked {
+            return Err(to_error(side_blocker));
+        }
+    }
+
+    let target = start.truncate() + offset;
+    let z = check_step(terrain, map_id, target, start.z, start_z, start_top, &mut items)
+        .map_err(to_error)?;
+    Ok(Location { map_id, position: target.extend(z), direction })
+}
+
+pub fn try_move_in_direction(
+    map: &MapStorage,
+    surfaces: &EntitySurfaces,
+    tile_data: &TileData,
+    position: Location,
+    direction: Direction,
+    ignore: Option<Entity>,
+) -> Result<Location, MoveError> {
+    let terrain = WorldTerrain { map, surfaces, tile_data, ignore };
+    check_movement(&terrain, position, direction)
+}
+
+const ALL_DIRECTIONS: [Direction; 8] = [
+    Direction::North,
+    Direction::Right,
+    Direction::East,
+    Direction::Down,
+    Direction::South,
+    Direction::Left,
+    Direction::West,
+    Direction::Up,
+];
+
+fn chebyshev_distance(a: IVec2, b: IVec2) -> i32 {
+    let delta = (a - b).abs();
+    delta.x.max(delta.y)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathStatus {
+    InProgress,
+    Found(Arc<[Direction]>),
+    NotFound,
+}
+
+/// An in-progress A* search, which can be advanced a few nodes at a time.
+///
+/// Steps between nodes are checked with [`try_move_in_direction`], so paths follow the
+/// same rules as player movement.
+#[derive(Debug, Clone)]
+pub struct PathSearch {
+    map_id: u8,
+    start: IVec3,
+    goal: IVec2,
+    range: i32,
+    ignore: Option<Entity>,
+    max_nodes: usize,
+    visited: usize,
+    open: BinaryHeap<Reverse<(i32, i32, IVec3Key)>>,
+    cost: HashMap<IVec3, i32>,
+    came_from: HashMap<IVec3, (IVec3, Direction)>,
+}
+
+// IVec3 isn't Ord, so wrap it for use in the open set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct IVec3Key(i32, i32, i32);
+
+impl From<IVec3> for IVec3Key {
+    fn from(value: IVec3) -> Self {
+        IVec3Key(value.x, value.y, value.z)
+    }
+}
+
+impl From<IVec3Key> for IVec3 {
+    fn from(value: IVec3Key) -> Self {
+        IVec3::new(value.0, value.1, value.2)
+    }
+}
+
+impl PathSearch {
+    /// Start a search from `start` to any tile within `range` tiles of `goal`.
+    ///
+    /// The search gives up after visiting `max_nodes` tiles.
+    pub fn new(start: Location, goal: IVec2, range: i32, ignore: Option<Entity>, max_nodes: usize) -> PathSearch {
+        let mut open = BinaryHeap::new();
+        let heuristic = chebyshev_distance(start.position.truncate(), goal);
+        open.push(Reverse((heuristic, 0, start.position.into())));
+
+        PathSearch {
+            map_id: start.map_id,
+            start: start.position,
+            goal,
+            range,
+            ignore,
+            max_nodes,
+            visited: 0,
+            open,
+            cost: HashMap::from([(start.position, 0)]),
+            came_from: HashMap::new(),
+        }
+    }
+
+    /// The number of nodes visited so far.
+    pub fn visited(&self) -> usize {
+        self.visited
+    }
+
+    /// Whether this search started from `location`.
+    pub fn starts_at(&self, location: &Location) -> bool {
+        self.map_id == location.map_id && self.start == location.position
+    }
+
+    fn build_path(&self, mut position: IVec3) -> Arc<[Direction]> {
+        let mut directions = Vec::new();
+        while position != self.start {
+            let (previous, direction) = self.came_from[&position];
+            directions.push(direction);
+            position = previous;
+        }
+        directions.reverse();
+        directions.into()
+    }
+
+    /// Visit up to `budget` more nodes.
+    ///
+    /// Returns the status of the search, and the number of nodes which were visited.
+    pub fn step(
+        &mut self,
+        map: &MapStorage,
+        surfaces: &EntitySurfaces,
+        tile_data: &TileData,
+        budget: usize,
+    ) -> (PathStatus, usize) {
+        let terrain = WorldTerrain { map, surfaces, tile_data, ignore: self.ignore };
+        self.step_on(&terrain, budget)
+    }
+
+    /// Visit up to `budget` more nodes of any terrain.
+    pub fn step_on(&mut self, terrain: &impl MovementTerrain, budget: usize) -> (PathStatus, usize) {
+        let mut used = 0;
+
+        while used < budget {
+            let Reverse((_, cost, key)) = match self.open.pop() {
+                Some(x) => x,
+                None => return (PathStatus::NotFound, used),
+            };
+            let position = IVec3::from(key);
+            if self.cost.get(&position).is_some_and(|c| *c < -cost) {
+                continue;
+            }
+
+            if chebyshev_distance(position.truncate(), self.goal) <= self.range {
+                return (PathStatus::Found(self.build_path(position)), used);
+            }
+
+            if self.visited >= self.max_nodes {
+                return (PathStatus::NotFound, used);
+            }
+            self.visited += 1;
+            used += 1;
+
+            let cost = -cost + 1;
+            let location = Location { map_id: self.map_id, position, direction: Direction::default() };
+            for direction in ALL_DIRECTIONS {
+                let next = match check_movement(terrain, location, direction) {
+                    Ok(x) => x.position,
+                    Err(_) => continue,
+                };
+
+                if self.cost.get(&next).is_some_and(|c| *c <= cost) {
+                    continue;
                 }
+
+                self.cost.insert(next, cost);
+                self.came_from.insert(next, (position, direction));
+                let estimate = cost + chebyshev_distance(next.truncate(), self.goal);
+                // Costs are negated so that ties prefer the node furthest along.
+                self.open.push(Reverse((estimate, -cost, next.into())));
+            }
+        }
+
+        (PathStatus::InProgress, used)
+    }
+
+    /// Run the search to completion, ignoring the per-tick budget.
+    pub fn run(&mut self, map: &MapStorage, surfaces: &EntitySurfaces, tile_data: &TileData) -> PathStatus {
+        let terrain = WorldTerrain { map, surfaces, tile_data, ignore: self.ignore };
+        self.run_on(&terrain)
+    }
+
+    /// Run the search to completion over any terrain.
+    pub fn run_on(&mut self, terrain: &impl MovementTerrain) -> PathStatus {
+        loop {
+            match self.step_on(terrain, usize::MAX).0 {
+                PathStatus::InProgress => continue,
+                status => return status,
+            }
+        }
+    }
+}
+
+/// Find a path from `start` to within `range` tiles of `goal`, visiting at most `max_nodes` tiles.
+#[allow(clippy::too_many_arguments)]
+pub fn find_path(
+    map: &MapStorage,
+    surfaces: &EntitySurfaces,
+    tile_data: &TileData,
+    start: Location,
+    goal: IVec2,
+    range: i32,
+    ignore: Option<Entity>,
+    max_nodes: usize,
+) -> Option<Arc<[Direction]>> {
+    match PathSearch::new(start, goal, range, ignore, max_nodes).run(map, surfaces, tile_data) {
+        PathStatus::Found(path) => Some(path),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Resource)]
+pub struct PathfindingSettings {
+    /// The total number of nodes all searches can visit in one tick.
+    pub nodes_per_tick: usize,
+    /// The number of nodes a single search can visit before giving up.
+    pub max_nodes_per_search: usize,
+    /// The number of nodes a search visits before the next search gets a turn.
+    pub nodes_per_slice: usize,
+    /// The number of ticks found paths are cached for.
+    pub cache_ticks: u64,
+    /// The maximum number of cached paths.
+    pub cache_capacity: usize,
+}
+
+impl Default for PathfindingSettings {
+    fn default() -> Self {
+        Self {
+            nodes_per_tick: 4096,
+            max_nodes_per_search: 2048,
+            nodes_per_slice: 64,
+            cache_ticks: 100,
+            cache_capacity: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PathKey {
+    map_id: u8,
+    start: IVec3,
+    goal: IVec2,
+    range: i32,
+}
+
+/// Recently found paths, shared between entities travelling between the same places.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct PathCache {
+    tick: u64,
+    paths: HashMap<PathKey, (Arc<[Direction]>, u64)>,
+}
+
+impl PathCache {
+    fn get(&self, key: &PathKey) -> Option<Arc<[Direction]>> {
+        self.paths.get(key).map(|(path, _)| path.clone())
+    }
+
+    fn insert(&mut self, key: PathKey, path: Arc<[Direction]>, capacity: usize) {
+        if self.paths.len() >= capacity {
+            let oldest = self.paths.iter()
+                .min_by_key(|(_, (_, tick))| *tick)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.paths.remove(&oldest);
+            }
+        }
+
+        self.paths.insert(key, (path, self.tick));
+    }
+
+    /// Forget all cached paths, for example after the world has changed significantly.
+    pub fn clear(&mut self) {
+        self.paths.clear();
+    }
+}
+
+/// Request a path for this entity to within `range` tiles of `goal`.
+///
+/// Once the search finishes, this is replaced with either a [`Path`] or [`PathNotFound`].
+#[derive(Debug, Clone, Component)]
+pub struct PathRequest {
+    pub goal: IVec2,
+    pub range: i32,
+}
+
+/// The state of an unfinished search for a [`PathRequest`].
+#[derive(Debug, Clone, Component)]
+pub struct PathSearchState {
+    search: PathSearch,
+}
+
+/// The remaining steps of a path found by pathfinding.
+#[derive(Debug, Clone, Default, Component)]
+pub struct Path {
+    pub directions: VecDeque<Direction>,
+}
+
+#[derive(Debug, Clone, Default, Component)]
+pub struct PathNotFound;
+
+enum PendingSearch<'a> {
+    Existing(Mut<'a, PathSearchState>),
+    New(PathSearch),
+}
+
+impl<'a> PendingSearch<'a> {
+    fn search(&mut self) -> &mut PathSearch {
+        match self {
+            PendingSearch::Existing(state) => &mut state.search,
+            PendingSearch::New(search) => search,
+        }
+    }
+}
+
+fn finish_search(commands: &mut Commands, entity: Entity, status: PathStatus) {
+    match status {
+        PathStatus::InProgress => {}
+        PathStatus::Found(path) => {
+            commands.entity(entity)
+                .remove::<(PathRequest, PathSearchState, PathNotFound)>()
+                .insert(Path { directions: path.iter().copied().collect() });
+        }
+        PathStatus::NotFound => {
+            commands.entity(entity)
+                .remove::<(PathRequest, PathSearchState, Path)>()
+                .insert(PathNotFound);
+        }
+    }
+}
+
+/// Advance path searches, sharing the per-tick node budget between them.
+///
+/// Searches take turns visiting [`PathfindingSettings::nodes_per_slice`] nodes each, and
+/// the first turn rotates between ticks, so that no search can starve the others.
+#[allow(clippy::too_many_arguments)]
+pub fn update_pathfinding(
+    mut commands: Commands,
+    settings: Res<PathfindingSettings>,
+    mut cache: ResMut<PathCache>,
+    map: Res<MapStorage>,
+    surfaces: Res<EntitySurfaces>,
+    tile_data: Res<TileDataResource>,
+    mut requests: Query<(Entity, &Location, Ref<PathRequest>, Option<&mut PathSearchState>)>,
+    mut next_turn: Local<usize>,
+) {
+    cache.tick += 1;
+    let tick = cache.tick;
+    let cache_ticks = settings.cache_ticks;
+    cache.paths.retain(|_, (_, created)| *created + cache_ticks > tick);
+
+    let mut pending = Vec::new();
+    for (entity, location, request, state) in &mut requests {
+        let key = PathKey {
+            map_id: location.map_id,
+            start: location.position,
+            goal: request.goal,
+            range: request.range,
+        };
+
+        // Cache hits keep the entry's original tick, so that busy paths still expire.
+        if let Some(path) = cache.get(&key) {
+            finish_search(&mut commands, entity, PathStatus::Found(path));
+            continue;
+        }
+
+        // Restart searches whose request changed or whose entity has moved since.
+        let search = match state {
+            Some(state) if !request.is_changed() && state.search.starts_at(location) =>
+                PendingSearch::Existing(state),
+            _ => PendingSearch::New(PathSearch::new(
+                *location, request.goal, request.range, Some(entity), settings.max_nodes_per_search)),
+        };
+        pending.push((entity, key, search));
+    }
+
+    if !pending.is_empty() {
+        let first = *next_turn % pending.len();
+        pending.rotate_left(first);
+    }
+
+    let slice = settings.nodes_per_slice.max(1);
+    let mut budget = settings.nodes_per_tick;
+    let mut turns = 0;
+    let mut in_progress = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        for (entity, key, mut search) in pending.drain(..) {
+            if budget == 0 {
+                in_progress.push((entity, key, search));
+                continue;
             }
-            SurfaceKind::Item { min_z, max_z, impassable, .. } => {
-                if *impassable {
-                    if test_position.z >= *min_z && test_position.z <= *max_z {
-                        return Err(MoveError::Obstructed(entity));
-                    }
-                } else if *max_z <= test_position.z {
-                    new_z = new_z.max(*max_z);
+
+            let (status, used) = search.search().step(&map, &surfaces, &tile_data, slice.min(budget));
+            budget -= used;
+            turns += 1;
+
+            if status == PathStatus::InProgress {
+                in_progress.push((entity, key, search));
+            } else {
+                if let PathStatus::Found(path) = &status {
+                    cache.insert(key, path.clone(), settings.cache_capacity);
                 }
+                finish_search(&mut commands, entity, status);
             }
         }
+
+        if budget == 0 {
+            break;
+        }
+        std::mem::swap(&mut pending, &mut in_progress);
     }
+    *next_turn = next_turn.wrapping_add(turns);
 
-    if new_z < 0 {
-        Err(MoveError::Impassable)
-    } else {
-        test_position.z = new_z;
-        Ok(Location { map_id: position.map_id, position: test_position, direction })
+    for (entity, _, search) in pending.into_iter().chain(in_progress) {
+        if let PendingSearch::New(search) = search {
+            commands.entity(entity).insert(PathSearchState { search });
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use glam::{IVec2, IVec3};
+
+    use yewoh::assets::map::{CHUNK_SIZE, MapChunk, Static};
+    use yewoh::assets::multi::{MultiPrefab, MultiPrefabComponent};
+    use yewoh::assets::tiles::{ItemInfo, LandInfo, TileData, TileFlags};
+    use yewoh::Direction;
+
+    use super::*;
+    use crate::world::spatial::MultiSurfaces;
+
+    pub(crate) const GRASS: u16 = 3;
+    const WATER: u16 = 0xa8;
+
+    pub(crate) const WALL: u16 = 1;
+    const FLOOR: u16 = 2;
+    const STAIR: u16 = 3;
+    const BRIDGE: u16 = 4;
+    const TABLE: u16 = 5;
+    pub(crate) const WINDOW: u16 = 6;
+
+    fn item(flags: TileFlags, height: u8) -> ItemInfo {
+        ItemInfo {
+            name: String::new(),
+            flags,
+            weight: 0,
+            quality: 0,
+            misc_data: 0,
+            quantity: 0,
+            animation: 0,
+            hue: 0,
+            stacking_offset: 0,
+            value: 0,
+            height,
+        }
+    }
+
+    fn tile_data() -> TileData {
+        let mut land = vec![LandInfo { name: String::new(), flags: TileFlags::empty(), texture_id: 0 }; 0x100];
+        land[WATER as usize].flags = TileFlags::IMPASSABLE | TileFlags::WET;
+
+        let items = vec![
+            item(TileFlags::empty(), 0),
+            item(TileFlags::WALL | TileFlags::IMPASSABLE, 20),
+            item(TileFlags::SURFACE, 1),
+            item(TileFlags::SURFACE | TileFlags::BRIDGE | TileFlags::STAIR_BACK, 5),
+            item(TileFlags::SURFACE | TileFlags::BRIDGE, 4),
+            item(TileFlags::SURFACE | TileFlags::IMPASSABLE, 6),
+            item(TileFlags::WINDOW, 20),
+        ];
+
+        TileData { land, items }
+    }
+
+    pub(crate) struct TestTerrain {
+        tile_data: TileData,
+        chunk: MapChunk,
+        statics: Vec<Static>,
+        surfaces: EntitySurfaces,
+    }
+
+    impl TestTerrain {
+        pub(crate) fn flat() -> TestTerrain {
+            let mut chunk = MapChunk::default();
+            chunk.tile_ids.fill(GRASS);
+            TestTerrain {
+                tile_data: tile_data(),
+                chunk,
+                statics: Vec::new(),
+                surfaces: EntitySurfaces::default(),
+            }
+        }
+
+        pub(crate) fn set_land(&mut self, x: usize, y: usize, tile_id: u16, z: i8) {
+            let index = x + y * CHUNK_SIZE;
+            self.chunk.tile_ids[index] = tile_id;
+            self.chunk.heights[index] = z;
+        }
+
+        pub(crate) fn add_static(&mut self, x: i32, y: i32, z: i32, graphic_id: u16) {
+            self.statics.push(Static { position: IVec3::new(x, y, z), graphic_id, hue: 0 });
+        }
+
+        pub(crate) fn add_item(&mut self, entity: Entity, x: i32, y: i32, z: i32, graphic_id: u16) {
+            let height = self.tile_data.items[graphic_id as usize].height as i32;
+            let kind = SurfaceKind::Item {
+                position: IVec2::new(x, y),
+                tile_id: graphic_id,
+                impassable: true,
+                min_z: z,
+                max_z: z + height,
+            };
+            self.surfaces.tree.insert_point(entity, kind, 0, IVec2::new(x, y));
+        }
+
+        /// Place a multi made of `(offset, graphic_id)` components with its origin at `origin`.
+        pub(crate) fn add_multi(&mut self, entity: Entity, origin: IVec3, components: &[(IVec3, u16)]) {
+            let prefab = MultiPrefab {
+                components: components.iter()
+                    .map(|(position, graphic)| MultiPrefabComponent {
+                        graphic: *graphic,
+                        position: *position,
+                        tile_flags: TileFlags::empty(),
+                        component_flags: 0,
+                        tooltip_ids: Vec::new(),
+                    })
+                    .collect(),
+            };
+            let (min, max) = prefab.bounds().unwrap();
+            let kind = SurfaceKind::Multi {
+                position: origin,
+                multi_id: 0,
+                tiles: Arc::new(MultiSurfaces::from_prefab(&prefab)),
+            };
+            self.surfaces.tree.insert_aabb(
+                entity, kind, 0, origin.truncate() + min.truncate(), origin.truncate() + max.truncate() + IVec2::ONE);
+        }
+
+        fn step(&self, x: i32, y: i32, z: i32, direction: Direction) -> Option<IVec3> {
+            let location = Location { map_id: 0, position: IVec3::new(x, y, z), direction };
+            check_movement(self, location, direction).ok().map(|l| l.position)
+        }
+    }
+
+    impl MovementTerrain for TestTerrain {
+        fn land(&self, _map_id: u8, position: IVec2) -> Option<LandTile> {
+            let size = CHUNK_SIZE as i32;
+            if position.x < 0 || position.y < 0 || position.x >= size || position.y >= size {
+                return None;
+            }
+
+            let (tile_id, z) = self.chunk.get(position.x as usize, position.y as usize);
+            let flags = self.tile_data.land[tile_id as usize].flags;
+            Some(LandTile { tile_id, z: z as i32, flags })
+        }
+
+        fn items(&self, map_id: u8, position: IVec2, items: &mut Vec<MovementTile>) {
+            self.statics(map_id, position, items);
+            surface_items(&self.surfaces, &self.tile_data, map_id, position, None, items);
+        }
+
+        fn statics(&self, _map_id: u8, position: IVec2, items: &mut Vec<MovementTile>) {
+            items.extend(self.statics.iter()
+                .filter(|s| s.position.truncate() == position)
+                .filter_map(|s| MovementTile::from_static(&self.tile_data, s)));
+        }
+
+        fn surfaces(&self) -> &EntitySurfaces {
+            &self.surfaces
+        }
+
+        fn tile_data(&self) -> &TileData {
+            &self.tile_data
+        }
+    }
+
+    #[test]
+    fn flat_ground() {
+        let terrain = TestTerrain::flat();
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), Some(IVec3::new(4, 3, 0)));
+        assert_eq!(terrain.step(3, 3, 0, Direction::Down), Some(IVec3::new(4, 4, 0)));
+    }
+
+    #[test]
+    fn gentle_slope_uses_average_height() {
+        let mut terrain = TestTerrain::flat();
+        // Raise the eastern corners of (4, 3), so the tile slopes up towards the east.
+        terrain.set_land(5, 3, GRASS, 8);
+        terrain.set_land(5, 4, GRASS, 8);
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), Some(IVec3::new(4, 3, 4)));
+
+        // Raising the southern corner too makes the tile fold along the other diagonal.
+        terrain.set_land(4, 4, GRASS, 8);
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), Some(IVec3::new(4, 3, 8)));
+    }
+
+    #[test]
+    fn steep_land_is_climbed_via_slopes() {
+        let mut terrain = TestTerrain::flat();
+        for x in 4..8 {
+            for y in 0..8 {
+                terrain.set_land(x, y, GRASS, 20);
+            }
+        }
+
+        // The tile before the plateau slopes up to meet it.
+        assert_eq!(terrain.step(2, 3, 0, Direction::East), Some(IVec3::new(3, 3, 10)));
+        assert_eq!(terrain.step(3, 3, 10, Direction::East), Some(IVec3::new(4, 3, 20)));
+        assert_eq!(terrain.step(4, 3, 20, Direction::West), Some(IVec3::new(3, 3, 10)));
+
+        // But it can't be climbed in one step from something flat at the bottom.
+        terrain.add_static(3, 3, 0, FLOOR);
+        assert_eq!(terrain.step(3, 3, 1, Direction::East), None);
+    }
+
+    #[test]
+    fn impassable_land_blocks() {
+        let mut terrain = TestTerrain::flat();
+        terrain.set_land(4, 3, WATER, 0);
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), None);
+    }
+
+    #[test]
+    fn walls_block() {
+        let mut terrain = TestTerrain::flat();
+        terrain.add_static(4, 3, 0, WALL);
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), None);
+    }
+
+    #[test]
+    fn stairs_climb() {
+        let mut terrain = TestTerrain::flat();
+        terrain.add_static(4, 3, 0, STAIR);
+        terrain.add_static(5, 3, 5, STAIR);
+        terrain.add_static(6, 3, 10, STAIR);
+
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), Some(IVec3::new(4, 3, 2)));
+        assert_eq!(terrain.step(4, 3, 2, Direction::East), Some(IVec3::new(5, 3, 7)));
+        assert_eq!(terrain.step(5, 3, 7, Direction::East), Some(IVec3::new(6, 3, 12)));
+        assert_eq!(terrain.step(6, 3, 12, Direction::West), Some(IVec3::new(5, 3, 7)));
+    }
+
+    #[test]
+    fn steps_too_high_block() {
+        let mut terrain = TestTerrain::flat();
+        terrain.add_static(4, 3, 0, TABLE);
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), None);
+
+        terrain.statics.clear();
+        terrain.add_static(4, 3, 5, FLOOR);
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), None);
+    }
+
+    #[test]
+    fn bridge_over_water() {
+        let mut terrain = TestTerrain::flat();
+        for x in 4..6 {
+            terrain.set_land(x, 3, WATER, -5);
+            terrain.add_static(x as i32, 3, 0, BRIDGE);
+        }
+
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), Some(IVec3::new(4, 3, 2)));
+        assert_eq!(terrain.step(4, 3, 2, Direction::East), Some(IVec3::new(5, 3, 2)));
+        assert_eq!(terrain.step(4, 3, 2, Direction::South), Some(IVec3::new(4, 4, 0)));
+    }
+
+    #[test]
+    fn ceiling_blocks() {
+        let mut terrain = TestTerrain::flat();
+        terrain.add_static(4, 3, 10, FLOOR);
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), None);
+
+        terrain.statics.clear();
+        terrain.add_static(4, 3, 20, FLOOR);
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), Some(IVec3::new(4, 3, 0)));
+    }
+
+    #[test]
+    fn upper_floor_walks_over_ground() {
+        let mut terrain = TestTerrain::flat();
+        terrain.add_static(3, 3, 20, FLOOR);
+        terrain.add_static(4, 3, 20, FLOOR);
+        assert_eq!(terrain.step(3, 3, 21, Direction::East), Some(IVec3::new(4, 3, 21)));
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), Some(IVec3::new(4, 3, 0)));
+    }
+
+    #[test]
+    fn diagonals_need_one_open_side() {
+        let mut terrain = TestTerrain::flat();
+        terrain.add_static(4, 3, 0, WALL);
+        assert_eq!(terrain.step(3, 3, 0, Direction::Down), Some(IVec3::new(4, 4, 0)));
+
+        terrain.add_static(3, 4, 0, WALL);
+        assert_eq!(terrain.step(3, 3, 0, Direction::Down), None);
+    }
+
+    #[test]
+    fn multis_block_and_carry_movement() {
+        let mut terrain = TestTerrain::flat();
+        terrain.add_multi(Entity::from_raw(1), IVec3::new(4, 3, 0), &[
+            (IVec3::new(0, 0, 0), WALL),
+            (IVec3::new(0, 1, 20), FLOOR),
+            (IVec3::new(1, 1, 20), FLOOR),
+        ]);
+
+        assert_eq!(terrain.step(3, 3, 0, Direction::East), None);
+        assert_eq!(terrain.step(4, 4, 21, Direction::East), Some(IVec3::new(5, 4, 21)));
+        assert_eq!(terrain.step(3, 4, 0, Direction::East), Some(IVec3::new(4, 4, 0)));
+    }
+
+    fn search(terrain: &TestTerrain, from: IVec3, goal: IVec2) -> PathStatus {
+        let start = Location { map_id: 0, position: from, direction: Direction::North };
+        PathSearch::new(start, goal, 0, None, 256).run_on(terrain)
+    }
+
+    #[test]
+    fn path_detours_around_wall() {
+        let mut terrain = TestTerrain::flat();
+        // Leave a gap at the southern edge.
+        for y in 0..7 {
+            terrain.add_static(4, y, 0, WALL);
+        }
+
+        let path = match search(&terrain, IVec3::new(1, 3, 0), IVec2::new(7, 3)) {
+            PathStatus::Found(path) => path,
+            status => panic!("expected a path, got {status:?}"),
+        };
+        assert!(path.len() > 6);
+
+        let mut position = IVec3::new(1, 3, 0);
+        for direction in path.iter().copied() {
+            position = terrain.step(position.x, position.y, position.z, direction)
+                .expect("path should be walkable");
+        }
+        assert_eq!(position, IVec3::new(7, 3, 0));
+    }
+
+    #[test]
+    fn path_not_found_when_walled_off() {
+        let mut terrain = TestTerrain::flat();
+        for y in 0..CHUNK_SIZE as i32 {
+            terrain.add_static(4, y, 0, WALL);
+        }
+
+        assert_eq!(search(&terrain, IVec3::new(1, 3, 0), IVec2::new(7, 3)), PathStatus::NotFound);
+    }
+
+    #[test]
+    fn cached_paths_expire_while_in_use() {
+        let mut world = World::new();
+        world.insert_resource(PathfindingSettings { cache_ticks: 3, ..Default::default() });
+        world.init_resource::<PathCache>();
+        world.init_resource::<MapStorage>();
+        world.init_resource::<EntitySurfaces>();
+        world.init_resource::<TileDataResource>();
+
+        let request = PathRequest { goal: IVec2::new(7, 3), range: 0 };
+        let location = Location { map_id: 0, position: IVec3::new(1, 3, 0), ..Default::default() };
+        let key = PathKey { map_id: 0, start: location.position, goal: request.goal, range: request.range };
+        let cached: Arc<[Direction]> = Arc::from([Direction::East; 6]);
+        world.resource_mut::<PathCache>().insert(key, cached.clone(), 16);
+
+        let entity = world.spawn((location, request.clone())).id();
+        let mut schedule = Schedule::new();
+        schedule.add_system(update_pathfinding);
+
+        for _ in 1..3 {
+            schedule.run(&mut world);
+            assert_eq!(world.get::<Path>(entity).unwrap().directions.len(), cached.len());
+            world.entity_mut(entity).remove::<Path>().insert(request.clone());
+        }
+
+        schedule.run(&mut world);
+        assert!(world.resource::<PathCache>().get(&key).is_none());
+        assert!(world.get::<Path>(entity).is_none());
     }
 }
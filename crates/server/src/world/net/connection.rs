@@ -6,24 +6,25 @@ use bevy_reflect::prelude::*;
 use log::{info, warn};
 use tokio::sync::mpsc;
 
-use yewoh::protocol::{AnyPacket, AsciiTextMessageRequest, CharacterProfile, ClientVersion, ClientVersionRequest, CreateCharacterClassic, CreateCharacterEnhanced, DeleteCharacter, DoubleClick, DropEntity, EntityRequest, EntityRequestKind, EntityTooltip, EntityTooltipLine, EquipEntity, FeatureFlags, GameServerLogin, Move, PickUpEntity, SelectCharacter, SingleClick, SupportedFeatures, UnicodeTextMessageRequest};
+use yewoh::protocol::{ActionRequest, AnyPacket, AsciiTextMessageRequest, CharacterProfile, ClientVersion, ClientVersionRequest, CreateCharacterClassic, CreateCharacterEnhanced, DeleteCharacter, DoubleClick, DropEntity, EntityRequest, EntityRequestKind, EntityTooltip, EntityTooltipLine, EquipEntity, FeatureFlags, GameServerLogin, PickUpEntity, SelectCharacter, SharedPacket, SingleClick, SupportedFeatures, UnicodeTextMessageRequest};
 use yewoh::protocol::encryption::Encryption;
 
 use crate::async_runtime::AsyncRuntime;
 use crate::game_server::NewSessionAttempt;
 use crate::lobby::{NewSessionRequest, SessionAllocator};
 use crate::world::entity::Tooltip;
-use crate::world::events::{CharacterListEvent, ChatRequestEvent, CreateCharacterEvent, DeleteCharacterEvent, DoubleClickEvent, DropEvent, EquipEvent, MoveEvent, PickUpEvent, ProfileEvent, ReceivedPacketEvent, RequestSkillsEvent, SelectCharacterEvent, SentPacketEvent, SingleClickEvent};
+use crate::world::gump::OpenGumps;
+use crate::world::events::{CharacterListEvent, ChatRequestEvent, CreateCharacterEvent, DeleteCharacterEvent, DoubleClickEvent, DropEvent, EquipEvent, PickUpEvent, ProfileEvent, ReceivedPacketEvent, RequestSkillsEvent, SelectCharacterEvent, SentPacketEvent, SingleClickEvent, UseSkillEvent};
 use crate::world::input::Targeting;
-use crate::world::net::ViewState;
+use crate::world::net::{MovementAnomalies, MovementState, ViewState};
 use crate::world::net::entity::NetEntityLookup;
-use crate::world::net::view::View;
+use crate::world::net::view::{View, ViewSettings};
 
 pub const DEFAULT_VIEW_RANGE: i32 = 18;
 
 pub enum WriterAction {
     Send(ClientVersion, AnyPacket),
-    SendArc(ClientVersion, Arc<AnyPacket>),
+    SendShared(ClientVersion, Arc<SharedPacket>),
 }
 
 #[derive(Debug, Clone, Component)]
@@ -53,10 +54,10 @@ impl NetClient {
         self.tx.send(WriterAction::Send(self.client_version, packet)).ok();
     }
 
-    pub fn send_packet_arc(&self, packet: impl Into<Arc<AnyPacket>>) {
+    pub fn send_packet_arc(&self, packet: impl Into<Arc<SharedPacket>>) {
         let packet = packet.into();
         log::trace!("OUT ({:?}): {:?}", self.address, packet);
-        self.tx.send(WriterAction::SendArc(self.client_version, packet)).ok();
+        self.tx.send(WriterAction::SendShared(self.client_version, packet)).ok();
     }
 }
 
@@ -107,14 +108,14 @@ impl NetServer {
     }
 }
 
-pub fn broadcast<'a>(clients: impl Iterator<Item=&'a NetClient>, packet: Arc<AnyPacket>) {
+pub fn broadcast<'a>(clients: impl Iterator<Item=&'a NetClient>, packet: Arc<SharedPacket>) {
     for client in clients {
         client.send_packet_arc(packet.clone());
     }
 }
 
 pub fn accept_new_clients(
-    runtime: Res<AsyncRuntime>, mut server: ResMut<NetServer>,
+    runtime: Res<AsyncRuntime>, mut server: ResMut<NetServer>, view_settings: Res<ViewSettings>,
     connections: Query<&NetClient>, mut commands: Commands,
 ) {
     while let Ok(new_session_request) = server.new_session_requests.try_recv() {
@@ -202,8 +203,8 @@ pub fn accept_new_clients(
                             warn!("Error sending packet {err}");
                         }
                     }
-                    WriterAction::SendArc(client_version, packet) => {
-                        if let Err(err) = writer.send_any(client_version, &packet).await {
+                    WriterAction::SendShared(client_version, packet) => {
+                        if let Err(err) = writer.send_shared(client_version, &packet).await {
                             warn!("Error sending packet {err}");
                         }
                     }
@@ -217,8 +218,11 @@ pub fn accept_new_clients(
                 client.clone(),
                 User { username },
                 Targeting::default(),
-                View { range: DEFAULT_VIEW_RANGE },
+                View::new(&view_settings),
                 ViewState::new(),
+                MovementState::default(),
+                MovementAnomalies::default(),
+                OpenGumps::default(),
             ))
             .id();
 
@@ -348,7 +352,6 @@ pub fn handle_login_packets(
 pub fn handle_input_packets(
     lookup: Res<NetEntityLookup>,
     mut events: EventReader<ReceivedPacketEvent>,
-    mut move_events: EventWriter<MoveEvent>,
     mut chat_events: EventWriter<ChatRequestEvent>,
     mut single_click_events: EventWriter<SingleClickEvent>,
     mut double_click_events: EventWriter<DoubleClickEvent>,
@@ -357,13 +360,12 @@ pub fn handle_input_packets(
     mut equip_events: EventWriter<EquipEvent>,
     mut profile_events: EventWriter<ProfileEvent>,
     mut skills_events: EventWriter<RequestSkillsEvent>,
+    mut use_skill_events: EventWriter<UseSkillEvent>,
 ) {
     for ReceivedPacketEvent { client_entity: connection, packet } in events.iter() {
         let client_entity = *connection;
 
-        if let Some(request) = packet.downcast::<Move>().cloned() {
-            move_events.send(MoveEvent { client_entity, request });
-        } else if let Some(request) = packet.downcast::<SingleClick>() {
+        if let Some(request) = packet.downcast::<SingleClick>() {
             single_click_events.send(SingleClickEvent {
                 client_entity,
                 target: lookup.net_to_ecs(request.target_id),
@@ -436,6 +438,11 @@ pub fn handle_input_packets(
                 EntityRequestKind::Skills => skills_events.send(RequestSkillsEvent { client_entity, target }),
                 _ => {}
             }
+        } else if let Some(ActionRequest::UseSkill(skill_id)) = packet.downcast::<ActionRequest>() {
+            use_skill_events.send(UseSkillEvent {
+                client_entity,
+                skill_id: *skill_id,
+            });
         }
     }
 }
@@ -16,9 +16,13 @@ pub use view::{
     MapInfo,
     MapInfos,
     View,
+    ViewSettings,
     ViewState,
     ContainerOpenedEvent,
+    EntityVisibility,
     start_synchronizing,
+    handle_view_range_packets,
+    apply_view_settings,
     finish_synchronizing,
     observe_ghosts,
     send_change_map,
@@ -39,6 +43,18 @@ pub use entity::{
 pub use combat::{
     send_updated_attack_target,
 };
+pub use movement::{
+    MovementSettings,
+    MovementViolation,
+    MovementViolationEvent,
+    MovementState,
+    MovementAnomalies,
+    is_mounted,
+    reject_move,
+    validate_move,
+    handle_move_packets,
+    send_fast_walk_keys,
+};
 
 mod connection;
 
@@ -47,3 +63,5 @@ mod entity;
 mod view;
 
 mod combat;
+
+mod movement;
@@ -0,0 +1,357 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use bevy_ecs::prelude::*;
+use rand::{Rng, thread_rng};
+
+use yewoh::Direction;
+use yewoh::protocol::{EquipmentSlot, ExtendedCommand, Move, MoveReject};
+
+use crate::world::entity::{Character, Location};
+use crate::world::events::{MoveEvent, ReceivedPacketEvent};
+use crate::world::net::{NetClient, Possessing};
+
+const FAST_WALK_KEY_COUNT: usize = 6;
+
+/// How many sequence numbers ahead of the expected one a move can be and still be accepted.
+const SEQUENCE_WINDOW: usize = 4;
+
+#[derive(Debug, Clone, Resource)]
+pub struct MovementSettings {
+    pub walk_delay: Duration,
+    pub run_delay: Duration,
+    pub mounted_walk_delay: Duration,
+    pub mounted_run_delay: Duration,
+    /// How far ahead of the expected pace a client can get before moves are rejected.
+    ///
+    /// This allows for moves arriving in bursts due to network jitter.
+    pub tolerance: Duration,
+    /// Whether to send fast walk keys to clients and require them on every move.
+    pub fast_walk_keys: bool,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            walk_delay: Duration::from_millis(400),
+            run_delay: Duration::from_millis(200),
+            mounted_walk_delay: Duration::from_millis(200),
+            mounted_run_delay: Duration::from_millis(100),
+            tolerance: Duration::from_millis(400),
+            fast_walk_keys: false,
+        }
+    }
+}
+
+impl MovementSettings {
+    pub fn step_delay(&self, mounted: bool, run: bool) -> Duration {
+        match (mounted, run) {
+            (false, false) => self.walk_delay,
+            (false, true) => self.run_delay,
+            (true, false) => self.mounted_walk_delay,
+            (true, true) => self.mounted_run_delay,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovementViolation {
+    /// The client moved faster than its speed allows.
+    TooFast,
+    /// The move sequence number was out of order.
+    BadSequence,
+    /// The move didn't include a valid fast walk key.
+    BadFastWalkKey,
+}
+
+/// Sent whenever a client's move is rejected for breaking the movement rules.
+#[derive(Debug, Clone)]
+pub struct MovementViolationEvent {
+    pub client_entity: Entity,
+    pub violation: MovementViolation,
+}
+
+/// The pace state before a move was accepted, kept until the move is confirmed.
+#[derive(Debug, Clone, Copy)]
+struct PendingMove {
+    sequence: u8,
+    next_move: Instant,
+    facing: Option<Direction>,
+}
+
+/// Tracks the pace of a client's movement.
+#[derive(Debug, Clone, Component)]
+pub struct MovementState {
+    next_sequence: u8,
+    next_move: Instant,
+    facing: Option<Direction>,
+    fast_walk_keys: Option<VecDeque<u32>>,
+    pending: VecDeque<PendingMove>,
+    resynchronizing: bool,
+}
+
+impl Default for MovementState {
+    fn default() -> Self {
+        Self {
+            next_sequence: 0,
+            next_move: Instant::now(),
+            facing: None,
+            fast_walk_keys: None,
+            pending: VecDeque::new(),
+            resynchronizing: false,
+        }
+    }
+}
+
+fn next_sequence(sequence: u8) -> u8 {
+    match sequence {
+        255 => 1,
+        n => n + 1,
+    }
+}
+
+impl MovementState {
+    /// Reset the expected move sequence, which the client also does when a move is rejected.
+    ///
+    /// The pace and facing are rolled back to before the oldest unconfirmed move, and any moves
+    /// still in flight are ignored until the client restarts from sequence 0.
+    pub fn reset_sequence(&mut self) {
+        if let Some(oldest) = self.pending.front() {
+            self.next_move = oldest.next_move;
+            self.facing = oldest.facing;
+        } else {
+            self.facing = None;
+        }
+
+        self.pending.clear();
+        self.next_sequence = 0;
+        self.resynchronizing = true;
+    }
+
+    /// Whether the client has yet to acknowledge a rejected move.
+    ///
+    /// Moves which were in flight when the reject was sent should be dropped without
+    /// being applied.
+    pub fn is_resynchronizing(&self) -> bool {
+        self.resynchronizing
+    }
+
+    /// Whether a move is left over from before the last reject.
+    pub fn is_stale(&self, sequence: u8) -> bool {
+        self.resynchronizing && sequence != 0
+    }
+
+    /// Mark a move as applied, so that it is no longer rolled back by a reject.
+    pub fn confirm_move(&mut self, sequence: u8) {
+        if let Some(index) = self.pending.iter().position(|m| m.sequence == sequence) {
+            self.pending.drain(..=index);
+        }
+    }
+
+    fn accept_sequence(&mut self, sequence: u8) -> bool {
+        let mut expected = self.next_sequence;
+        for _ in 0..SEQUENCE_WINDOW {
+            if expected == sequence {
+                self.next_sequence = next_sequence(sequence);
+                self.resynchronizing = false;
+                return true;
+            }
+
+            if expected == 0 {
+                break;
+            }
+
+            expected = next_sequence(expected);
+        }
+
+        false
+    }
+
+    fn push_pending(&mut self, sequence: u8, next_move: Instant, facing: Option<Direction>) {
+        if self.pending.len() >= u8::MAX as usize {
+            self.pending.pop_front();
+        }
+
+        self.pending.push_back(PendingMove { sequence, next_move, facing });
+    }
+}
+
+/// The number of movement rule violations for a client.
+#[derive(Debug, Clone, Default, Component)]
+pub struct MovementAnomalies {
+    pub count: u32,
+    pub last_violation: Option<(MovementViolation, Instant)>,
+}
+
+pub fn is_mounted(character: &Character) -> bool {
+    character.equipment.iter().any(|e| e.slot == EquipmentSlot::Mount)
+}
+
+/// Reject a move, resetting the client's sequence and pace to match.
+pub fn reject_move(client: &NetClient, state: &mut MovementState, request: &Move, location: &Location) {
+    state.reset_sequence();
+    client.send_packet(MoveReject {
+        sequence: request.sequence,
+        position: location.position,
+        direction: location.direction,
+    }.into());
+}
+
+/// Check a move request against the sequence, fast walk keys and speed limits.
+///
+/// Turning on the spot doesn't count towards the speed limit. Stale moves (see
+/// [`MovementState::is_stale`]) should be dropped before calling this.
+pub fn validate_move(
+    settings: &MovementSettings,
+    client: &NetClient,
+    state: &mut MovementState,
+    request: &Move,
+    location: &Location,
+    mounted: bool,
+    now: Instant,
+) -> Result<(), MovementViolation> {
+    if !state.accept_sequence(request.sequence) {
+        return Err(MovementViolation::BadSequence);
+    }
+
+    if let Some(keys) = &mut state.fast_walk_keys {
+        match keys.iter().position(|k| *k == request.fast_walk) {
+            Some(index) => {
+                keys.remove(index);
+                let key = thread_rng().gen_range(1..=u32::MAX);
+                keys.push_back(key);
+                client.send_packet(ExtendedCommand::AddFastWalkKey(key).into());
+            }
+            None => return Err(MovementViolation::BadFastWalkKey),
+        }
+    }
+
+    // Moves can arrive faster than they're applied, so track the facing separately.
+    let previous_move = state.next_move;
+    let previous_facing = state.facing;
+    let facing = state.facing.unwrap_or(location.direction);
+    if facing == request.direction {
+        if state.next_move > now + settings.tolerance {
+            return Err(MovementViolation::TooFast);
+        }
+
+        let delay = settings.step_delay(mounted, request.run);
+        state.next_move = state.next_move.max(now) + delay;
+    }
+
+    state.facing = Some(request.direction);
+    state.push_pending(request.sequence, previous_move, previous_facing);
+    Ok(())
+}
+
+pub fn send_fast_walk_keys(
+    settings: Res<MovementSettings>,
+    mut clients: Query<(&NetClient, &mut MovementState), Added<Possessing>>,
+) {
+    if !settings.fast_walk_keys {
+        return;
+    }
+
+    let mut rng = thread_rng();
+    for (client, mut state) in &mut clients {
+        let mut keys = [0u32; FAST_WALK_KEY_COUNT];
+        for key in keys.iter_mut() {
+            *key = rng.gen_range(1..=u32::MAX);
+        }
+
+        state.fast_walk_keys = Some(keys.iter().copied().collect());
+        client.send_packet(ExtendedCommand::FastWalkKeys(keys).into());
+    }
+}
+
+pub fn handle_move_packets(
+    settings: Res<MovementSettings>,
+    mut events: EventReader<ReceivedPacketEvent>,
+    mut move_events: EventWriter<MoveEvent>,
+    mut violation_events: EventWriter<MovementViolationEvent>,
+    mut clients: Query<(&NetClient, &Possessing, &mut MovementState, &mut MovementAnomalies)>,
+    characters: Query<(&Location, &Character)>,
+) {
+    let now = Instant::now();
+
+    for ReceivedPacketEvent { client_entity, packet } in events.iter() {
+        let client_entity = *client_entity;
+        let request = match packet.downcast::<Move>() {
+            Some(x) => x,
+            None => continue,
+        };
+
+        let (client, possessing, mut state, mut anomalies) = match clients.get_mut(client_entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        let (location, character) = match characters.get(possessing.entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        if state.is_stale(request.sequence) {
+            log::trace!("dropped stale move {} from {:?}", request.sequence, client.address());
+            continue;
+        }
+
+        match validate_move(&settings, client, &mut state, request, location, is_mounted(character), now) {
+            Ok(()) => {
+                move_events.send(MoveEvent { client_entity, request: request.clone() });
+            }
+            Err(violation) => {
+                log::debug!("rejected move from {:?}: {:?}", client.address(), violation);
+                reject_move(client, &mut state, request, location);
+                anomalies.count += 1;
+                anomalies.last_violation = Some((violation, now));
+                violation_events.send(MovementViolationEvent { client_entity, violation });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_window() {
+        let mut state = MovementState::default();
+        assert!(!state.accept_sequence(1));
+        assert!(state.accept_sequence(0));
+        assert!(state.accept_sequence(1));
+        assert!(state.accept_sequence(3));
+        assert!(!state.accept_sequence(3));
+        assert!(!state.accept_sequence(4 + SEQUENCE_WINDOW as u8));
+
+        state.next_sequence = 254;
+        assert!(state.accept_sequence(1));
+        assert_eq!(state.next_sequence, 2);
+    }
+
+    #[test]
+    fn test_reject_rolls_back_unconfirmed_moves() {
+        let start = Instant::now();
+        let mut state = MovementState::default();
+        state.next_move = start;
+        state.facing = Some(Direction::North);
+
+        for sequence in 0..3 {
+            assert!(state.accept_sequence(sequence));
+            let previous = state.next_move;
+            state.next_move += Duration::from_millis(100);
+            state.facing = Some(Direction::East);
+            state.push_pending(sequence, previous, Some(Direction::North));
+        }
+
+        state.confirm_move(0);
+        state.reset_sequence();
+        assert_eq!(state.next_move, start + Duration::from_millis(100));
+        assert_eq!(state.facing, Some(Direction::North));
+        assert!(state.is_stale(2));
+        assert!(!state.is_stale(0));
+        assert!(state.accept_sequence(0));
+        assert!(!state.is_resynchronizing());
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
 
 use bevy_ecs::change_detection::DetectChanges;
 use bevy_ecs::component::Component;
@@ -12,12 +13,13 @@ use bitflags::bitflags;
 use glam::UVec2;
 
 use yewoh::{EntityKind, Notoriety};
-use yewoh::protocol::{CharacterEquipment, DeleteEntity, EntityFlags, EntityTooltipVersion, EquipmentSlot, OpenContainer, UpdateCharacter, UpsertContainerContents, UpsertEntityCharacter, UpsertEntityContained, UpsertEntityEquipped, UpsertEntityWorld, UpsertLocalPlayer};
-use yewoh::protocol::{BeginEnterWorld, ChangeSeason, EndEnterWorld, ExtendedCommand};
+use yewoh::protocol::{CharacterEquipment, DeleteEntity, EntityFlags, EntityTooltipVersion, EquipmentSlot, OpenContainer, UpdateCharacter, UpsertContainerContents, UpsertEntityCharacter, UpsertEntityContained, UpsertEntityEquipped, UpsertEntityWorld, UpsertLocalPlayer, ViewRange};
+use yewoh::protocol::{BeginEnterWorld, ChangeSeason, EndEnterWorld, ExtendedCommand, Packet, SharedPacket};
 
-use crate::world::entity::{Character, Container, EquippedBy, Flags, Graphic, Location, Notorious, ParentContainer, Quantity, Stats, Tooltip};
+use crate::world::entity::{Character, Container, EquippedBy, Flags, Graphic, Location, Multi, Notorious, NotorietyOverrides, ParentContainer, Quantity, SeesEverything, Stats, Tooltip, VisibleTo};
 use crate::world::net::{NetClient, NetEntity, NetEntityLookup, NetOwner};
-use crate::world::net::connection::Possessing;
+use crate::world::events::ReceivedPacketEvent;
+use crate::world::net::connection::{DEFAULT_VIEW_RANGE, Possessing};
 use crate::world::spatial::{EntityPositions, view_aabb};
 
 #[derive(Debug, Clone)]
@@ -38,9 +40,48 @@ pub struct MapInfos {
     pub maps: HashMap<u8, MapInfo>,
 }
 
+#[derive(Debug, Clone, Resource)]
+pub struct ViewSettings {
+    /// The view range given to clients before they ask for one.
+    pub default_range: i32,
+    pub min_range: i32,
+    /// The largest view range clients can ask for.
+    ///
+    /// This can be lowered to shrink everyone's view range when the server is under load.
+    pub max_range: i32,
+}
+
+impl Default for ViewSettings {
+    fn default() -> Self {
+        Self {
+            default_range: DEFAULT_VIEW_RANGE,
+            min_range: 5,
+            max_range: 24,
+        }
+    }
+}
+
+impl ViewSettings {
+    pub fn clamp(&self, range: i32) -> i32 {
+        range.clamp(self.min_range, self.max_range.max(self.min_range))
+    }
+}
+
 #[derive(Debug, Clone, Component)]
 pub struct View {
+    /// The range entities are visible from, in tiles.
     pub range: i32,
+    /// The range the client asked for, which may be larger than the server allows.
+    pub requested_range: i32,
+}
+
+impl View {
+    pub fn new(settings: &ViewSettings) -> View {
+        View {
+            range: settings.clamp(settings.default_range),
+            requested_range: settings.default_range,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Component, Reflect)]
@@ -82,6 +123,7 @@ struct CharacterState {
 struct WorldItemState {
     location: Location,
     flags: EntityFlags,
+    is_multi: bool,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -338,6 +380,11 @@ pub fn send_ghost_updates(
     entity_lookup: Res<NetEntityLookup>,
     mut clients: Query<(&NetClient, &mut ViewState)>,
 ) {
+    // Character packets are identical for every observer who sees the same notoriety and
+    // equipment, so they are only encoded once.
+    let mut upserts = HashMap::<(Entity, Notoriety, Vec<Entity>), Arc<SharedPacket>>::new();
+    let mut updates = HashMap::<(Entity, Notoriety), Arc<SharedPacket>>::new();
+
     for (client, mut view_state) in clients.iter_mut() {
         if !view_state.dirty {
             continue;
@@ -368,41 +415,52 @@ pub fn send_ghost_updates(
                     let dirty_flags = std::mem::replace(&mut character.dirty_flags, CharacterDirtyFlags::empty());
 
                     if dirty_flags.contains(CharacterDirtyFlags::UPSERT) {
-                        let mut equipment = Vec::new();
-
-                        for child_entity in view_state.iter_children(entity) {
-                            let item = match view_state.ghosts.get(&child_entity) {
-                                Some(GhostState::Item(x)) => x,
-                                _ => continue,
-                            };
-                            let by = match &item.position {
-                                ItemPositionState::Equipped(by) => by,
-                                _ => continue,
-                            };
-                            let child_id = match entity_lookup.ecs_to_net(child_entity) {
-                                Some(x) => x,
-                                None => continue,
-                            };
-                            equipment.push(CharacterEquipment {
-                                id: child_id,
-                                slot: by.slot,
-                                graphic_id: item.graphic.id,
-                                hue: item.graphic.hue,
-                            });
-                        }
+                        let mut equipped = view_state.iter_children(entity)
+                            .filter(|child_entity| matches!(view_state.ghosts.get(child_entity), Some(GhostState::Item(ItemState {
+                                position: ItemPositionState::Equipped(_),
+                                ..
+                            }))))
+                            .collect::<Vec<_>>();
+                        equipped.sort();
+
+                        let packet = upserts.entry((entity, character.notoriety, equipped)).or_insert_with_key(|(_, _, equipped)| {
+                            let mut equipment = Vec::new();
+
+                            for child_entity in equipped {
+                                let item = match view_state.ghosts.get(child_entity) {
+                                    Some(GhostState::Item(x)) => x,
+                                    _ => continue,
+                                };
+                                let by = match &item.position {
+                                    ItemPositionState::Equipped(by) => by,
+                                    _ => continue,
+                                };
+                                let child_id = match entity_lookup.ecs_to_net(*child_entity) {
+                                    Some(x) => x,
+                                    None => continue,
+                                };
+                                equipment.push(CharacterEquipment {
+                                    id: child_id,
+                                    slot: by.slot,
+                                    graphic_id: item.graphic.id,
+                                    hue: item.graphic.hue,
+                                });
+                            }
 
-                        client.send_packet(UpsertEntityCharacter {
-                            id,
-                            body_type: character.body_type,
-                            position: character.location.position,
-                            direction: character.location.direction,
-                            hue: character.hue,
-                            flags: character.flags,
-                            notoriety: character.notoriety,
-                            equipment,
-                        }.into());
+                            UpsertEntityCharacter {
+                                id,
+                                body_type: character.body_type,
+                                position: character.location.position,
+                                direction: character.location.direction,
+                                hue: character.hue,
+                                flags: character.flags,
+                                notoriety: character.notoriety,
+                                equipment,
+                            }.into_arc()
+                        });
+                        client.send_packet_arc(packet.clone());
                     } else if dirty_flags.contains(CharacterDirtyFlags::UPDATE) {
-                        client.send_packet(UpdateCharacter {
+                        let packet = updates.entry((entity, character.notoriety)).or_insert_with(|| UpdateCharacter {
                             id,
                             body_type: character.body_type,
                             position: character.location.position,
@@ -410,7 +468,8 @@ pub fn send_ghost_updates(
                             hue: character.hue,
                             flags: character.flags,
                             notoriety: character.notoriety,
-                        }.into());
+                        }.into_arc());
+                        client.send_packet_arc(packet.clone());
                     }
 
                     if dirty_flags.contains(CharacterDirtyFlags::STATS) {
@@ -435,9 +494,10 @@ pub fn send_ghost_updates(
                     match &item.position {
                         ItemPositionState::World(world) => {
                             if !dirty_flags.is_empty() {
+                                let kind = if world.is_multi { EntityKind::Multi } else { EntityKind::Item };
                                 client.send_packet(UpsertEntityWorld {
                                     id,
-                                    kind: EntityKind::Item,
+                                    kind,
                                     graphic_id: item.graphic.id,
                                     graphic_inc: 0,
                                     direction: world.location.direction,
@@ -489,19 +549,54 @@ pub fn send_ghost_updates(
     }
 }
 
+/// Checks [`VisibleTo`] and [`SeesEverything`] to decide which characters can see an entity.
+#[derive(SystemParam)]
+pub struct EntityVisibility<'w, 's> {
+    visibility: Query<'w, 's, &'static VisibleTo>,
+    omniscient: Query<'w, 's, (), With<SeesEverything>>,
+}
+
+impl<'w, 's> EntityVisibility<'w, 's> {
+    /// Whether the character `observer` can see `entity`, ignoring range.
+    pub fn can_see(&self, observer: Option<Entity>, entity: Entity) -> bool {
+        let visible_to = match self.visibility.get(entity) {
+            Ok(x) => x,
+            _ => return true,
+        };
+
+        match observer {
+            Some(observer) => observer == entity
+                || visible_to.can_see(observer)
+                || self.omniscient.contains(observer),
+            None => false,
+        }
+    }
+}
+
 #[derive(SystemParam)]
 pub struct WorldObserver<'w, 's> {
-    characters: Query<'w, 's, (&'static Character, &'static Flags, &'static Location, &'static Notorious, &'static Stats)>,
-    world_items: Query<'w, 's, (&'static Graphic, &'static Flags, &'static Location, Option<&'static Tooltip>, Option<&'static Quantity>, Option<&'static Container>)>,
+    characters: Query<'w, 's, (&'static Character, &'static Flags, &'static Location, &'static Notorious, &'static Stats, Option<&'static NotorietyOverrides>)>,
+    world_items: Query<'w, 's, (&'static Graphic, &'static Flags, &'static Location, Option<&'static Tooltip>, Option<&'static Quantity>, Option<&'static Container>), Without<Multi>>,
+    multis: Query<'w, 's, (&'static Multi, &'static Location, Option<&'static Graphic>, Option<&'static Flags>)>,
     child_items: Query<'w, 's, (&'static Graphic, &'static ParentContainer, Option<&'static Tooltip>, Option<&'static Quantity>, Option<&'static Container>)>,
     equipped_items: Query<'w, 's, (&'static Graphic, Option<&'static Tooltip>, Option<&'static Quantity>, Option<&'static Container>)>,
+    visibility: EntityVisibility<'w, 's>,
 }
 
 impl<'w, 's> WorldObserver<'w, 's> {
+    /// Whether the character `observer` can see `entity`, ignoring range.
+    pub fn can_see(&self, observer: Option<Entity>, entity: Entity) -> bool {
+        self.visibility.can_see(observer, entity)
+    }
+
     fn observe_container(
         &self, viewer: Entity, view_state: &mut Mut<ViewState>, container: &Container,
     ) {
         for child in &container.items {
+            if !self.can_see(view_state.possessed, *child) {
+                continue;
+            }
+
             if let Ok((graphic, parent, tooltip, quantity, container)) = self.child_items.get(*child) {
                 view_state.upsert_ghost(*child, GhostState::Item(ItemState {
                     dirty_flags: ItemDirtyFlags::empty(),
@@ -568,6 +663,10 @@ impl<'w, 's> WorldObserver<'w, 's> {
         }));
 
         for equipped in &character.equipment {
+            if !self.can_see(view_state.possessed, equipped.entity) {
+                continue;
+            }
+
             if let Ok((graphic, tooltip, quantity, container)) = self.equipped_items.get(equipped.entity) {
                 self.observe_equipped(
                     viewer, view_state, entity, equipped.entity, equipped.slot,
@@ -588,6 +687,7 @@ impl<'w, 's> WorldObserver<'w, 's> {
             position: ItemPositionState::World(WorldItemState {
                 location: *location,
                 flags,
+                is_multi: false,
             }),
             quantity: quantity.map_or(1, |q| q.quantity),
             tooltip: Default::default(),
@@ -604,14 +704,51 @@ impl<'w, 's> WorldObserver<'w, 's> {
         }
     }
 
+    /// Multis are sent with their multi ID in place of a graphic, and only the hue is taken
+    /// from any [`Graphic`].
+    fn observe_multi(
+        &self, view_state: &mut Mut<ViewState>, entity: Entity, multi: &Multi,
+        location: &Location, graphic: Option<&Graphic>, flags: EntityFlags,
+    ) {
+        view_state.upsert_ghost(entity, GhostState::Item(ItemState {
+            dirty_flags: ItemDirtyFlags::empty(),
+            graphic: Graphic {
+                id: multi.id,
+                hue: graphic.map_or(0, |g| g.hue),
+            },
+            position: ItemPositionState::World(WorldItemState {
+                location: *location,
+                flags,
+                is_multi: true,
+            }),
+            quantity: 1,
+            tooltip: Default::default(),
+            tooltip_version: 0,
+            container_gump: None,
+        }));
+    }
+
     fn observe_entity(&self, viewer: Entity, view_state: &mut Mut<ViewState>, entity: Entity) {
-        if let Ok((character, flags, location, notorious, stats)) = self.characters.get(entity) {
-            self.observe_character(viewer, view_state, entity, character, flags.flags, location, notorious.0, stats);
+        if !self.can_see(view_state.possessed, entity) {
+            return;
+        }
+
+        if let Ok((character, flags, location, notorious, stats, overrides)) = self.characters.get(entity) {
+            let notoriety = match (overrides, view_state.possessed) {
+                (Some(overrides), Some(possessed)) => overrides.towards(possessed, notorious.0),
+                _ => notorious.0,
+            };
+            self.observe_character(viewer, view_state, entity, character, flags.flags, location, notoriety, stats);
         }
 
         if let Ok((graphic, flags, location, tooltip, quantity, container)) = self.world_items.get(entity) {
             self.observe_world_item(viewer, view_state, entity, graphic, flags.flags, location, tooltip, quantity, container);
         }
+
+        if let Ok((multi, location, graphic, flags)) = self.multis.get(entity) {
+            let flags = flags.map_or(EntityFlags::empty(), |f| f.flags);
+            self.observe_multi(view_state, entity, multi, location, graphic, flags);
+        }
     }
 }
 
@@ -636,6 +773,48 @@ pub fn observe_ghosts(
     }
 }
 
+pub fn handle_view_range_packets(
+    settings: Res<ViewSettings>,
+    mut events: EventReader<ReceivedPacketEvent>,
+    mut clients: Query<(&NetClient, &mut View)>,
+) {
+    for ReceivedPacketEvent { client_entity, packet } in events.iter() {
+        let request = match packet.downcast::<ViewRange>() {
+            Some(x) => x,
+            None => continue,
+        };
+
+        let (client, mut view) = match clients.get_mut(*client_entity) {
+            Ok(x) => x,
+            _ => continue,
+        };
+
+        let requested_range = request.0 as i32;
+        let range = settings.clamp(requested_range);
+        view.requested_range = requested_range;
+        view.range = range;
+        client.send_packet(ViewRange(range as u8).into());
+    }
+}
+
+/// Apply changes to the [`ViewSettings`] to existing clients.
+pub fn apply_view_settings(
+    settings: Res<ViewSettings>,
+    mut clients: Query<(&NetClient, &mut View)>,
+) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+
+    for (client, mut view) in &mut clients {
+        let range = settings.clamp(view.requested_range);
+        if view.range != range {
+            view.range = range;
+            client.send_packet(ViewRange(range as u8).into());
+        }
+    }
+}
+
 pub fn start_synchronizing(
     clients: Query<(Entity, &ViewState, Ref<Possessing>), (With<NetClient>, Without<Synchronizing>)>,
     characters: Query<&Location, (With<NetOwner>, With<NetEntity>, With<Location>, With<Character>)>,
@@ -772,3 +951,180 @@ pub fn send_opened_containers(
         }.into());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::prelude::*;
+    use glam::IVec3;
+
+    use super::*;
+    use crate::world::entity::CharacterEquipped;
+    use crate::world::spatial::update_entity_positions;
+
+    struct TestWorld {
+        world: World,
+        schedule: Schedule,
+    }
+
+    impl TestWorld {
+        fn new() -> TestWorld {
+            let mut world = World::new();
+            world.init_resource::<EntityPositions>();
+            let mut schedule = Schedule::new();
+            schedule.add_systems((update_entity_positions, observe_ghosts).chain());
+            TestWorld { world, schedule }
+        }
+
+        fn location(x: i32) -> Location {
+            Location { map_id: 0, position: IVec3::new(x, 0, 0), ..Default::default() }
+        }
+
+        fn spawn_character(&mut self, x: i32) -> Entity {
+            self.world.spawn((
+                Character { body_type: 0x190, ..Default::default() },
+                Flags::default(),
+                Self::location(x),
+                Notorious::default(),
+                Stats::default(),
+            )).id()
+        }
+
+        fn spawn_item(&mut self, x: i32) -> Entity {
+            self.world.spawn((Graphic { id: 0xeed, hue: 0 }, Flags::default(), Self::location(x))).id()
+        }
+
+        fn spawn_observer(&mut self, x: i32) -> (Entity, Entity) {
+            let character = self.spawn_character(x);
+            let mut view_state = ViewState::new();
+            view_state.map_id = 0;
+            view_state.possessed = Some(character);
+            let client = self.world.spawn((
+                View { range: DEFAULT_VIEW_RANGE, requested_range: DEFAULT_VIEW_RANGE },
+                view_state,
+                Possessing { entity: character },
+            )).id();
+            self.world.entity_mut(character).insert(NetOwner { client_entity: client });
+            (client, character)
+        }
+
+        fn update(&mut self) {
+            self.schedule.run(&mut self.world);
+        }
+
+        fn view_state(&self, client: Entity) -> &ViewState {
+            self.world.get::<ViewState>(client).unwrap()
+        }
+
+        fn sees(&self, client: Entity, entity: Entity) -> bool {
+            let view_state = self.view_state(client);
+            view_state.ghosts.contains_key(&entity) && !view_state.to_remove.contains(&entity)
+        }
+    }
+
+    #[test]
+    fn visible_to_limits_observers() {
+        let mut world = TestWorld::new();
+        let (first, first_character) = world.spawn_observer(0);
+        let (second, second_character) = world.spawn_observer(1);
+        let item = world.spawn_item(2);
+        world.world.entity_mut(item).insert(VisibleTo::only(first_character));
+        world.world.entity_mut(second_character).insert(VisibleTo::nobody());
+        world.update();
+
+        assert!(world.sees(first, item));
+        assert!(!world.sees(second, item));
+        // Hidden characters still see themselves.
+        assert!(world.sees(second, second_character));
+        assert!(!world.sees(first, second_character));
+        assert!(world.sees(second, first_character));
+    }
+
+    #[test]
+    fn sees_everything_ignores_visible_to() {
+        let mut world = TestWorld::new();
+        let (first, _) = world.spawn_observer(0);
+        let (second, second_character) = world.spawn_observer(1);
+        let item = world.spawn_item(2);
+        world.world.entity_mut(item).insert(VisibleTo::nobody());
+        world.world.entity_mut(second_character).insert(SeesEverything);
+        world.update();
+
+        assert!(!world.sees(first, item));
+        assert!(world.sees(second, item));
+    }
+
+    #[test]
+    fn children_need_a_visible_parent() {
+        let mut world = TestWorld::new();
+        let (first, first_character) = world.spawn_observer(0);
+        let (second, _) = world.spawn_observer(1);
+
+        let hidden_container = world.spawn_item(2);
+        let visible_container = world.spawn_item(3);
+        let mut contained = Vec::new();
+        for (container, visible_to) in [
+            (hidden_container, None),
+            (visible_container, Some(VisibleTo::only(first_character))),
+        ] {
+            let child = world.world.spawn((
+                Graphic { id: 0xeed, hue: 0 },
+                ParentContainer { parent: container, position: Default::default(), grid_index: 0 },
+            )).id();
+            if let Some(visible_to) = visible_to {
+                world.world.entity_mut(child).insert(visible_to);
+            }
+            world.world.entity_mut(container).insert(Container { gump_id: 0x3c, items: vec![child] });
+            contained.push(child);
+        }
+        world.world.entity_mut(hidden_container).insert(VisibleTo::only(first_character));
+
+        let wearer = world.spawn_character(4);
+        let equipped = world.world.spawn((Graphic { id: 0x1515, hue: 0 }, VisibleTo::only(first_character))).id();
+        world.world.get_mut::<Character>(wearer).unwrap().equipment
+            .push(CharacterEquipped::new(EquipmentSlot::Top, equipped));
+        world.update();
+
+        assert!(world.sees(first, contained[0]));
+        assert!(world.sees(first, contained[1]));
+        assert!(world.sees(first, equipped));
+        assert!(!world.sees(second, hidden_container));
+        assert!(!world.sees(second, contained[0]));
+        assert!(world.sees(second, visible_container));
+        assert!(!world.sees(second, contained[1]));
+        assert!(world.sees(second, wearer));
+        assert!(!world.sees(second, equipped));
+
+        let view_state = world.view_state(first);
+        assert!(matches!(view_state.ghosts.get(&equipped), Some(GhostState::Item(ItemState {
+            position: ItemPositionState::Equipped(EquippedBy { parent, .. }),
+            ..
+        })) if *parent == wearer));
+        assert_eq!(view_state.iter_children(visible_container).collect::<Vec<_>>(), vec![contained[1]]);
+    }
+
+    #[test]
+    fn losing_visibility_deletes_ghosts() {
+        let mut world = TestWorld::new();
+        let (client, _) = world.spawn_observer(0);
+        let item = world.spawn_item(2);
+        world.update();
+        assert!(world.sees(client, item));
+
+        world.world.entity_mut(item).insert(VisibleTo::nobody());
+        world.update();
+        assert!(!world.sees(client, item));
+
+        // These are the ghosts that send_ghost_updates sends DeleteEntity for.
+        let mut removed = Vec::new();
+        world.world.get_mut::<ViewState>(client).unwrap()
+            .for_each_removal(|entity, _| removed.push(entity));
+        assert_eq!(removed, vec![item]);
+
+        world.world.entity_mut(item).remove::<VisibleTo>();
+        world.update();
+        match world.view_state(client).ghosts.get(&item) {
+            Some(GhostState::Item(state)) => assert!(state.dirty_flags.contains(ItemDirtyFlags::UPSERT)),
+            state => panic!("expected item ghost, got {state:?}"),
+        }
+    }
+}
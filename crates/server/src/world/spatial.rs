@@ -1,14 +1,15 @@
 use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 use bevy_ecs::prelude::*;
-use glam::{IVec2, IVec3};
+use glam::{IVec2, IVec3, Vec2};
 use rstar::{AABB, Envelope, Point, PointDistance, RTree, RTreeObject, SelectionFunction};
 
-use yewoh::assets::map::{CHUNK_SIZE, MapChunk};
+use yewoh::assets::multi::MultiPrefab;
 
-use crate::world::entity::{Graphic, Location};
-use crate::world::map::{Chunk, Impassable, Surface, TileDataResource};
+use crate::world::entity::{Graphic, Location, Multi};
+use crate::world::map::{Impassable, MultiDataResource, Surface, TileDataResource};
 use crate::world::net::{NetClient, NetOwner, Possessing, View};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -197,19 +198,33 @@ impl<T> SelectionFunction<Entry<T>> for SelectEntityFunction<T> {
     }
 }
 
-fn clamp_point(pt: IVec2, aabb: &BoundingBox) -> IVec2 {
-    let min = aabb.min;
-    let max = aabb.max;
-    IVec2::new(
-        pt.x.max(min.x).min(max.x - 1),
-        pt.y.max(min.y).min(max.y - 1),
-    )
-}
-
 fn line_crosses_aabb(start: IVec2, end: IVec2, aabb: &BoundingBox) -> bool {
-    let clamped_start = clamp_point(start, aabb);
-    let clamped_end = clamp_point(end, aabb);
-    clamped_start != clamped_end || clamped_start != start
+    // Clip the line between tile centres against each axis of the box in turn.
+    let start = start.as_vec2() + Vec2::splat(0.5);
+    let delta = end.as_vec2() + Vec2::splat(0.5) - start;
+    let min = aabb.min.as_vec2();
+    let max = aabb.max.as_vec2();
+    let mut t_min = 0f32;
+    let mut t_max = 1f32;
+
+    for axis in 0..2 {
+        if delta[axis] == 0.0 {
+            if start[axis] < min[axis] || start[axis] > max[axis] {
+                return false;
+            }
+            continue;
+        }
+
+        let t0 = (min[axis] - start[axis]) / delta[axis];
+        let t1 = (max[axis] - start[axis]) / delta[axis];
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+        if t_min > t_max {
+            return false;
+        }
+    }
+
+    true
 }
 
 struct LineSelectionFunction<T> {
@@ -340,10 +355,53 @@ impl<T> SpatialEntityTree<T> {
     }
 }
 
+/// A single component of a multi.
+#[derive(Debug, Clone, Copy)]
+pub struct MultiTile {
+    pub tile_id: u16,
+    /// The height of the base of this tile, relative to the multi.
+    pub z: i32,
+}
+
+/// The components of a multi, indexed by their offset from the multi's position.
+#[derive(Debug, Clone, Default)]
+pub struct MultiSurfaces {
+    tiles: HashMap<IVec2, Vec<MultiTile>>,
+}
+
+impl MultiSurfaces {
+    pub fn from_prefab(prefab: &MultiPrefab) -> MultiSurfaces {
+        let mut tiles = HashMap::<IVec2, Vec<MultiTile>>::new();
+        for component in &prefab.components {
+            tiles.entry(component.position.truncate()).or_default().push(MultiTile {
+                tile_id: component.graphic,
+                z: component.position.z,
+            });
+        }
+        MultiSurfaces { tiles }
+    }
+
+    pub fn tiles_at(&self, offset: IVec2) -> &[MultiTile] {
+        self.tiles.get(&offset).map_or(&[], |t| t.as_slice())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum SurfaceKind {
-    Chunk { position: IVec2, chunk: MapChunk },
     Item { position: IVec2, tile_id: u16, impassable: bool, min_z: i32, max_z: i32 },
+    Multi { position: IVec3, multi_id: u16, tiles: Arc<MultiSurfaces> },
+}
+
+impl SurfaceKind {
+    /// The tiles of a multi at a world position, with their absolute base heights.
+    pub fn multi_tiles_at(&self, position: IVec2) -> impl Iterator<Item = MultiTile> + '_ {
+        let (origin, tiles) = match self {
+            SurfaceKind::Multi { position: origin, tiles, .. } =>
+                (*origin, tiles.tiles_at(position - origin.truncate())),
+            _ => (IVec3::ZERO, &[][..]),
+        };
+        tiles.iter().map(move |t| MultiTile { tile_id: t.tile_id, z: t.z + origin.z })
+    }
 }
 
 #[derive(Debug, Clone, Default, Resource)]
@@ -354,24 +412,12 @@ pub struct EntitySurfaces {
 pub fn update_entity_surfaces(
     mut storage: ResMut<EntitySurfaces>,
     tile_data: Res<TileDataResource>,
-    chunks: Query<(Entity, &Location, &Chunk), Or<(Changed<Location>, Changed<Chunk>)>>,
     surfaces: Query<
         (Entity, &Location, &Graphic, Option<&Impassable>),
         (Or<(With<Impassable>, With<Surface>)>, Or<(Changed<Location>, Changed<Surface>, Changed<Impassable>)>),
     >,
-    mut removed_chunks: RemovedComponents<Chunk>,
     mut removed_surfaces: RemovedComponents<Surface>,
 ) {
-    for (entity, position, chunk) in chunks.iter() {
-        let min = position.position.truncate();
-        let max = min + IVec2::new(CHUNK_SIZE as i32, CHUNK_SIZE as i32);
-        let kind = SurfaceKind::Chunk {
-            position: min,
-            chunk: chunk.map_chunk.clone(),
-        };
-        storage.tree.insert_aabb(entity, kind, position.map_id, min, max);
-    }
-
     for (entity, position, graphic, impassable) in surfaces.iter() {
         let tile_data = match tile_data.items.get(graphic.id as usize) {
             Some(x) => x,
@@ -388,11 +434,52 @@ pub fn update_entity_surfaces(
         storage.tree.insert_point(entity, kind, position.map_id, position.position.truncate());
     }
 
-    for entity in removed_chunks.iter() {
+    for entity in removed_surfaces.iter() {
+        storage.tree.remove(entity);
+    }
+}
+
+pub fn update_multi_surfaces(
+    mut storage: ResMut<EntitySurfaces>,
+    multi_data: Option<Res<MultiDataResource>>,
+    multis: Query<(Entity, &Location, &Multi), Or<(Changed<Location>, Changed<Multi>)>>,
+    mut removed_multis: RemovedComponents<Multi>,
+    mut cache: Local<HashMap<u16, Arc<MultiSurfaces>>>,
+) {
+    let multi_data = match multi_data {
+        Some(x) => x,
+        None => return,
+    };
+
+    for (entity, location, multi) in &multis {
+        // The footprint alone doesn't capture changes in height or multi ID.
         storage.tree.remove(entity);
+
+        let prefab = match multi_data.prefabs.get(multi.id as usize) {
+            Some(x) => x,
+            None => continue,
+        };
+        let (min, max) = match prefab.bounds() {
+            Some(x) => x,
+            None => continue,
+        };
+
+        let tiles = cache.entry(multi.id)
+            .or_insert_with(|| Arc::new(MultiSurfaces::from_prefab(prefab)))
+            .clone();
+        let kind = SurfaceKind::Multi {
+            position: location.position,
+            multi_id: multi.id,
+            tiles,
+        };
+
+        let origin = location.position.truncate();
+        storage.tree.insert_aabb(
+            entity, kind, location.map_id,
+            origin + min.truncate(), origin + max.truncate() + IVec2::ONE);
     }
 
-    for entity in removed_surfaces.iter() {
+    for entity in removed_multis.iter() {
         storage.tree.remove(entity);
     }
 }
@@ -420,15 +507,27 @@ pub struct EntityPositions {
 
 pub fn update_entity_positions(
     mut storage: ResMut<EntityPositions>,
-    entities: Query<(Entity, &Location, Option<&Extents>), Or<(Changed<Location>, Changed<Extents>)>>,
+    multi_data: Option<Res<MultiDataResource>>,
+    entities: Query<
+        (Entity, &Location, Option<&Extents>, Option<&Multi>),
+        Or<(Changed<Location>, Changed<Extents>, Changed<Multi>)>,
+    >,
     mut removed_entities: RemovedComponents<Location>,
 ) {
-    for (entity, position, extents) in &entities {
-        let extents = extents.cloned().unwrap_or(Extents::default());
+    for (entity, position, extents, multi) in &entities {
         let map_id = position.map_id;
         let position = position.position.truncate();
-        let min = position - extents.min.truncate();
-        let max = position + extents.max.truncate();
+
+        // Multis take up their whole footprint, so that large multis are seen as soon as
+        // any part of them is in range.
+        let multi_bounds = multi.and_then(|multi| multi_data.as_ref()?.prefabs.get(multi.id as usize)?.bounds());
+        let (min, max) = match multi_bounds {
+            Some((min, max)) => (position + min.truncate(), position + max.truncate() + IVec2::ONE),
+            None => {
+                let extents = extents.cloned().unwrap_or(Extents::default());
+                (position - extents.min.truncate(), position + extents.max.truncate())
+            }
+        };
         storage.tree.insert_aabb(entity, (), map_id, min, max);
     }
 
@@ -490,3 +589,78 @@ pub fn update_client_positions(
         storage.tree.remove(entity);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use yewoh::assets::multi::{MultiData, MultiPrefabComponent};
+    use yewoh::assets::tiles::TileFlags;
+    use yewoh::Direction;
+
+    use super::*;
+
+    #[test]
+    fn lines_select_crossed_entries() {
+        let mut tree = SpatialEntityTree::<()>::default();
+        let on_line = Entity::from_raw(1);
+        let beside_line = Entity::from_raw(2);
+        let corner = Entity::from_raw(3);
+        let large = Entity::from_raw(4);
+        tree.insert_point(on_line, (), 0, IVec2::new(3, 3));
+        tree.insert_point(beside_line, (), 0, IVec2::new(3, 5));
+        tree.insert_point(corner, (), 0, IVec2::new(6, 1));
+        tree.insert_aabb(large, (), 0, IVec2::new(10, 0), IVec2::new(20, 10));
+
+        let crossed = |start: IVec2, end: IVec2| {
+            let mut entities = tree.iter_line(0, start, end).map(|(e, _)| e).collect::<Vec<_>>();
+            entities.sort();
+            entities
+        };
+
+        assert_eq!(crossed(IVec2::new(0, 3), IVec2::new(8, 3)), vec![on_line]);
+        assert_eq!(crossed(IVec2::new(0, 0), IVec2::new(8, 8)), vec![on_line]);
+        // Diagonal lines select tiles in either direction.
+        assert_eq!(crossed(IVec2::new(5, 0), IVec2::new(7, 2)), vec![corner]);
+        assert_eq!(crossed(IVec2::new(5, 2), IVec2::new(7, 0)), vec![corner]);
+        assert_eq!(crossed(IVec2::new(8, 0), IVec2::new(15, 5)), vec![large]);
+        assert_eq!(crossed(IVec2::new(0, 8), IVec2::new(8, 8)), vec![]);
+        assert!(tree.iter_line(1, IVec2::new(0, 3), IVec2::new(8, 3)).next().is_none());
+    }
+
+    #[test]
+    fn multis_without_a_footprint_are_removed() {
+        let component = MultiPrefabComponent {
+            graphic: 1,
+            position: IVec3::ZERO,
+            tile_flags: TileFlags::empty(),
+            component_flags: 0,
+            tooltip_ids: Vec::new(),
+        };
+        let mut world = World::new();
+        world.init_resource::<EntitySurfaces>();
+        world.insert_resource(MultiDataResource {
+            multi_data: MultiData {
+                prefabs: vec![MultiPrefab { components: vec![component] }, MultiPrefab::default()],
+            },
+        });
+
+        let location = Location { map_id: 0, position: IVec3::new(4, 3, 0), direction: Direction::North };
+        let entity = world.spawn((location, Multi { id: 0 })).id();
+        let mut schedule = Schedule::new();
+        schedule.add_system(update_multi_surfaces);
+        let surfaces_at = |world: &World| world.resource::<EntitySurfaces>().tree
+            .iter_at_point(0, IVec2::new(4, 3)).count();
+
+        schedule.run(&mut world);
+        assert_eq!(surfaces_at(&world), 1);
+
+        for id in [1, 2] {
+            world.get_mut::<Multi>(entity).unwrap().id = id;
+            schedule.run(&mut world);
+            assert_eq!(surfaces_at(&world), 0);
+
+            world.get_mut::<Multi>(entity).unwrap().id = 0;
+            schedule.run(&mut world);
+            assert_eq!(surfaces_at(&world), 1);
+        }
+    }
+}